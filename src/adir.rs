@@ -1,7 +1,7 @@
 use clap::{App, Arg, SubCommand};
 
 use i3utils::gtk::widgets::config::{
-    BaseConfig, CalendarConfig, Config, ScaleConfig, WidgetConfig,
+    BaseConfig, CalendarConfig, Config, EntryConfig, ScaleConfig, WidgetConfig,
 };
 use i3utils::gtk::Application;
 
@@ -11,6 +11,25 @@ fn create_parser() -> App<'static, 'static> {
         .author("mandragore")
         .about("Creates dialog windows")
         .subcommand(SubCommand::with_name("calendar").about("Opens a calendar dialog"))
+        .subcommand(
+            SubCommand::with_name("entry")
+                .about("Opens a text entry dialog, printing the text once entered")
+                .arg(
+                    Arg::with_name("prompt")
+                        .long("--prompt")
+                        .require_equals(true)
+                        .value_name("PROMPT")
+                        .help("The text shown in the empty entry")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("masked")
+                        .long("--masked")
+                        .help("Hide the entered text, as for passwords")
+                        .takes_value(false)
+                        .multiple(false),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("scale")
                 .about("Opens a scale dialog")
@@ -145,6 +164,11 @@ fn main() -> Result<(), String> {
             let widget_config = WidgetConfig::Calendar(calendar_config);
             Config::new(base_config, widget_config)
         }
+        "entry" => {
+            let entry_config = EntryConfig::new(submatches);
+            let widget_config = WidgetConfig::Entry(entry_config);
+            Config::new(base_config, widget_config)
+        }
         _ => return Err(String::from("Unknown dialog type")),
     };
 
@@ -0,0 +1,12 @@
+use std::env;
+use std::process;
+
+use i3utils::rmenu::elevate;
+
+fn main() {
+    let prompt = env::args().nth(1);
+    if let Err(e) = elevate::askpass(prompt.as_deref()) {
+        eprintln!("rmenu-askpass: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,13 @@
+use std::process;
+
+use i3utils::rmenu::{self, Builder, Error};
+
+fn main() {
+    match rmenu::run_menu(Builder::new()) {
+        Ok(()) | Err(Error::Cancelled) => (),
+        Err(e) => {
+            eprintln!("rmenu: {}", e);
+            process::exit(e.exit_code());
+        }
+    }
+}
@@ -1,7 +1,7 @@
 pub mod widgets;
 
 use gtk::{
-    BoxExt, ContainerExt, GtkWindowExt, HeaderBarExt, ObjectExt, RangeExt, ScaleExt,
+    BoxExt, ContainerExt, EntryExt, GtkWindowExt, HeaderBarExt, ObjectExt, RangeExt, ScaleExt,
     StyleContextExt, WidgetExt,
 };
 
@@ -143,6 +143,17 @@ impl Content {
                 let calendar = gtk::Calendar::new();
                 container.pack_start(&calendar, true, true, 0);
             }
+            WidgetConfig::Entry(entry_config) => {
+                let entry = gtk::Entry::new();
+                entry.set_visibility(!entry_config.masked());
+                entry.set_placeholder_text(entry_config.prompt().as_str());
+                container.pack_start(&entry, true, true, 0);
+                // Print the text once Enter is pressed, and close the dialog.
+                entry.connect_activate(|x| {
+                    println!("{}", x.get_text().unwrap_or_default());
+                    gtk::main_quit();
+                });
+            }
         }
 
         Content { container }
@@ -26,9 +26,16 @@ pub struct CalendarConfig {
     _dummy: i32,
 }
 
+/// A struct that holds the configuration options used by the Entry dialog.
+pub struct EntryConfig {
+    prompt: String,
+    masked: bool,
+}
+
 pub enum WidgetConfig {
     Scale(ScaleConfig),
     Calendar(CalendarConfig),
+    Entry(EntryConfig),
 }
 
 /// A struct that holds the base and widget-specific configurations.
@@ -122,6 +129,22 @@ impl CalendarConfig {
     }
 }
 
+impl EntryConfig {
+    pub fn new(matches: &ArgMatches) -> EntryConfig {
+        let prompt = matches.value_of("prompt").unwrap_or("").to_string();
+        let masked = matches.occurrences_of("masked") == 1;
+        EntryConfig { prompt, masked }
+    }
+
+    pub fn prompt(&self) -> &String {
+        &self.prompt
+    }
+
+    pub fn masked(&self) -> bool {
+        self.masked
+    }
+}
+
 impl ScaleConfig {
     pub fn new(matches: &ArgMatches) -> Result<ScaleConfig, String> {
         let max_val = matches
@@ -1 +1,2 @@
 pub mod gtk;
+pub mod rmenu;
@@ -1,253 +1,1429 @@
 use std::io::prelude::*;
 
-use std::fs::File;
-
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use std::vec::Vec;
 
 use linked_hash_map::LinkedHashMap;
+use unicode_width::UnicodeWidthStr;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+pub mod appearance;
+pub mod audit;
+pub mod builder;
+pub mod cache;
+pub mod clipboard;
+pub mod columns;
+pub mod daemon;
+pub mod desktop;
+pub mod edit;
+pub mod elevate;
+pub mod error;
+pub mod i3;
+pub mod import;
+pub mod locale;
+pub mod mime;
+pub mod open;
+pub mod plugin;
+pub mod policy;
+pub mod process;
+pub mod script;
+pub mod secret;
+pub mod sequence;
 
-use clap::{App, Arg};
+use self::appearance::Appearance;
+use self::audit::Outcome;
+pub use self::builder::{get_config, Builder, FnBuild};
+use self::columns::Columns;
+use self::elevate::Elevate;
+pub use self::error::Error;
+use self::i3::keybindings::ConfigParser;
+use self::locale::{tr, trf};
+use self::policy::Policy;
+use self::secret::Secret;
 
 const ROFI: &str = "/usr/bin/rofi";
 
+/// The most accelerators a menu can have, as rofi has that many custom key
+/// bindings.
+pub const MAX_ACCELS: usize = 19;
+
+/// The default key bindings of rofi, other than the custom ones, which
+/// accelerators can not take.
+const ROFI_BINDINGS: &[(&str, &str)] = &[
+    ("kb-primary-paste", "Control+V,Shift+Insert"),
+    ("kb-secondary-paste", "Control+v,Insert"),
+    ("kb-clear-line", "Control+w"),
+    ("kb-move-front", "Control+a"),
+    ("kb-move-end", "Control+e"),
+    ("kb-move-word-back", "Alt+b,Control+Left"),
+    ("kb-move-word-forward", "Alt+f,Control+Right"),
+    ("kb-move-char-back", "Left,Control+b"),
+    ("kb-move-char-forward", "Right,Control+f"),
+    ("kb-remove-word-back", "Control+Alt+h,Control+BackSpace"),
+    ("kb-remove-word-forward", "Control+Alt+d"),
+    ("kb-remove-char-forward", "Delete,Control+d"),
+    ("kb-remove-char-back", "BackSpace,Shift+BackSpace,Control+h"),
+    ("kb-remove-to-eol", "Control+k"),
+    ("kb-remove-to-sol", "Control+u"),
+    ("kb-accept-entry", "Control+j,Control+m,Return,KP_Enter"),
+    ("kb-accept-custom", "Control+Return"),
+    ("kb-accept-custom-alt", "Control+Shift+Return"),
+    ("kb-accept-alt", "Shift+Return"),
+    ("kb-delete-entry", "Shift+Delete"),
+    ("kb-mode-next", "Shift+Right,Control+Tab"),
+    ("kb-mode-previous", "Shift+Left,Control+ISO_Left_Tab"),
+    ("kb-mode-complete", "Control+l"),
+    ("kb-row-left", "Control+Page_Up"),
+    ("kb-row-right", "Control+Page_Down"),
+    ("kb-row-up", "Up,Control+p"),
+    ("kb-row-down", "Down,Control+n"),
+    ("kb-row-tab", "Tab"),
+    ("kb-element-prev", "ISO_Left_Tab"),
+    ("kb-page-prev", "Page_Up"),
+    ("kb-page-next", "Page_Down"),
+    ("kb-row-first", "Home,KP_Home"),
+    ("kb-row-last", "End,KP_End"),
+    ("kb-row-select", "Control+space"),
+    ("kb-screenshot", "Alt+S"),
+    ("kb-ellipsize", "Alt+period"),
+    ("kb-toggle-case-sensitivity", "grave,dead_grave"),
+    ("kb-toggle-sort", "Alt+grave"),
+    ("kb-cancel", "Escape,Control+g,Control+bracketleft"),
+    ("kb-select-1", "Super+1"),
+    ("kb-select-2", "Super+2"),
+    ("kb-select-3", "Super+3"),
+    ("kb-select-4", "Super+4"),
+    ("kb-select-5", "Super+5"),
+    ("kb-select-6", "Super+6"),
+    ("kb-select-7", "Super+7"),
+    ("kb-select-8", "Super+8"),
+    ("kb-select-9", "Super+9"),
+    ("kb-select-10", "Super+0"),
+];
+
+/// Returns the name of the default rofi key binding that `key` would clash
+/// with, if any.
+///
+/// Keys are compared without regard to case or to the order of their
+/// modifiers.
+pub fn rofi_binding(key: &str) -> Option<&'static str> {
+    let key = normalize_key(key);
+    ROFI_BINDINGS
+        .iter()
+        .find(|(_, keys)| keys.split(',').any(|x| normalize_key(x) == key))
+        .map(|(name, _)| *name)
+}
+
+/// Returns `key` in lower case, with its modifiers sorted.
+fn normalize_key(key: &str) -> String {
+    let key = key.trim().to_lowercase();
+    let mut parts: Vec<&str> = key.split('+').collect();
+    let last = parts.pop().unwrap_or_default();
+    parts.sort_unstable();
+    parts.push(last);
+    parts.join("+")
+}
+
+/// The exit code of rofi when the first custom key binding is pressed, which
+/// is followed by those of the others.
+const CUSTOM_KEY_EXIT_CODE: i32 = 10;
+
+/// The exit codes of rmenu, as listed by `--help`. They are those of
+/// `Error::exit_code`.
+const EXIT_STATUS_HELP: &str = "EXIT STATUS:
+    0      Success, or the menu was dismissed
+    1      Other failure
+    2      The config describes an invalid menu
+    3      The config file could not be read
+    4      The config file is not valid YAML
+    5      A program could not be started
+    6      A command failed
+    124    A command timed out";
+
+/// The answers offered when a command must be confirmed. No comes first, so
+/// that it is the one selected by default.
+const CONFIRM_NO: &str = "No";
+const CONFIRM_YES: &str = "Yes";
+
+/// The separator between the option strings of a menu path.
+pub const PATH_SEPARATOR: char = '/';
+
+/// The character escaping separators, and itself, in the option strings of a
+/// menu path.
+pub const PATH_ESCAPE: char = '\\';
+
+/// The label of separator rows that do not have one.
+pub const SEPARATOR_LINE: &str = "────────────";
+
+/// The separator between the option strings of a breadcrumb label.
+pub const BREADCRUMB_SEPARATOR: &str = " › ";
+
 /// A trait for creating actions taken when a menu item is selected.
 ///
 /// A type implementing `RofiAction` is one that, when selected through rofi,
 /// has a meaningful action to perform.
-trait RofiAction {
+pub trait RofiAction {
     /// Executes the action and the action to be performed next.
-    fn run(&self) -> Result<Option<&Box<RofiAction>>, String>;
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error>;
+
+    /// Returns the action reached by selecting `key`, if this action has one.
+    fn get(&self, _key: &str) -> Option<&dyn RofiAction> {
+        None
+    }
+
+    /// Returns the action as a `RofiMenu`, if it is one.
+    fn as_menu(&self) -> Option<&RofiMenu> {
+        None
+    }
+
+    /// Returns the action as a `RofiCommand`, if it is one.
+    fn as_command(&self) -> Option<&RofiCommand> {
+        None
+    }
+
+    /// Returns the menu the action shows when it runs, if it is a generator
+    /// such as `I3Windows`.
+    fn generate(&self) -> Option<Result<RofiMenu, Error>> {
+        None
+    }
+
+    /// Whether running the action asks the user for something in a window
+    /// of its own, such as a confirmation or a secret.
+    fn needs_input(&self) -> bool {
+        false
+    }
 }
 
 /// A struct for executing shell commands through a rofi menu.
-struct RofiCommand {
+pub struct RofiCommand {
     /// The name of the command to be executed.
     command: String,
     /// The arguments of the command to be executed.
     args: Vec<String>,
+    /// Whether rmenu goes on without waiting for the command to exit.
+    detach: bool,
+    /// How long the command may run before it is stopped.
+    timeout: Option<Duration>,
+    /// The question asked before running the command, if it must be
+    /// confirmed.
+    confirm: Option<String>,
+    /// The secret asked for before running the command and passed to it.
+    secret: Option<Secret>,
+    /// How the command is run as root, if it is.
+    elevate: Option<Elevate>,
 }
 
 impl RofiCommand {
     pub fn new(command: String, args: Vec<String>) -> RofiCommand {
-        RofiCommand { command, args }
+        RofiCommand {
+            command,
+            args,
+            detach: false,
+            timeout: None,
+            confirm: None,
+            secret: None,
+            elevate: None,
+        }
+    }
+
+    /// Creates a command that is left running in the background, such as an
+    /// application being launched.
+    pub fn detached(command: String, args: Vec<String>) -> RofiCommand {
+        RofiCommand {
+            command,
+            args,
+            detach: true,
+            timeout: None,
+            confirm: None,
+            secret: None,
+            elevate: None,
+        }
+    }
+
+    /// The name of the command to be executed.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// The arguments of the command to be executed.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Sets how long the command may run before it is stopped.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// How long the command may run before it is stopped.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Asks `question` before running the command, which only runs if the
+    /// answer is yes.
+    pub fn set_confirm(&mut self, question: Option<String>) {
+        self.confirm = question;
+    }
+
+    /// The question asked before running the command, if it must be
+    /// confirmed.
+    pub fn confirm(&self) -> Option<&str> {
+        self.confirm.as_deref()
+    }
+
+    /// Asks for `secret` before running the command, which is given it on its
+    /// stdin or in its environment.
+    pub fn set_secret(&mut self, secret: Option<Secret>) {
+        self.secret = secret;
+    }
+
+    /// The secret asked for before running the command, if any.
+    pub fn secret(&self) -> Option<&Secret> {
+        self.secret.as_ref()
+    }
+
+    /// Whether the command is started without waiting for it.
+    pub fn detach(&self) -> bool {
+        self.detach
+    }
+
+    /// Runs the command as root with `elevate`.
+    pub fn set_elevate(&mut self, elevate: Option<Elevate>) {
+        self.elevate = elevate;
+    }
+
+    /// How the command is run as root, if it is.
+    pub fn elevate(&self) -> Option<Elevate> {
+        self.elevate
+    }
+
+    /// The program run and its arguments, which are those of the command
+    /// unless it is elevated.
+    pub fn invocation(&self) -> (String, Vec<String>) {
+        match &self.elevate {
+            Some(elevate) => elevate.wrap(&self.command, &self.args),
+            None => (self.command.clone(), self.args.clone()),
+        }
+    }
+
+    /// Creates the process running the command.
+    pub fn process(&self) -> Result<Command, Error> {
+        let (program, args) = self.invocation();
+        let mut process = Command::new(program);
+        process.args(args);
+        if let Some(elevate) = &self.elevate {
+            elevate.prepare(&mut process)?;
+        }
+        Ok(process)
     }
 }
 
 impl RofiAction for RofiCommand {
-    fn run(&self) -> Result<Option<&Box<RofiAction>>, String> {
-        Command::new(&self.command)
-            .args(&self.args)
-            .output()
-            .or(Err("Failed to run command"))?;
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        if let Some(question) = &self.confirm {
+            if !ask_confirmation(question)? {
+                return Err(Error::Cancelled);
+            }
+        }
+        let value = match &self.secret {
+            Some(secret) => match secret.read()? {
+                Some(value) => Some((secret, value)),
+                None => return Err(Error::Cancelled),
+            },
+            None => None,
+        };
+        let (program, args) = self.invocation();
+        if self.detach {
+            let mut command = self.process()?;
+            command
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            if let Some((secret, value)) = &value {
+                secret.pass(&mut command, value);
+            }
+            let mut child = command.spawn().map_err(|e| Error::spawn(&program, e))?;
+            if let Some((secret, value)) = value {
+                secret.feed(&mut child, &value)?;
+            }
+            audit::record(&program, &args, Outcome::Detached, Duration::default(), &[]);
+            // Reap the child once it exits, in case rmenu is still running.
+            thread::spawn(move || child.wait());
+            return Ok(None);
+        }
+        let start = Instant::now();
+        // Stderr is only kept for the audit log, as the end of it is recorded.
+        let audit = audit::is_enabled();
+        let mut command = self.process()?;
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(if audit { Stdio::piped() } else { Stdio::null() });
+        // Commands with a timeout get a process group of their own, so that
+        // everything they started can be stopped along with them.
+        if self.timeout.is_some() {
+            command.process_group(0);
+        }
+        if let Some((secret, value)) = &value {
+            secret.pass(&mut command, value);
+        }
+        let mut child = command.spawn().map_err(|e| Error::spawn(&program, e))?;
+        drop(command);
+        // The value is zeroed as soon as the command has it.
+        if let Some((secret, value)) = value {
+            secret.feed(&mut child, &value)?;
+        }
+        let stderr = child.stderr.take().map(process::PipeReader::spawn);
+
+        let deadline = self.timeout.map(|x| start + x);
+        let status = process::wait_until(&mut child, deadline)
+            .map_err(|e| Error::io(tr("Failed to wait for command"), e))?;
+        let status = match status {
+            Some(status) => status,
+            None => {
+                process::kill_groups(&mut [child]);
+                audit::record(&program, &args, Outcome::TimedOut, start.elapsed(), &[]);
+                return Err(Error::TimedOut {
+                    command: self.command.clone(),
+                    timeout: self.timeout.unwrap_or_default(),
+                });
+            }
+        };
+        // Whatever the command left running in the background may still hold
+        // stderr, so it is not waited for past the deadline.
+        let stderr = match stderr {
+            Some(stderr) => {
+                stderr.wait_closed(process::pipe_deadline(deadline));
+                stderr.take()
+            }
+            None => Vec::new(),
+        };
+        audit::record(
+            &program,
+            &args,
+            Outcome::Exited(status),
+            start.elapsed(),
+            &stderr,
+        );
+        if !status.success() {
+            return Err(Error::CommandFailed {
+                command: self.command.clone(),
+                status,
+            });
+        }
         Ok(None)
     }
+
+    fn as_command(&self) -> Option<&RofiCommand> {
+        Some(self)
+    }
+
+    fn needs_input(&self) -> bool {
+        // Only doas never asks for a password.
+        self.confirm.is_some()
+            || self.secret.is_some()
+            || self.elevate.is_some_and(|x| !matches!(x, Elevate::Doas))
+    }
 }
 
 /// A struct for displaying rofi menus
-struct RofiMenu {
+pub struct RofiMenu {
     /// The name of the `RofiMenu`.
     name: String,
     /// The prompt displayed in he menu.
     prompt: String,
     /// The options available in the `RofiMenu`.
-    options: LinkedHashMap<String, Box<RofiAction>>,
+    options: LinkedHashMap<String, Box<dyn RofiAction>>,
+    /// Whether the leaves of all submenus are listed in a single menu.
+    flat: bool,
+    /// The names or paths of the icons shown next to options.
+    icons: HashMap<String, String>,
+    /// The look and behaviour of the rofi window.
+    appearance: Appearance,
+    /// The non-selectable rows shown between options, with the index of the
+    /// option they come before.
+    separators: Vec<(usize, String)>,
+    /// The columns the rows are laid out in, if the options have more than
+    /// their strings to show.
+    columns: Option<Columns>,
+    /// The cells shown after the strings of options, in the columns after
+    /// the first.
+    column_values: HashMap<String, Vec<String>>,
+    /// The keys selecting options right away, shown before their strings.
+    /// Flat menus list the options of submenus too and ignore them.
+    accels: HashMap<String, String>,
+}
+
+/// A leaf action of a menu tree, as found by `RofiMenu::leaves`.
+pub struct Leaf<'a> {
+    /// The option strings leading to the action.
+    path: Vec<&'a str>,
+    /// The names of the submenus leading to the action.
+    menus: Vec<&'a str>,
+    /// The action itself.
+    action: &'a dyn RofiAction,
+    /// The cells shown after the label of the leaf.
+    column_values: &'a [String],
+}
+
+impl<'a> Leaf<'a> {
+    /// The option strings leading to the action.
+    pub fn path(&self) -> &[&'a str] {
+        &self.path
+    }
+
+    /// The names of the submenus leading to the action.
+    pub fn menus(&self) -> &[&'a str] {
+        &self.menus
+    }
+
+    /// The action itself.
+    pub fn action(&self) -> &'a dyn RofiAction {
+        self.action
+    }
+
+    /// The label of the leaf, made of the option strings leading to it.
+    pub fn breadcrumb(&self) -> String {
+        self.path.join(BREADCRUMB_SEPARATOR)
+    }
 }
 
 impl RofiMenu {
     pub fn new(
         name: String,
         prompt: String,
-        options: LinkedHashMap<String, Box<RofiAction>>,
+        options: LinkedHashMap<String, Box<dyn RofiAction>>,
+        flat: bool,
     ) -> RofiMenu {
         RofiMenu {
             name,
             options,
             prompt,
+            flat,
+            icons: HashMap::new(),
+            appearance: Appearance::default(),
+            separators: Vec::new(),
+            columns: None,
+            column_values: HashMap::new(),
+            accels: HashMap::new(),
         }
     }
 
-    fn optionstring(&self) -> String {
-        let key_vec: Vec<&String> = self.options.keys().collect();
-        let res = key_vec.iter().fold(String::new(), |mut acc, x| {
-            acc.push_str(&format!("{}\n", x));
-            acc
-        });
-        res
+    /// Shows a non-selectable row with `label` before the option at
+    /// `position`, or after the last option if there are not that many.
+    pub fn add_separator(&mut self, position: usize, label: String) {
+        self.separators.push((position, label));
+    }
+
+    /// The non-selectable rows shown before the option at `position`.
+    pub fn separators_before(&self, position: usize) -> impl Iterator<Item = &str> {
+        self.separators
+            .iter()
+            .filter(move |x| x.0 == position || (position == self.options.len() && x.0 > position))
+            .map(|x| {
+                if x.1.is_empty() {
+                    SEPARATOR_LINE
+                } else {
+                    x.1.as_str()
+                }
+            })
+    }
+
+    /// Sets the look and behaviour of the rofi window.
+    pub fn set_appearance(&mut self, appearance: Appearance) {
+        self.appearance = appearance;
+    }
+
+    /// The look and behaviour of the rofi window.
+    pub fn appearance(&self) -> &Appearance {
+        &self.appearance
+    }
+
+    /// Shows `icon` next to `option`.
+    pub fn set_icon(&mut self, option: &str, icon: String) {
+        self.icons.insert(String::from(option), icon);
     }
+
+    /// The icon shown next to `option`, if any.
+    pub fn icon(&self, option: &str) -> Option<&str> {
+        self.icons.get(option).map(String::as_str)
+    }
+
+    /// Lays the rows of the menu out in `columns`.
+    pub fn set_columns(&mut self, columns: Option<Columns>) {
+        self.columns = columns;
+    }
+
+    /// Shows `values` after `option`, in the columns after the first.
+    pub fn set_column_values(&mut self, option: &str, values: Vec<String>) {
+        self.column_values.insert(String::from(option), values);
+    }
+
+    /// Selects `option` when `key` is pressed, as rofi names keys, such as
+    /// `s` or `Alt+s`.
+    pub fn set_accel(&mut self, option: &str, key: String) {
+        self.accels.insert(String::from(option), key);
+    }
+
+    /// The key selecting `option` right away, if any.
+    pub fn accel(&self, option: &str) -> Option<&str> {
+        self.accels.get(option).map(String::as_str)
+    }
+
+    /// The options with an accelerator, in display order.
+    fn accelerated(&self) -> Vec<&str> {
+        self.options
+            .keys()
+            .filter(|x| self.accels.contains_key(*x))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// The rows showing the options, in display order.
+    pub fn labels(&self) -> Vec<String> {
+        self.option_rows(false)
+    }
+
+    /// The rows showing the options, with their accelerators if `accels` is
+    /// set.
+    fn option_rows(&self, accels: bool) -> Vec<String> {
+        let prefixes: Vec<String> = self
+            .options
+            .keys()
+            .map(|x| match self.accels.get(x) {
+                Some(key) if accels => format!("[{}] ", key),
+                _ => String::new(),
+            })
+            .collect();
+        // Options without an accelerator are indented like the others.
+        let width = prefixes.iter().map(|x| x.width()).max().unwrap_or(0);
+        let labels: Vec<String> = self
+            .options
+            .keys()
+            .zip(&prefixes)
+            .map(|(x, prefix)| format!("{}{}{}", prefix, " ".repeat(width - prefix.width()), x))
+            .collect();
+        let rows = self
+            .options
+            .keys()
+            .zip(&labels)
+            .map(|(x, label)| {
+                self.cells(label, self.column_values.get(x).map_or(&[], Vec::as_slice))
+            })
+            .collect();
+        self.layout(rows)
+    }
+
+    /// The rows showing `leaves` in a flat menu, in the same order.
+    pub fn leaf_labels(&self, leaves: &[Leaf]) -> Vec<String> {
+        let breadcrumbs: Vec<String> = leaves.iter().map(|x| x.breadcrumb()).collect();
+        let rows = leaves
+            .iter()
+            .zip(&breadcrumbs)
+            .map(|(leaf, breadcrumb)| self.cells(breadcrumb, leaf.column_values))
+            .collect();
+        self.layout(rows)
+    }
+
+    fn cells<'a>(&self, label: &'a str, values: &'a [String]) -> Vec<&'a str> {
+        let mut cells = vec![label];
+        cells.extend(values.iter().map(String::as_str));
+        cells
+    }
+
+    fn layout(&self, rows: Vec<Vec<&str>>) -> Vec<String> {
+        match &self.columns {
+            Some(columns) => columns.format(&rows),
+            None => rows.iter().map(|x| String::from(x[0])).collect(),
+        }
+    }
+
+    /// Whether the menu lists the leaves of all its submenus.
+    pub fn is_flat(&self) -> bool {
+        self.flat
+    }
+
+    /// Collects every action reachable from the menu that is not a menu
+    /// itself, in display order.
+    pub fn leaves(&self) -> Vec<Leaf<'_>> {
+        let mut leaves = Vec::new();
+        self.collect_leaves(&mut Vec::new(), &mut Vec::new(), &mut leaves);
+        leaves
+    }
+
+    fn collect_leaves<'a>(
+        &'a self,
+        path: &mut Vec<&'a str>,
+        menus: &mut Vec<&'a str>,
+        leaves: &mut Vec<Leaf<'a>>,
+    ) {
+        for (key, action) in &self.options {
+            path.push(key);
+            match action.as_menu() {
+                Some(menu) => {
+                    menus.push(&menu.name);
+                    menu.collect_leaves(path, menus, leaves);
+                    menus.pop();
+                }
+                None => leaves.push(Leaf {
+                    path: path.clone(),
+                    menus: menus.clone(),
+                    action: action.as_ref(),
+                    column_values: self.column_values.get(key).map_or(&[], Vec::as_slice),
+                }),
+            }
+            path.pop();
+        }
+    }
+
+    /// Shows every leaf of the menu in a single rofi list and returns the
+    /// selected one.
+    pub fn run_flat(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        let leaves = self.leaves();
+        // Submenu names are passed as keywords, so that they can be searched
+        // even though they are not displayed.
+        let labels = self.leaf_labels(&leaves);
+        let rows = leaves
+            .iter()
+            .zip(&labels)
+            .fold(String::new(), |mut acc, (x, label)| {
+                acc.push_str(&format!("{}\0meta\x1f{}\n", label, x.menus.join(" ")));
+                acc
+            });
+        let (output, _) = self.select(&rows, leaves.len(), &["-format", "i"])?;
+        if output.trim().is_empty() {
+            return Err(Error::Cancelled);
+        }
+        let index = parse_index(&output)?;
+        let leaf = leaves
+            .get(index)
+            .ok_or_else(|| Error::Failed(tr("Menu item has no action")))?;
+        for key in &leaf.path {
+            audit::select(key);
+        }
+        Ok(Some(leaf.action))
+    }
+
+    /// The prompt displayed in the menu.
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    /// The options of the menu, in display order.
+    pub fn options(&self) -> &LinkedHashMap<String, Box<dyn RofiAction>> {
+        &self.options
+    }
+
+    /// Returns the rows shown in rofi, and the option of each row, which is
+    /// `None` for separators.
+    fn optionstring(&self) -> (String, Vec<Option<&str>>) {
+        let mut res = String::new();
+        let mut keys = Vec::new();
+        for (i, (x, label)) in self.options.keys().zip(self.option_rows(true)).enumerate() {
+            for separator in self.separators_before(i) {
+                res.push_str(&format!("{}\0nonselectable\x1ftrue\n", separator));
+                keys.push(None);
+            }
+            match self.icons.get(x) {
+                Some(icon) => res.push_str(&format!("{}\0icon\x1f{}\n", label, icon)),
+                None => res.push_str(&format!("{}\n", label)),
+            }
+            keys.push(Some(x.as_str()));
+        }
+        for separator in self.separators_before(self.options.len()) {
+            res.push_str(&format!("{}\0nonselectable\x1ftrue\n", separator));
+            keys.push(None);
+        }
+        (res, keys)
+    }
+
+    /// Shows `rows` in rofi and returns its output, which is empty if the
+    /// menu was dismissed, and its exit code.
+    fn select(
+        &self,
+        rows: &str,
+        count: usize,
+        extra_args: &[&str],
+    ) -> Result<(String, Option<i32>), Error> {
+        // rofi -p <menu name> -dmenu -no-custom <appearance arguments>
+        let mut args = vec![String::from("-no-custom")];
+        args.extend(self.appearance.args(count));
+        if !self.icons.is_empty() {
+            args.push(String::from("-show-icons"));
+        }
+        args.extend(extra_args.iter().map(|x| String::from(*x)));
+        dmenu(&self.prompt, rows, &args)
+    }
+}
+
+/// Runs rofi in dmenu mode with `prompt` and `args`, listing `rows`, and
+/// returns its output and exit code.
+fn dmenu(prompt: &str, rows: &str, args: &[String]) -> Result<(String, Option<i32>), Error> {
+    let mut comm = Command::new(ROFI)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .arg("-p")
+        .arg(prompt)
+        .arg("-dmenu")
+        .args(args)
+        .spawn()
+        .map_err(|e| Error::spawn(ROFI, e))?;
+    let stdin = process::pipe(comm.stdin.as_mut(), "stdin")?;
+    stdin
+        .write_all(rows.as_bytes())
+        .map_err(|e| Error::io(tr("Failed to write in stdin"), e))?;
+    let output = comm
+        .wait_with_output()
+        .map_err(|e| Error::io(tr("Failed to read stdout"), e))?;
+    Ok((
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        output.status.code(),
+    ))
+}
+
+/// Lets the user choose one of `rows` through rofi and returns its index.
+///
+/// Returns `None` if the menu was dismissed.
+pub fn choose(prompt: &str, rows: &[String]) -> Result<Option<usize>, Error> {
+    let args = [
+        "-l",
+        &rows.len().to_string(),
+        "-i",
+        "-no-custom",
+        "-format",
+        "i",
+    ];
+    let args: Vec<String> = args.iter().map(|x| String::from(*x)).collect();
+    let (output, _) = dmenu(prompt, &format!("{}\n", rows.join("\n")), &args)?;
+    if output.trim().is_empty() {
+        return Ok(None);
+    }
+    parse_index(&output).map(Some)
+}
+
+/// Parses the index of the row selected in rofi with `-format i`.
+fn parse_index(output: &str) -> Result<usize, Error> {
+    output
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| Error::Invalid(trf("Invalid selection index {}", &[&output.trim()])))
+}
+
+/// Inserts `action` into `options` under `label`, numbering the label if it is
+/// already taken, and returns the label used.
+pub fn insert_unique(
+    options: &mut LinkedHashMap<String, Box<dyn RofiAction>>,
+    label: String,
+    action: Box<dyn RofiAction>,
+) -> String {
+    let mut unique = label.clone();
+    let mut n = 1;
+    while options.contains_key(&unique) {
+        n += 1;
+        unique = format!("{} ({})", label, n);
+    }
+    options.insert(unique.clone(), action);
+    unique
+}
+
+/// Asks `question` through rofi and returns whether the answer is yes.
+///
+/// The answers are translated with the message catalog, the question
+/// must already be.
+pub fn ask_confirmation(question: &str) -> Result<bool, Error> {
+    let (no, yes) = (tr(CONFIRM_NO), tr(CONFIRM_YES));
+    let args: Vec<String> = ["-l", "2", "-i", "-no-custom"]
+        .iter()
+        .map(|x| String::from(*x))
+        .collect();
+    let (output, _) = dmenu(question, &format!("{}\n{}\n", no, yes), &args)?;
+    Ok(output.trim() == yes)
+}
+
+/// Shows `message` in a rofi window and waits for it to be closed.
+pub fn show_message(message: &str) -> Result<(), Error> {
+    Command::new(ROFI)
+        .arg("-e")
+        .arg(message)
+        .status()
+        .map_err(|e| Error::spawn(ROFI, e))?;
+    Ok(())
+}
+
+/// Asks for a line of free text through rofi.
+///
+/// Returns `None` if the prompt was dismissed or left empty.
+pub fn prompt_text(prompt: &str) -> Result<Option<String>, Error> {
+    Ok(edit_text(prompt, "")?.filter(|x| !x.is_empty()))
+}
+
+/// Asks for a new value of a text field through rofi, starting from
+/// `current`.
+///
+/// Returns `None` if the prompt was dismissed, while an empty value is
+/// returned as it is.
+pub fn edit_text(prompt: &str, current: &str) -> Result<Option<String>, Error> {
+    let args: Vec<String> = ["-l", "0", "-filter", current]
+        .iter()
+        .map(|x| String::from(*x))
+        .collect();
+    let (output, code) = dmenu(prompt, "", &args)?;
+    // A dismissed prompt exits with an error.
+    if code != Some(0) {
+        return Ok(None);
+    }
+    Ok(Some(String::from(output.trim_end_matches('\n'))))
 }
 
 impl RofiAction for RofiMenu {
-    fn run(&self) -> Result<Option<&Box<RofiAction>>, String> {
-        let mut comm = Command::new(&ROFI)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            // Add the arguments:
-            //
-            // rofi -p <menu name> -l <number of items> -dmenu -i -no-custom
-            .arg("-p")
-            .arg(&self.prompt)
-            .arg("-l")
-            .arg(self.options.len().to_string())
-            .arg("-dmenu")
-            .arg("-i")
-            .arg("-no-custom")
-            .spawn()
-            .or(Err("Failed to spawn process"))?;
-        let stdin = comm.stdin.as_mut().ok_or("Failed to open stdin")?;
-        stdin
-            .write_all(&self.optionstring().into_bytes())
-            .or(Err("Failed to write in stdin"))?;
-        let output = comm.wait_with_output().or(Err("Failed to read stdout"))?;
-        let output = String::from_utf8_lossy(&output.stdout);
-        if output.is_empty() {
-            return Ok(None);
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        if self.flat {
+            return self.run_flat();
+        }
+        let (rows, keys) = self.optionstring();
+        // Rows are selected by index, since they may show more than the
+        // option strings.
+        let mut args = vec![String::from("-format"), String::from("i")];
+        let accelerated = self.accelerated();
+        for (n, option) in accelerated.iter().enumerate() {
+            args.push(format!("-kb-custom-{}", n + 1));
+            args.push(self.accels[*option].clone());
         }
+        // The default bindings of the other custom keys, such as `Alt+2`,
+        // could clash with the accelerators, which rofi refuses.
+        if !accelerated.is_empty() {
+            for n in accelerated.len()..MAX_ACCELS {
+                args.push(format!("-kb-custom-{}", n + 1));
+                args.push(String::new());
+            }
+        }
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let (output, code) = self.select(&rows, keys.len(), &args)?;
+        let accel = code
+            .and_then(|x| x.checked_sub(CUSTOM_KEY_EXIT_CODE))
+            .and_then(|x| accelerated.get(x as usize));
+        let key = match accel {
+            Some(option) => *option,
+            None if output.trim().is_empty() => return Err(Error::Cancelled),
+            None => {
+                let index = parse_index(&output)?;
+                keys.get(index)
+                    .copied()
+                    .flatten()
+                    .ok_or_else(|| Error::Failed(tr("Menu item has no action")))?
+            }
+        };
+        audit::select(key);
         self.options
-            .get::<str>(&output.trim())
-            .map_or(Err("Menu item has no action".to_string()), |x| Ok(Some(x)))
-    }
-}
-
-fn get_config(filename: &str) -> Result<serde_yaml::Value, String> {
-    let mut file = File::open(filename).or(Err("Could not open config file"))?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .or(Err("Could not read config file"))?;
-    let yaml_menu: serde_yaml::Value =
-        serde_yaml::from_str(contents.as_str()).or(Err("Could not parse config file"))?;
-    Ok(yaml_menu)
-}
-
-type FnBuild = Fn(&serde_yaml::Value) -> Result<Box<dyn RofiAction>, String>;
-struct Builder {
-    sub_builders: LinkedHashMap<String, Box<FnBuild>>,
-}
-
-/// A struct that builds a RofiAction hierarchy from a YAML file.
-impl Builder {
-    pub fn new() -> Builder {
-        let sub_builders = LinkedHashMap::new();
-        Builder { sub_builders }
-    }
-
-    /// Add a subbuilder to the builder.
-    pub fn add_subbuilder(&mut self, type_str: String, subbuilder: Box<FnBuild>) {
-        self.sub_builders.insert(type_str, subbuilder);
-    }
-
-    fn build_menu(&self, yaml_menu: &serde_yaml::Value) -> Result<Box<dyn RofiAction>, String> {
-        let name = yaml_menu
-            .get("name")
-            .ok_or("RofiMenu has no name")?
-            .as_str()
-            .ok_or("Name is not a string")?;
-        let prompt = yaml_menu
-            .get("prompt")
-            .ok_or("RofiMenu has no prompt")?
-            .as_str()
-            .ok_or("Prompt is not a string")?;
-        let options = yaml_menu
-            .get("options")
-            .ok_or("RofiMenu has no options")?
-            .as_sequence()
-            .ok_or("Options is not a sequence")?
-            .iter()
-            .filter_map(|x| if x.is_mapping() { Some(x) } else { None });
-
-        let mut option_map = LinkedHashMap::new();
-        for opt in options {
-            let display_string = opt
-                .get("string")
-                .ok_or("Menu option has not string")?
-                .as_str()
-                .ok_or("Menu option string is not a string")?;
-            let action = opt.get("action").ok_or("Menu option has no action")?;
-            let action = self.build_action(action)?;
-            option_map.insert(String::from(display_string), action);
-        }
-        Ok(Box::new(RofiMenu::new(
-            String::from(name),
-            String::from(prompt),
-            option_map,
-        )))
-    }
-
-    fn build_command(
-        &self,
-        yaml_command: &serde_yaml::Value,
-    ) -> Result<Box<dyn RofiAction>, String> {
-        let command = yaml_command
-            .get("command")
-            .ok_or("RofiCommand has no command")?;
-        let command = command.as_str().ok_or("Command is not a string")?;
-        let command = String::from(command);
-
-        let args = yaml_command
-            .get("args")
-            .ok_or("RofiCommand has no args")?
-            .as_sequence()
-            .ok_or("Args is not a sequence")?;
-        let args: Vec<String> = args
-            .iter()
-            .filter_map(|x| x.as_str())
-            .map(|x| String::from(x))
-            .collect();
-        Ok(Box::new(RofiCommand::new(command, args)))
-    }
-
-    fn build_action(&self, yaml: &serde_yaml::Value) -> Result<Box<dyn RofiAction>, String> {
-        let t = yaml
-            .get("type")
-            .ok_or("Action with no type")?
-            .as_str()
-            .ok_or("Type is not string")?;
-        match t {
-            "RofiMenu" => self.build_menu(yaml),
-            "RofiCommand" => self.build_command(yaml),
-            _ => {
-                let sub_builder = self
-                    .sub_builders
-                    .get(t)
-                    .ok_or(format!("Unknown type {}", t))?;
-                sub_builder(yaml)
+            .get::<str>(key)
+            .map(|x| Some(x.as_ref()))
+            .ok_or_else(|| Error::Failed(tr("Menu item has no action")))
+    }
+
+    fn get(&self, key: &str) -> Option<&dyn RofiAction> {
+        self.options.get(key).map(|x| x.as_ref())
+    }
+
+    fn as_menu(&self) -> Option<&RofiMenu> {
+        Some(self)
+    }
+
+    fn needs_input(&self) -> bool {
+        true
+    }
+}
+
+/// Runs `action` and every action that follows it.
+pub fn run_chain(action: &dyn RofiAction) -> Result<(), Error> {
+    follow(action.run()?)
+}
+
+/// Runs `action`, if any, and every action that follows it.
+pub fn follow(mut action: Option<&dyn RofiAction>) -> Result<(), Error> {
+    loop {
+        action = match action {
+            None => {
+                break;
             }
+            Some(a) => a.run()?,
         }
     }
+    Ok(())
 }
 
-fn create_parser() -> App<'static, 'static> {
+/// Finds the action reached by following `path` from `root`.
+///
+/// The path is a list of option strings separated by `/`, as built by
+/// `join_path`. An empty path refers to `root` itself.
+pub fn find_action<'a>(root: &'a dyn RofiAction, path: &str) -> Result<&'a dyn RofiAction, Error> {
+    let mut action = root;
+    for key in split_path(path) {
+        action = action
+            .get(&key)
+            .ok_or_else(|| Error::Invalid(trf("Menu has no option {}", &[&key])))?;
+    }
+    Ok(action)
+}
+
+/// Appends `key` to the menu path `path`, escaping the separators in it.
+pub fn join_path(path: &str, key: &str) -> String {
+    let mut joined = String::from(path);
+    if !joined.is_empty() {
+        joined.push(PATH_SEPARATOR);
+    }
+    for c in key.chars() {
+        if c == PATH_SEPARATOR || c == PATH_ESCAPE {
+            joined.push(PATH_ESCAPE);
+        }
+        joined.push(c);
+    }
+    joined
+}
+
+/// Splits the menu path `path` into its option strings, the reverse of
+/// `join_path`.
+pub fn split_path(path: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    let mut key = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            PATH_ESCAPE => key.extend(chars.next()),
+            PATH_SEPARATOR => keys.push(std::mem::take(&mut key)),
+            _ => key.push(c),
+        }
+    }
+    keys.push(key);
+    keys.retain(|x| !x.is_empty());
+    keys
+}
+
+/// Creates the command line parser shared by every rmenu binary.
+pub fn create_parser() -> App<'static, 'static> {
     App::new("rmenu")
         .version("0.1")
         .author("mandragore")
         .about("Creates custom rofi menus")
+        .after_help(EXIT_STATUS_HELP)
         .arg(
             Arg::with_name("config")
                 .long("--config")
                 .help("The configuration file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("script")
+                .long("--script")
+                .value_name("SELECTION")
+                .help("Run as a rofi script-mode modi")
+                // Rofi appends the selected entry to the command line. Taking
+                // it as the value of `--script` keeps clap from mistaking it
+                // for a subcommand.
+                .takes_value(true)
+                .min_values(0)
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::with_name("plugin-dir")
+                .long("--plugin-dir")
+                .value_name("DIR")
+                .help("A directory searched for plugins before $PATH")
                 .takes_value(true)
-                .required(true),
+                .multiple(true)
+                .number_of_values(1),
         )
+        .arg(
+            Arg::with_name("plugin-timeout")
+                .long("--plugin-timeout")
+                .value_name("SECONDS")
+                .help("How long plugins may take to reply")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("command-timeout")
+                .long("--command-timeout")
+                .value_name("SECONDS")
+                .help("How long commands without a timeout of their own may run")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("policy")
+                .long("--policy")
+                .value_name("FILE")
+                .help("Restrict the commands of the config with this policy file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-policy")
+                .long("--no-policy")
+                .help("Do not restrict the commands of the config")
+                .conflicts_with("policy")
+                .takes_value(false)
+                .multiple(false),
+        )
+        .arg(
+            Arg::with_name("audit-log")
+                .long("--audit-log")
+                .help("Record the commands run in the audit log")
+                .takes_value(false)
+                .multiple(false),
+        )
+        .arg(
+            Arg::with_name("flat")
+                .long("--flat")
+                .help("List the actions of all submenus in a single menu")
+                .takes_value(false)
+                .multiple(false),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Manages the output of generators cached with their cache setting")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("clear")
+                        .about("Removes the cached output")
+                        .arg(
+                            Arg::with_name("key")
+                                .value_name("KEY")
+                                .help("Only remove the output cached under this key"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("refresh")
+                        .about("Generates the output described on the standard input again")
+                        .setting(AppSettings::Hidden),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Reports every command of the config that breaks the policy"),
+        )
+        .subcommand(
+            SubCommand::with_name("daemon")
+                .about("Keeps the menu in memory and shows it when asked by `rmenu show`"),
+        )
+        .subcommand(
+            SubCommand::with_name("edit")
+                .about("Edits the options of the config through rofi and writes it back"),
+        )
+        .subcommand(
+            SubCommand::with_name("from-i3-config")
+                .about("Shows a menu of the key bindings of the i3 config")
+                .arg(
+                    Arg::with_name("file")
+                        .long("--file")
+                        .value_name("FILE")
+                        .help("Read this config file instead of asking the running i3")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("--output")
+                        .value_name("FILE")
+                        .help("Write the menu as an rmenu config file instead of showing it")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Translates a menu of another launcher into an rmenu config file")
+                .arg(
+                    Arg::with_name("from")
+                        .long("--from")
+                        .value_name("FORMAT")
+                        .help("The format of the menu")
+                        .possible_values(&["jgmenu", "xdg-menu"])
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("FILE")
+                        .help("The menu to translate")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("--output")
+                        .value_name("FILE")
+                        .help("Write the config to this file instead of the standard output")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("log")
+                .about("Shows the records of the audit log")
+                .arg(
+                    Arg::with_name("path")
+                        .long("--path")
+                        .value_name("PATH")
+                        .help("Only show the options under this menu path")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .long("--command")
+                        .value_name("COMMAND")
+                        .help("Only show the runs of this command")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("failed")
+                        .long("--failed")
+                        .help("Only show the commands that failed or timed out")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("last")
+                        .long("--last")
+                        .value_name("N")
+                        .help("Only show the last N records")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("--json")
+                        .help("Print the records as JSON lines")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("i3-history")
+                .about("Records the windows focused in i3, for sorting window menus"),
+        )
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("Asks the running daemon to show a menu")
+                .arg(
+                    Arg::with_name("path")
+                        .value_name("PATH")
+                        .help(
+                            "The submenu to show, as option strings separated by `/`, \
+                             where `/` and `\\` in a string are escaped with `\\`",
+                        )
+                        .default_value(""),
+                ),
+        )
+}
+
+/// Shows or writes the menu of the key bindings of an i3 config.
+fn from_i3_config(builder: &Builder, matches: &ArgMatches) -> Result<(), Error> {
+    let parser = ConfigParser::new();
+    let modes = match matches.value_of("file") {
+        Some(file) => parser.parse_file(Path::new(file))?,
+        None => parser.parse_running()?,
+    };
+    let yaml_menu = i3::keybindings::to_yaml(&modes);
+    match matches.value_of("output") {
+        Some(output) => write_yaml(&yaml_menu, Some(output)),
+        None => run_chain(builder.build_action(&yaml_menu)?.as_ref()),
+    }
+}
+
+/// Writes `yaml` to the file `output`, or to the standard output.
+fn write_yaml(yaml: &serde_yaml::Value, output: Option<&str>) -> Result<(), Error> {
+    let contents = serde_yaml::to_string(yaml)
+        .map_err(|e| Error::Failed(trf("Could not serialize menu: {}", &[&e])))?;
+    match output {
+        Some(output) => fs::write(output, contents + "\n")
+            .map_err(|e| Error::io(trf("Could not write {}", &[&output]), e)),
+        None => {
+            println!("{}", contents);
+            Ok(())
+        }
+    }
 }
 
-fn main() -> Result<(), String> {
+/// Translates a menu of another launcher into an rmenu config, warning
+/// about what could not be translated.
+fn import(matches: &ArgMatches) -> Result<(), Error> {
+    let format = matches
+        .value_of("from")
+        .expect("Required argument `from', not specified");
+    let file = matches
+        .value_of("file")
+        .expect("Required argument `file', not specified");
+    let import = import::import(format, file)?;
+    for warning in &import.warnings {
+        eprintln!("rmenu: warning: {}", warning);
+    }
+    write_yaml(&import.menu, matches.value_of("output"))
+}
+
+/// Runs rmenu as instructed by the command line, building menus with
+/// `builder`.
+///
+/// Binaries that register their own action types with
+/// `Builder::add_subbuilder` call this from `main` to reuse the rest of
+/// rmenu.
+///
+/// A dismissed menu returns `Error::Cancelled`, which is not a failure.
+pub fn run_menu(mut builder: Builder) -> Result<(), Error> {
     let parser = create_parser();
     let matches = parser.get_matches();
+    if let Some(dirs) = matches.values_of("plugin-dir") {
+        for dir in dirs {
+            builder.plugins_mut().add_dir(PathBuf::from(dir));
+        }
+    }
+    if let Some(timeout) = matches.value_of("plugin-timeout") {
+        let timeout = timeout
+            .parse::<f64>()
+            .ok()
+            .filter(|x| x.is_finite() && *x >= 0.0)
+            .ok_or_else(|| Error::Invalid(tr("plugin-timeout is not a number of seconds")))?;
+        builder
+            .plugins_mut()
+            .set_timeout(Duration::from_secs_f64(timeout));
+    }
+    if let Some(timeout) = matches.value_of("command-timeout") {
+        let timeout = timeout
+            .parse::<f64>()
+            .ok()
+            .filter(|x| x.is_finite() && *x >= 0.0)
+            .ok_or_else(|| Error::Invalid(tr("command-timeout is not a number of seconds")))?;
+        builder.set_command_timeout(Some(Duration::from_secs_f64(timeout)));
+    }
+    if let ("show", Some(submatches)) = matches.subcommand() {
+        let path = submatches
+            .value_of("path")
+            .expect("Argument with default value `path', not specified");
+        return daemon::show(path, matches.is_present("flat"));
+    }
+    // The default policy file is optional, unlike one that is given. The
+    // commands read from the i3 config are held to it too.
+    let policy_path = match matches.value_of("policy") {
+        _ if matches.is_present("no-policy") => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => Policy::default_path().filter(|x| x.exists()),
+    };
+    if let Some(path) = &policy_path {
+        builder.set_policy(Some(Policy::load(path)?));
+    }
+    if let ("from-i3-config", Some(submatches)) = matches.subcommand() {
+        return from_i3_config(&builder, submatches);
+    }
+    if let ("import", Some(submatches)) = matches.subcommand() {
+        return import(submatches);
+    }
+    if let ("i3-history", Some(_)) = matches.subcommand() {
+        return i3::windows::record_history();
+    }
+    if let ("log", Some(submatches)) = matches.subcommand() {
+        return audit::show(submatches);
+    }
+    if let ("cache", Some(submatches)) = matches.subcommand() {
+        return match submatches.subcommand() {
+            ("clear", Some(clear_matches)) => cache::clear(clear_matches.value_of("key")),
+            _ => {
+                let mut request = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut request)
+                    .map_err(|e| Error::io(tr("Failed to read the refresh request"), e))?;
+                cache::refresh(&request)
+            }
+        };
+    }
+    if matches.is_present("audit-log") {
+        audit::enable()?;
+    }
+
     let conf_filename = matches
         .value_of("config")
-        .ok_or("Required argument config is missing")?;
-    let builder = Builder::new();
-    let yaml_menu = get_config(conf_filename).or(Err("Could not load config file"))?;
-    let main_menu = builder.build_action(&yaml_menu)?;
-    let mut action = main_menu.run()?;
-    loop {
-        action = match action {
-            None => {
-                break;
-            }
-            Some(a) => a.run()?,
+        .ok_or_else(|| Error::Invalid(tr("Required argument config is missing")))?;
+    if let ("check", Some(_)) = matches.subcommand() {
+        let path = policy_path
+            .ok_or_else(|| Error::Invalid(tr("There is no policy to check the config against")))?;
+        let yaml_menu = get_config(conf_filename)?;
+        let violations = builder.check(&yaml_menu).map_err(Error::into_invalid)?;
+        for violation in &violations {
+            println!("{}", violation);
         }
+        if !violations.is_empty() {
+            return Err(Error::Invalid(trf(
+                "{} violations of the policy in {}",
+                &[&violations.len(), &path.display()],
+            )));
+        }
+        return Ok(());
+    }
+    if let ("edit", Some(_)) = matches.subcommand() {
+        return edit::Editor::new(conf_filename)?.run(&builder);
+    }
+    if let ("daemon", Some(_)) = matches.subcommand() {
+        let mut daemon = daemon::Daemon::new(conf_filename, builder)?;
+        daemon.set_flat(matches.is_present("flat"));
+        return daemon.run();
+    }
+
+    let yaml_menu = get_config(conf_filename)?;
+    let main_menu = builder
+        .build_action(&yaml_menu)
+        .map_err(Error::into_invalid)?;
+    if matches.is_present("script") {
+        return script::run_script(main_menu.as_ref(), matches.value_of("script"));
+    }
+    if matches.is_present("flat") {
+        let menu = main_menu
+            .as_menu()
+            .ok_or_else(|| Error::Invalid(tr("Only menus can be flattened")))?;
+        return follow(menu.run_flat()?);
+    }
+    run_chain(main_menu.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Joins `keys` into a menu path.
+    fn join(keys: &[&str]) -> String {
+        keys.iter()
+            .fold(String::new(), |path, key| join_path(&path, key))
+    }
+
+    #[test]
+    fn parse_index_rejects_other_output_as_invalid() {
+        assert_eq!(parse_index("3\n").unwrap(), 3);
+        let e = parse_index("-1\n").unwrap_err();
+        assert_eq!(e.exit_code(), error::EXIT_INVALID);
+        assert_eq!(e.to_string(), "Invalid selection index -1");
+    }
+
+    #[test]
+    fn join_path_escapes_separators_and_escapes() {
+        assert_eq!(join(&["System", "Power"]), "System/Power");
+        assert_eq!(join(&["AC/DC", "Back\\slash"]), "AC\\/DC/Back\\\\slash");
+        assert_eq!(join_path("", "/"), "\\/");
+    }
+
+    #[test]
+    fn split_path_reverses_join_path() {
+        let paths: &[&[&str]] = &[
+            &["System", "Power"],
+            &["AC/DC", "Back\\slash"],
+            &["/", "\\", "\\/", "/\\"],
+            &["trailing\\"],
+        ];
+        for keys in paths {
+            assert_eq!(split_path(&join(keys)), *keys);
+        }
+    }
+
+    #[test]
+    fn split_path_skips_empty_keys() {
+        assert_eq!(split_path("/System//Power/"), vec!["System", "Power"]);
+        assert!(split_path("").is_empty());
+        // A lone escape at the end escapes nothing.
+        assert_eq!(split_path("Power\\"), vec!["Power"]);
     }
-    Ok(())
 }
@@ -0,0 +1,85 @@
+/// The highest placement of the rofi window accepted by `-location`.
+pub const MAX_LOCATION: u8 = 8;
+
+/// The look and behaviour of the rofi window showing a menu.
+///
+/// Every setting is optional, so that unset ones can be inherited from the
+/// `defaults` of an enclosing menu.
+#[derive(Clone, Default)]
+pub struct Appearance {
+    /// The theme file used instead of the configured one.
+    pub theme: Option<String>,
+    /// A theme snippet applied over the theme.
+    pub theme_str: Option<String>,
+    /// The width of the window, as a rofi theme distance such as `30%`.
+    pub width: Option<String>,
+    /// The placement of the window, from 0 (centered) to 8.
+    pub location: Option<u8>,
+    /// How many rows are shown, instead of the number of options.
+    pub lines: Option<usize>,
+    /// A message shown below the prompt, which may use Pango markup.
+    pub mesg: Option<String>,
+    /// Whether filtering the options is case sensitive.
+    pub case_sensitive: Option<bool>,
+    /// Whether the only option left by the filter is selected right away.
+    pub auto_select: Option<bool>,
+    /// Raw arguments appended to the rofi command line.
+    pub extra_args: Option<Vec<String>>,
+}
+
+impl Appearance {
+    /// Returns this appearance, with the settings it leaves unset taken from
+    /// `defaults`.
+    pub fn or(self, defaults: &Appearance) -> Appearance {
+        let defaults = defaults.clone();
+        Appearance {
+            theme: self.theme.or(defaults.theme),
+            theme_str: self.theme_str.or(defaults.theme_str),
+            width: self.width.or(defaults.width),
+            location: self.location.or(defaults.location),
+            lines: self.lines.or(defaults.lines),
+            mesg: self.mesg.or(defaults.mesg),
+            case_sensitive: self.case_sensitive.or(defaults.case_sensitive),
+            auto_select: self.auto_select.or(defaults.auto_select),
+            extra_args: self.extra_args.or(defaults.extra_args),
+        }
+    }
+
+    /// The rofi arguments setting up a menu of `count` options.
+    pub fn args(&self, count: usize) -> Vec<String> {
+        let mut args = vec![String::from("-l"), self.lines.unwrap_or(count).to_string()];
+        if self.case_sensitive == Some(true) {
+            args.push(String::from("-case-sensitive"));
+        } else {
+            args.push(String::from("-i"));
+        }
+        if self.auto_select == Some(true) {
+            args.push(String::from("-auto-select"));
+        }
+        if let Some(theme) = &self.theme {
+            args.push(String::from("-theme"));
+            args.push(theme.clone());
+        }
+        // rofi only reads a single `-theme-str`, so the width is merged into
+        // it.
+        let theme_str = match (&self.width, &self.theme_str) {
+            (Some(width), Some(s)) => Some(format!("window {{width: {};}} {}", width, s)),
+            (Some(width), None) => Some(format!("window {{width: {};}}", width)),
+            (None, s) => s.clone(),
+        };
+        if let Some(theme_str) = theme_str {
+            args.push(String::from("-theme-str"));
+            args.push(theme_str);
+        }
+        if let Some(location) = self.location {
+            args.push(String::from("-location"));
+            args.push(location.to_string());
+        }
+        if let Some(mesg) = &self.mesg {
+            args.push(String::from("-mesg"));
+            args.push(mesg.clone());
+        }
+        args.extend(self.extra_args.iter().flatten().cloned());
+        args
+    }
+}
@@ -0,0 +1,392 @@
+use std::io::prelude::*;
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::BufReader;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ArgMatches;
+use serde_json::json;
+
+use super::locale::{tr, trf};
+use super::{join_path, split_path, Error};
+
+/// The name of the audit log, inside `$XDG_STATE_HOME/i3utils`.
+const LOG_NAME: &str = "rmenu.log";
+
+/// The size past which the audit log is rotated.
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+
+/// How many rotated logs are kept, as `rmenu.log.1` (the newest) and so on.
+const KEEP_ROTATED: usize = 3;
+
+/// How many bytes of the end of the stderr of a command are recorded.
+pub const STDERR_TAIL: usize = 1024;
+
+/// The state of the audit log, when it is enabled.
+struct AuditLog {
+    /// The file records are appended to.
+    file: PathBuf,
+    /// The option strings selected since the menu was shown.
+    path: Vec<String>,
+}
+
+static AUDIT: Mutex<Option<AuditLog>> = Mutex::new(None);
+
+/// How a recorded command ended.
+pub enum Outcome {
+    /// The command was left running in the background.
+    Detached,
+    /// The command exited, or was killed by a signal.
+    Exited(ExitStatus),
+    /// The command was stopped after running out of time.
+    TimedOut,
+}
+
+/// Returns the path of the audit log.
+pub fn log_path() -> Result<PathBuf, Error> {
+    let state_home = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&env::var_os("HOME").ok_or_else(|| Error::Failed(tr("HOME is not set")))?)
+            .join(".local/state"),
+    };
+    Ok(state_home.join("i3utils").join(LOG_NAME))
+}
+
+/// Starts recording the commands run by this rmenu.
+pub fn enable() -> Result<(), Error> {
+    let file = log_path()?;
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| Error::io(trf("Could not create {}", &[&dir.display()]), e))?;
+    }
+    if let Ok(mut audit) = AUDIT.lock() {
+        *audit = Some(AuditLog {
+            file,
+            path: Vec::new(),
+        });
+    }
+    Ok(())
+}
+
+/// Returns whether the commands run are being recorded.
+pub fn is_enabled() -> bool {
+    AUDIT.lock().map(|x| x.is_some()).unwrap_or(false)
+}
+
+/// Notes that the menu at `path` is being shown, or the action at `path` run.
+pub fn begin(path: &str) {
+    if let Ok(mut audit) = AUDIT.lock() {
+        if let Some(audit) = audit.as_mut() {
+            audit.path = split_path(path);
+        }
+    }
+}
+
+/// Notes that the option `key` was selected.
+pub fn select(key: &str) {
+    if let Ok(mut audit) = AUDIT.lock() {
+        if let Some(audit) = audit.as_mut() {
+            audit.path.push(String::from(key));
+        }
+    }
+}
+
+/// Records a command that was run, if the audit log is enabled.
+///
+/// Failing to write the record is reported, but does not fail the command.
+pub fn record(command: &str, args: &[String], outcome: Outcome, duration: Duration, stderr: &[u8]) {
+    let audit = match AUDIT.lock() {
+        Ok(audit) => audit,
+        Err(_) => return,
+    };
+    let audit = match audit.as_ref() {
+        Some(audit) => audit,
+        None => return,
+    };
+
+    let (path, option) = match audit.path.split_last() {
+        Some((option, path)) => (
+            path.iter().fold(String::new(), |acc, x| join_path(&acc, x)),
+            option.as_str(),
+        ),
+        None => (String::new(), ""),
+    };
+    let (exit_code, signal) = match &outcome {
+        Outcome::Exited(status) => (status.code(), status.signal()),
+        _ => (None, None),
+    };
+    let stderr = &stderr[stderr.len().saturating_sub(STDERR_TAIL)..];
+    let record = json!({
+        "time": timestamp(SystemTime::now()),
+        "path": path,
+        "option": option,
+        "command": command,
+        "args": args,
+        "exit_code": exit_code,
+        "signal": signal,
+        "timed_out": matches!(outcome, Outcome::TimedOut),
+        "detached": matches!(outcome, Outcome::Detached),
+        "duration": duration.as_secs_f64(),
+        "stderr": String::from_utf8_lossy(stderr),
+    });
+    if let Err(e) = append(&audit.file, &record) {
+        eprintln!("rmenu: {}", trf("Could not write the audit log: {}", &[&e]));
+    }
+}
+
+/// Appends `record` to the log at `file`, rotating it first if it is full.
+fn append(file: &Path, record: &serde_json::Value) -> std::io::Result<()> {
+    if fs::metadata(file).map(|x| x.len()).unwrap_or(0) >= MAX_LOG_SIZE {
+        for i in (1..KEEP_ROTATED).rev() {
+            let _ = fs::rename(rotated(file, i), rotated(file, i + 1));
+        }
+        fs::rename(file, rotated(file, 1))?;
+    }
+    let mut log = OpenOptions::new().create(true).append(true).open(file)?;
+    // Records are written with a single call, so that those of concurrent
+    // rmenus are not interleaved.
+    log.write_all(format!("{}\n", record).as_bytes())
+}
+
+/// Returns the path of the `n`th rotated log of `file`.
+fn rotated(file: &Path, n: usize) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Formats `time` as an RFC 3339 timestamp in UTC.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+
+    // Converts days since the epoch to a civil date, after Howard Hinnant's
+    // `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Prints the records of the audit log selected by the `log` subcommand.
+pub fn show(matches: &ArgMatches) -> Result<(), Error> {
+    let file = log_path()?;
+    let mut records = Vec::new();
+    // Rotated logs hold the older records, the highest number the oldest.
+    let files = (1..=KEEP_ROTATED)
+        .rev()
+        .map(|x| rotated(&file, x))
+        .chain(std::iter::once(file.clone()));
+    for file in files.filter(|x| x.exists()) {
+        let log = fs::File::open(&file)
+            .map_err(|e| Error::io(trf("Could not open {}", &[&file.display()]), e))?;
+        for line in BufReader::new(log).lines() {
+            let line =
+                line.map_err(|e| Error::io(trf("Could not read {}", &[&file.display()]), e))?;
+            // A record cut short by a crash should not hide the others.
+            if let Ok(record) = serde_json::from_str::<serde_json::Value>(&line) {
+                records.push(record);
+            }
+        }
+    }
+
+    let path = matches.value_of("path");
+    let command = matches.value_of("command");
+    let failed = matches.is_present("failed");
+    records.retain(|x| selected(x, path, command, failed));
+    if let Some(last) = matches.value_of("last") {
+        let last = last
+            .parse::<usize>()
+            .map_err(|_| Error::Invalid(tr("last is not a number of records")))?;
+        records.drain(..records.len().saturating_sub(last));
+    }
+
+    let mut out = std::io::stdout();
+    for record in &records {
+        let text = if matches.is_present("json") {
+            format!("{}\n", record)
+        } else {
+            pretty(record)
+        };
+        if out.write_all(text.as_bytes()).is_err() {
+            // The reader, such as a pager, went away.
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether `record` is of an option at or below the menu path
+/// `path`, of `command`, and of a failure if `failed` is set.
+fn selected(
+    record: &serde_json::Value,
+    path: Option<&str>,
+    command: Option<&str>,
+    failed: bool,
+) -> bool {
+    let get_str = |key| record.get(key).and_then(|x| x.as_str()).unwrap_or("");
+    // Paths are compared by option strings, so that `Sys` is not taken for
+    // the start of `System`.
+    let full_path = split_path(&join_path(get_str("path"), get_str("option")));
+    path.is_none_or(|p| full_path.starts_with(&split_path(p)))
+        && command.is_none_or(|c| get_str("command") == c)
+        && (!failed || !succeeded(record))
+}
+
+/// Returns whether the command of `record` exited successfully.
+fn succeeded(record: &serde_json::Value) -> bool {
+    record.get("detached").and_then(|x| x.as_bool()) == Some(true)
+        || record.get("exit_code").and_then(|x| x.as_i64()) == Some(0)
+}
+
+/// Formats `record` for reading.
+fn pretty(record: &serde_json::Value) -> String {
+    let get_str = |key| record.get(key).and_then(|x| x.as_str()).unwrap_or("");
+    let get_i64 = |key| record.get(key).and_then(|x| x.as_i64());
+
+    let mut command = vec![String::from(get_str("command"))];
+    let args = record.get("args").and_then(|x| x.as_array());
+    for arg in args.into_iter().flatten().filter_map(|x| x.as_str()) {
+        if arg.is_empty() || arg.contains(char::is_whitespace) {
+            command.push(format!("{:?}", arg));
+        } else {
+            command.push(String::from(arg));
+        }
+    }
+    let outcome = if record.get("detached").and_then(|x| x.as_bool()) == Some(true) {
+        String::from("detached")
+    } else if record.get("timed_out").and_then(|x| x.as_bool()) == Some(true) {
+        String::from("timed out")
+    } else if let Some(signal) = get_i64("signal") {
+        format!("killed by signal {}", signal)
+    } else {
+        format!("exit {}", get_i64("exit_code").unwrap_or(-1))
+    };
+    let duration = record
+        .get("duration")
+        .and_then(|x| x.as_f64())
+        .unwrap_or(0.0);
+
+    let mut text = format!(
+        "{}  {}\n    $ {}\n    {} after {:.2} s\n",
+        get_str("time"),
+        join_path(get_str("path"), get_str("option")),
+        command.join(" "),
+        outcome,
+        duration
+    );
+    for line in get_str("stderr").lines() {
+        text.push_str(&format!("    | {}\n", line));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    /// Returns a new empty directory for a test.
+    fn temp_dir() -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "rmenu-audit-test-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Returns the timestamp of `secs` seconds after the epoch.
+    fn at(secs: u64) -> String {
+        timestamp(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn timestamp_formats_utc_dates() {
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(1_709_164_800), "2024-02-29T00:00:00Z");
+        assert_eq!(at(1_709_251_199), "2024-02-29T23:59:59Z");
+        assert_eq!(at(1_704_067_199), "2023-12-31T23:59:59Z");
+        assert_eq!(at(1_704_067_200), "2024-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn append_rotates_full_logs_keeping_three() {
+        let dir = temp_dir();
+        let file = dir.join(LOG_NAME);
+        for n in 1..=KEEP_ROTATED {
+            fs::write(rotated(&file, n), n.to_string()).unwrap();
+        }
+        fs::write(&file, vec![b'x'; MAX_LOG_SIZE as usize]).unwrap();
+
+        append(&file, &json!({"command": "true"})).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "{\"command\":\"true\"}\n"
+        );
+        assert_eq!(fs::metadata(rotated(&file, 1)).unwrap().len(), MAX_LOG_SIZE);
+        assert_eq!(fs::read_to_string(rotated(&file, 2)).unwrap(), "1");
+        assert_eq!(fs::read_to_string(rotated(&file, 3)).unwrap(), "2");
+        assert!(!rotated(&file, 4).exists());
+
+        // A log below the limit is appended to.
+        append(&file, &json!({"command": "false"})).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap().lines().count(), 2);
+        assert_eq!(fs::read_to_string(rotated(&file, 2)).unwrap(), "1");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn selected_filters_by_path_command_and_failure() {
+        let record = json!({
+            "path": "System/Power",
+            "option": "Reboot",
+            "command": "systemctl",
+            "exit_code": 1,
+            "detached": false,
+        });
+        assert!(selected(&record, None, None, false));
+        assert!(selected(&record, Some("System"), None, false));
+        assert!(selected(&record, Some("System/Power/Reboot"), None, false));
+        assert!(!selected(&record, Some("Sys"), None, false));
+        assert!(!selected(&record, Some("System/Pow"), None, false));
+        assert!(!selected(&record, Some("Systemd"), None, false));
+        assert!(selected(&record, None, Some("systemctl"), false));
+        assert!(!selected(&record, None, Some("systemd"), false));
+        assert!(selected(&record, Some("System"), Some("systemctl"), true));
+
+        let record = json!({"path": "", "option": "Lock", "command": "loginctl", "exit_code": 0});
+        assert!(selected(&record, Some("Lock"), None, false));
+        assert!(!selected(&record, None, None, true));
+        let record = json!({"option": "Browser", "command": "firefox", "detached": true});
+        assert!(!selected(&record, None, None, true));
+    }
+}
@@ -0,0 +1,936 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use linked_hash_map::LinkedHashMap;
+
+use super::appearance::{Appearance, MAX_LOCATION};
+use super::cache::{self, Cache};
+use super::clipboard::{self, Content, Copy, Selection};
+use super::columns::{Align, Column, Columns};
+use super::desktop::DesktopApps;
+use super::elevate::Elevate;
+use super::i3::windows::{I3Windows, WindowAction, WindowOrder};
+use super::i3::workspaces::{I3Workspaces, WorkspaceAction};
+use super::i3::{self, I3Command};
+use super::locale::{localize, tr, trf};
+use super::open::{OpenFile, OpenUrl};
+use super::plugin::Plugins;
+use super::policy::Policy;
+use super::secret::{Secret, SecretInput};
+use super::sequence::{Pipeline, Sequence};
+use super::{rofi_binding, Error, RofiAction, RofiCommand, RofiMenu, MAX_ACCELS};
+
+/// The characters that make the command of an i3 `exec` shell code, which
+/// can not be checked against a policy.
+const SHELL_SPECIAL: &str = "|&;<>()$`\\\"'*?[]#~=%{}!\n";
+
+/// Loads the YAML config in `filename`.
+pub fn get_config(filename: &str) -> Result<serde_yaml::Value, Error> {
+    let path = PathBuf::from(filename);
+    let contents = fs::read_to_string(&path).map_err(|source| Error::Config {
+        path: path.clone(),
+        source,
+    })?;
+    serde_yaml::from_str(&contents).map_err(|source| Error::Parse { path, source })
+}
+
+/// A function building a custom action type from its YAML description.
+///
+/// The `Builder` is passed along so that the action can build nested actions
+/// with `Builder::build_action`.
+pub type FnBuild = dyn Fn(&Builder, &serde_yaml::Value) -> Result<Box<dyn RofiAction>, Error>;
+
+/// A struct that builds a RofiAction hierarchy from a YAML file.
+///
+/// Besides the built-in types, it keeps a registry of builders for custom
+/// action types, which are added with `add_subbuilder`. Types that are
+/// neither built in nor registered are built by external plugins.
+pub struct Builder {
+    sub_builders: LinkedHashMap<String, Box<FnBuild>>,
+    plugins: Plugins,
+    /// The appearance inherited from the `defaults` of the menus being built.
+    defaults: RefCell<Appearance>,
+    /// The timeout of commands that do not set one.
+    command_timeout: Option<Duration>,
+    /// The restrictions on the commands that are built.
+    policy: Option<Policy>,
+    /// The policy violations found by `check`, which does not stop at the
+    /// first one.
+    violations: RefCell<Option<Vec<String>>>,
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        let sub_builders = LinkedHashMap::new();
+        let plugins = Plugins::new();
+        Builder {
+            sub_builders,
+            plugins,
+            defaults: RefCell::new(Appearance::default()),
+            command_timeout: None,
+            policy: None,
+            violations: RefCell::new(None),
+        }
+    }
+
+    /// The external plugins used for unknown types.
+    pub fn plugins_mut(&mut self) -> &mut Plugins {
+        &mut self.plugins
+    }
+
+    /// Sets the timeout of commands that do not set one.
+    pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+        self.command_timeout = timeout;
+    }
+
+    /// Sets the restrictions on the commands that are built.
+    pub fn set_policy(&mut self, policy: Option<Policy>) {
+        self.policy = policy;
+    }
+
+    /// Builds the action described in `yaml` and returns every way in which
+    /// it breaks the policy.
+    pub fn check(&self, yaml: &serde_yaml::Value) -> Result<Vec<String>, Error> {
+        self.violations.replace(Some(Vec::new()));
+        let built = self.build_action(yaml);
+        let violations = self.violations.replace(None).unwrap_or_default();
+        built?;
+        Ok(violations)
+    }
+
+    /// Add a subbuilder to the builder.
+    ///
+    /// Actions whose `type` is `type_str` are then built by `subbuilder`.
+    pub fn add_subbuilder(&mut self, type_str: String, subbuilder: Box<FnBuild>) {
+        self.sub_builders.insert(type_str, subbuilder);
+    }
+
+    /// Builds a `RofiMenu` from its YAML description.
+    pub fn build_menu(&self, yaml_menu: &serde_yaml::Value) -> Result<Box<dyn RofiAction>, Error> {
+        let name = yaml_menu
+            .get("name")
+            .ok_or_else(|| Error::Invalid(tr("RofiMenu has no name")))?
+            .as_str()
+            .ok_or_else(|| Error::Invalid(tr("Name is not a string")))?;
+        let prompt = optional_text(yaml_menu, "prompt")?
+            .ok_or_else(|| Error::Invalid(tr("RofiMenu has no prompt")))?;
+        let options = yaml_menu
+            .get("options")
+            .ok_or_else(|| Error::Invalid(tr("RofiMenu has no options")))?
+            .as_sequence()
+            .ok_or_else(|| Error::Invalid(tr("Options is not a sequence")))?;
+        let flat = optional_bool(yaml_menu, "flat")?.unwrap_or(false);
+        let columns = yaml_menu.get("columns").map(build_columns).transpose()?;
+
+        // The defaults of a menu apply to the menu itself and to every menu
+        // below it, unless they override them.
+        let defaults = match yaml_menu.get("defaults") {
+            Some(yaml_defaults) => parse_appearance(yaml_defaults)?.or(&self.defaults.borrow()),
+            None => self.defaults.borrow().clone(),
+        };
+        let appearance = parse_appearance(yaml_menu)?.or(&defaults);
+
+        let previous = self.defaults.replace(defaults);
+        let menu = self.build_options(name, prompt, options, flat, columns);
+        self.defaults.replace(previous);
+
+        let mut menu = menu?;
+        menu.set_appearance(appearance);
+        Ok(Box::new(menu))
+    }
+
+    /// Builds a menu of `options`, described in YAML, laid out in `columns`.
+    fn build_options(
+        &self,
+        name: &str,
+        prompt: &str,
+        options: &[serde_yaml::Value],
+        flat: bool,
+        columns: Option<Columns>,
+    ) -> Result<RofiMenu, Error> {
+        // The first column holds the option strings.
+        let value_count = columns.as_ref().map_or(0, |x| x.len() - 1);
+        let mut option_map = LinkedHashMap::new();
+        let mut icons = Vec::new();
+        let mut column_values = Vec::new();
+        let mut accels: Vec<(&str, &str)> = Vec::new();
+        let mut separators = Vec::new();
+        for opt in options.iter().filter(|x| x.is_mapping()) {
+            if let Some(label) = optional_text(opt, "separator")? {
+                separators.push((option_map.len(), label));
+                continue;
+            }
+            let display_string = optional_text(opt, "string")?
+                .ok_or_else(|| Error::Invalid(tr("Menu option has not string")))?;
+            let action = opt
+                .get("action")
+                .ok_or_else(|| Error::Invalid(tr("Menu option has no action")))?;
+            let action = self.build_action(action)?;
+            option_map.insert(String::from(display_string), action);
+            if let Some(icon) = optional_str(opt, "icon")? {
+                icons.push((display_string, icon));
+            }
+            if let Some(key) = optional_str(opt, "accel")? {
+                // Rofi separates the keys of a binding with commas.
+                if key.is_empty() || key.contains(',') {
+                    return Err(Error::Invalid(trf(
+                        "Invalid accelerator {}",
+                        &[&format!("{:?}", key)],
+                    )));
+                }
+                if let Some(binding) = rofi_binding(key) {
+                    return Err(Error::Invalid(trf(
+                        "Accelerator {} of {} is taken by rofi's {}",
+                        &[&key, &display_string, &binding],
+                    )));
+                }
+                if let Some((other, _)) = accels.iter().find(|(_, x)| *x == key) {
+                    return Err(Error::Invalid(trf(
+                        "Accelerator {} is used by both {} and {}",
+                        &[&key, &other, &display_string],
+                    )));
+                }
+                accels.push((display_string, key));
+            }
+            if let Some(values) = opt.get("columns") {
+                let values = values
+                    .as_sequence()
+                    .ok_or_else(|| Error::Invalid(tr("columns is not a sequence")))?
+                    .iter()
+                    .map(|x| localize(x).map(String::from))
+                    .collect::<Option<Vec<String>>>()
+                    .ok_or_else(|| Error::Invalid(tr("columns item is not a text")))?;
+                if values.len() > value_count {
+                    return Err(Error::Invalid(trf(
+                        "Menu option {} has {} columns but the menu has {} after its strings",
+                        &[&display_string, &values.len(), &value_count],
+                    )));
+                }
+                column_values.push((display_string, values));
+            }
+        }
+        if accels.len() > MAX_ACCELS {
+            return Err(Error::Invalid(trf(
+                "Menu {} has {} accelerators but at most {} are supported",
+                &[&name, &accels.len(), &MAX_ACCELS],
+            )));
+        }
+        let mut menu = RofiMenu::new(String::from(name), String::from(prompt), option_map, flat);
+        for (option, key) in accels {
+            menu.set_accel(option, String::from(key));
+        }
+        for (option, icon) in icons {
+            menu.set_icon(option, String::from(icon));
+        }
+        for (position, label) in separators {
+            menu.add_separator(position, String::from(label));
+        }
+        for (option, values) in column_values {
+            menu.set_column_values(option, values);
+        }
+        menu.set_columns(columns);
+        Ok(menu)
+    }
+
+    /// Parses the appearance set in `yaml`, with the unset settings inherited
+    /// from the enclosing menus.
+    fn appearance(&self, yaml: &serde_yaml::Value) -> Result<Appearance, Error> {
+        Ok(parse_appearance(yaml)?.or(&self.defaults.borrow()))
+    }
+
+    /// Builds a `RofiCommand` from its YAML description.
+    pub fn build_command(
+        &self,
+        yaml_command: &serde_yaml::Value,
+    ) -> Result<Box<dyn RofiAction>, Error> {
+        let command = yaml_command
+            .get("command")
+            .ok_or_else(|| Error::Invalid(tr("RofiCommand has no command")))?;
+        let command = command
+            .as_str()
+            .ok_or_else(|| Error::Invalid(tr("Command is not a string")))?;
+        let command = String::from(command);
+
+        let args = yaml_command
+            .get("args")
+            .ok_or_else(|| Error::Invalid(tr("RofiCommand has no args")))?
+            .as_sequence()
+            .ok_or_else(|| Error::Invalid(tr("Args is not a sequence")))?;
+        let args: Vec<String> = args
+            .iter()
+            .filter_map(|x| x.as_str())
+            .map(String::from)
+            .collect();
+        let timeout = optional_seconds(yaml_command, "timeout")?;
+        let askpass = optional_str(yaml_command, "askpass")?
+            .map(str::parse)
+            .transpose()?;
+        let elevate = match optional_str(yaml_command, "elevate")? {
+            Some(method) => Some(Elevate::new(method, askpass)?),
+            None if askpass.is_some() => {
+                return Err(Error::Invalid(tr(
+                    "askpass only applies to elevated commands",
+                )))
+            }
+            None => None,
+        };
+        // Elevated commands are confirmed unless told otherwise.
+        let confirm = match yaml_command.get("confirm") {
+            Some(serde_yaml::Value::Bool(false)) => None,
+            None if elevate.is_none() => None,
+            None | Some(serde_yaml::Value::Bool(true)) if elevate.is_some() => {
+                Some(trf("Run {} as root?", &[&command]))
+            }
+            None | Some(serde_yaml::Value::Bool(true)) => Some(trf("Run {}?", &[&command])),
+            Some(confirm) => match localize(confirm) {
+                Some(question) => Some(String::from(question)),
+                None => return Err(Error::Invalid(tr("confirm is not a boolean or a text"))),
+            },
+        };
+        let secret = yaml_command.get("secret").map(build_secret).transpose()?;
+        // The environment is reset for elevated commands.
+        if elevate.is_some() && secret.as_ref().is_some_and(|x| x.env().is_some()) {
+            return Err(Error::Invalid(tr(
+                "The secret of an elevated command can only be passed on its stdin",
+            )));
+        }
+        if optional_bool(yaml_command, "detach")?.unwrap_or(false) {
+            if timeout.is_some() {
+                return Err(Error::Invalid(tr(
+                    "Detached commands can not have a timeout",
+                )));
+            }
+            let mut command = RofiCommand::detached(command, args);
+            command.set_confirm(confirm);
+            command.set_secret(secret);
+            command.set_elevate(elevate);
+            self.apply_policy(
+                command.command(),
+                command.args(),
+                command.confirm().is_some(),
+            )?;
+            return Ok(Box::new(command));
+        }
+        let mut command = RofiCommand::new(command, args);
+        command.set_timeout(timeout.or(self.command_timeout));
+        command.set_confirm(confirm);
+        command.set_secret(secret);
+        command.set_elevate(elevate);
+        self.apply_policy(
+            command.command(),
+            command.args(),
+            command.confirm().is_some(),
+        )?;
+        Ok(Box::new(command))
+    }
+
+    /// Fails if running `command` with `args` breaks the policy, unless the
+    /// violations are being collected by `check`. `confirmed` tells whether
+    /// the user is asked before it runs.
+    ///
+    /// Custom builders call this for every program their actions run.
+    pub fn apply_policy(
+        &self,
+        command: &str,
+        args: &[String],
+        confirmed: bool,
+    ) -> Result<(), Error> {
+        let violations = match &self.policy {
+            Some(policy) => policy.violations(command, args, confirmed),
+            None => return Ok(()),
+        };
+        self.report_violations(violations)
+    }
+
+    /// Fails if a policy is set, as the commands run by `action` can not
+    /// be checked against it.
+    fn reject_unchecked(&self, action: &str) -> Result<(), Error> {
+        if self.policy.is_none() {
+            return Ok(());
+        }
+        self.report_violations(vec![trf(
+            "{} can not be checked against the policy",
+            &[&action],
+        )])
+    }
+
+    /// Applies the policy to the `command` of the application opening a URL
+    /// or file for `action`, which is rejected if it has none.
+    fn apply_handler_policy(
+        &self,
+        action: &str,
+        command: Result<Vec<String>, Error>,
+    ) -> Result<(), Error> {
+        match command {
+            Ok(command) => self.apply_policy(&command[0], &command[1..], false),
+            Err(e) => self.reject_unchecked(&format!("{} ({})", action, e)),
+        }
+    }
+
+    /// Fails with `violations` of the policy, unless they are being
+    /// collected by `check`.
+    fn report_violations(&self, violations: Vec<String>) -> Result<(), Error> {
+        if violations.is_empty() {
+            return Ok(());
+        }
+        match self.violations.borrow_mut().as_mut() {
+            Some(found) => found.extend(violations),
+            None => {
+                return Err(Error::Invalid(trf(
+                    "Policy violation: {}",
+                    &[&violations.join("; ")],
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds an `I3Command` from its YAML description.
+    pub fn build_i3_command(
+        &self,
+        yaml_command: &serde_yaml::Value,
+    ) -> Result<Box<dyn RofiAction>, Error> {
+        let command = yaml_command
+            .get("command")
+            .ok_or_else(|| Error::Invalid(tr("I3Command has no command")))?
+            .as_str()
+            .ok_or_else(|| Error::Invalid(tr("Command is not a string")))?;
+        // Programs started by i3 are held to the policy too, as long as they
+        // are plain commands rather than shell code.
+        for exec in i3::exec_commands(command) {
+            if exec.contains(|x: char| SHELL_SPECIAL.contains(x)) {
+                self.reject_unchecked(&format!("i3 exec {}", exec))?;
+                continue;
+            }
+            let mut words = exec.split_whitespace().map(String::from);
+            match words.next() {
+                Some(program) => self.apply_policy(&program, &words.collect::<Vec<_>>(), false)?,
+                None => return Err(Error::Invalid(tr("i3 exec has no command"))),
+            }
+        }
+        Ok(Box::new(I3Command::new(String::from(command))))
+    }
+
+    /// Builds an `I3Windows` generator from its YAML description.
+    pub fn build_i3_windows(
+        &self,
+        yaml_windows: &serde_yaml::Value,
+    ) -> Result<Box<dyn RofiAction>, Error> {
+        let prompt =
+            optional_text(yaml_windows, "prompt")?.map_or_else(|| tr("window"), String::from);
+        let actions = optional_list(yaml_windows, "actions")?.unwrap_or(vec![WindowAction::Focus]);
+        if actions.is_empty() {
+            return Err(Error::Invalid(tr("I3Windows has no actions")));
+        }
+        if yaml_windows.get("cache").is_some() {
+            return Err(Error::Invalid(tr(
+                "I3Windows can not be cached, as i3 changes all the time",
+            )));
+        }
+        let mark = optional_str(yaml_windows, "mark")?.unwrap_or("rmenu");
+        let order = match optional_str(yaml_windows, "sort")? {
+            None | Some("tree") => WindowOrder::Tree,
+            Some("recent") => WindowOrder::Recent,
+            Some(s) => return Err(Error::Invalid(trf("Unknown window order {}", &[&s]))),
+        };
+        let mut windows = I3Windows::new(
+            prompt,
+            actions,
+            String::from(mark),
+            optional_str(yaml_windows, "workspace")?.map(String::from),
+            optional_str(yaml_windows, "output")?.map(String::from),
+            optional_str(yaml_windows, "class")?.map(String::from),
+            order,
+        );
+        windows.set_appearance(self.appearance(yaml_windows)?);
+        Ok(Box::new(windows))
+    }
+
+    /// Builds an `I3Workspaces` generator from its YAML description.
+    pub fn build_i3_workspaces(
+        &self,
+        yaml_workspaces: &serde_yaml::Value,
+    ) -> Result<Box<dyn RofiAction>, Error> {
+        let prompt =
+            optional_text(yaml_workspaces, "prompt")?.map_or_else(|| tr("workspace"), String::from);
+        let actions =
+            optional_list(yaml_workspaces, "actions")?.unwrap_or(vec![WorkspaceAction::Switch]);
+        if actions.is_empty() {
+            return Err(Error::Invalid(tr("I3Workspaces has no actions")));
+        }
+        if yaml_workspaces.get("cache").is_some() {
+            return Err(Error::Invalid(tr(
+                "I3Workspaces can not be cached, as i3 changes all the time",
+            )));
+        }
+        let create = optional_bool(yaml_workspaces, "create")?.unwrap_or(false);
+        let mut workspaces = I3Workspaces::new(prompt, actions, create);
+        workspaces.set_appearance(self.appearance(yaml_workspaces)?);
+        Ok(Box::new(workspaces))
+    }
+
+    /// Builds a `DesktopApps` generator from its YAML description.
+    pub fn build_desktop_apps(
+        &self,
+        yaml_apps: &serde_yaml::Value,
+    ) -> Result<Box<dyn RofiAction>, Error> {
+        let prompt = optional_text(yaml_apps, "prompt")?.unwrap_or("run");
+        let group = optional_bool(yaml_apps, "group_by_category")?.unwrap_or(false);
+        let actions = optional_bool(yaml_apps, "actions")?.unwrap_or(false);
+        let terminal = optional_str(yaml_apps, "terminal")?.unwrap_or("i3-sensible-terminal");
+        let mut apps =
+            DesktopApps::new(String::from(prompt), group, actions, String::from(terminal));
+        if self.policy.is_some() {
+            // Only the applications installed now can be checked, so they are
+            // the only ones launched.
+            self.apply_policy(terminal, &[], false)?;
+            let in_terminal = [String::from(terminal), String::from("-e")];
+            let mut checked = Vec::new();
+            for app in apps.applications() {
+                let commands = Some(app.command).into_iter();
+                for command in commands.chain(app.actions.into_iter().map(|(_, x)| x)) {
+                    let inner = command.strip_prefix(&in_terminal[..]);
+                    for command in Some(&command[..]).into_iter().chain(inner) {
+                        if let Some((program, args)) = command.split_first() {
+                            self.apply_policy(program, args, false)?;
+                        }
+                    }
+                    checked.push(command);
+                }
+            }
+            apps.set_checked(Some(checked));
+        }
+        apps.set_appearance(self.appearance(yaml_apps)?);
+        apps.set_cache(self.cache(yaml_apps, "DesktopApps")?);
+        Ok(Box::new(apps))
+    }
+
+    /// Builds a `Copy` from its YAML description.
+    pub fn build_copy(&self, yaml_copy: &serde_yaml::Value) -> Result<Box<dyn RofiAction>, Error> {
+        let content = match (
+            optional_text(yaml_copy, "text")?,
+            optional_str(yaml_copy, "command")?,
+        ) {
+            (Some(text), None) => Content::Text(String::from(text)),
+            (None, Some(command)) => {
+                let args = optional_list(yaml_copy, "args")?.unwrap_or_default();
+                // The command runs like any other, so it is held to the
+                // same policy.
+                self.apply_policy(command, &args, false)?;
+                Content::Command(String::from(command), args)
+            }
+            _ => return Err(Error::Invalid(tr("Copy needs either a text or a command"))),
+        };
+        let selection = optional_str(yaml_copy, "selection")?
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(Selection::Clipboard);
+        let helper = optional_str(yaml_copy, "helper")?.unwrap_or(clipboard::DEFAULT_HELPER);
+        let helper_args = optional_list(yaml_copy, "helper_args")?;
+        let mut copy = Copy::new(content, selection, String::from(helper), helper_args)?;
+        copy.set_timeout(optional_seconds(yaml_copy, "timeout")?.or(self.command_timeout));
+        self.apply_policy(copy.helper(), copy.helper_args(), false)?;
+        Ok(Box::new(copy))
+    }
+
+    /// Builds an `OpenUrl` from its YAML description.
+    pub fn build_open_url(
+        &self,
+        yaml_open: &serde_yaml::Value,
+    ) -> Result<Box<dyn RofiAction>, Error> {
+        let url = optional_str(yaml_open, "url")?
+            .ok_or_else(|| Error::Invalid(tr("OpenUrl has no url")))?;
+        let terminal = optional_str(yaml_open, "terminal")?.unwrap_or("i3-sensible-terminal");
+        let mut open = OpenUrl::new(String::from(url), String::from(terminal));
+        if self.policy.is_some() {
+            // The application is looked up now, so that the one checked is
+            // the one that runs.
+            let command = open.resolve_handler();
+            self.apply_handler_policy(&format!("OpenUrl {}", url), command)?;
+        }
+        Ok(Box::new(open))
+    }
+
+    /// Builds an `OpenFile` from its YAML description.
+    pub fn build_open_file(
+        &self,
+        yaml_open: &serde_yaml::Value,
+    ) -> Result<Box<dyn RofiAction>, Error> {
+        let path = optional_str(yaml_open, "path")?
+            .ok_or_else(|| Error::Invalid(tr("OpenFile has no path")))?;
+        let terminal = optional_str(yaml_open, "terminal")?.unwrap_or("i3-sensible-terminal");
+        let mut open = OpenFile::new(String::from(path), String::from(terminal));
+        open.set_mime_type(optional_str(yaml_open, "mime_type")?.map(String::from));
+        if self.policy.is_some() {
+            let command = open.resolve_handler();
+            self.apply_handler_policy(&format!("OpenFile {}", path), command)?;
+        }
+        Ok(Box::new(open))
+    }
+
+    /// Parses the `cache` setting of the generator of type `type_str`
+    /// described by `yaml`, if it has one.
+    fn cache(&self, yaml: &serde_yaml::Value, type_str: &str) -> Result<Option<Cache>, Error> {
+        let yaml_cache = match yaml.get("cache") {
+            Some(yaml_cache) => yaml_cache,
+            None => return Ok(None),
+        };
+        let ttl = optional_seconds(yaml_cache, "ttl")?
+            .ok_or_else(|| Error::Invalid(tr("cache has no ttl")))?;
+        let key = match optional_str(yaml_cache, "key")? {
+            Some(key) => String::from(key),
+            None => cache::default_key(type_str, yaml),
+        };
+        Ok(Some(Cache::new(key, ttl)))
+    }
+
+    /// Builds a `Sequence` from its YAML description.
+    pub fn build_sequence(
+        &self,
+        yaml_sequence: &serde_yaml::Value,
+    ) -> Result<Box<dyn RofiAction>, Error> {
+        let actions = self.build_actions(yaml_sequence, "actions")?;
+        let stop_on_error = optional_bool(yaml_sequence, "stop_on_error")?.unwrap_or(true);
+        Ok(Box::new(Sequence::new(actions, stop_on_error)))
+    }
+
+    /// Builds a `Pipeline` from its YAML description.
+    pub fn build_pipeline(
+        &self,
+        yaml_pipeline: &serde_yaml::Value,
+    ) -> Result<Box<dyn RofiAction>, Error> {
+        let commands = self.build_actions(yaml_pipeline, "commands")?;
+        Ok(Box::new(Pipeline::new(commands)?))
+    }
+
+    /// Builds the sequence of actions under `key`.
+    fn build_actions(
+        &self,
+        yaml: &serde_yaml::Value,
+        key: &str,
+    ) -> Result<Vec<Box<dyn RofiAction>>, Error> {
+        yaml.get(key)
+            .ok_or_else(|| Error::Invalid(trf("Action has no {}", &[&key])))?
+            .as_sequence()
+            .ok_or_else(|| Error::Invalid(trf("{} is not a sequence", &[&key])))?
+            .iter()
+            .map(|x| self.build_action(x))
+            .collect()
+    }
+
+    /// Builds an action of any registered type from its YAML description.
+    pub fn build_action(&self, yaml: &serde_yaml::Value) -> Result<Box<dyn RofiAction>, Error> {
+        let t = yaml
+            .get("type")
+            .ok_or_else(|| Error::Invalid(tr("Action with no type")))?
+            .as_str()
+            .ok_or_else(|| Error::Invalid(tr("Type is not string")))?;
+        match t {
+            "RofiMenu" => self.build_menu(yaml),
+            "RofiCommand" => self.build_command(yaml),
+            "I3Command" => self.build_i3_command(yaml),
+            "I3Windows" => self.build_i3_windows(yaml),
+            "I3Workspaces" => self.build_i3_workspaces(yaml),
+            "DesktopApps" => self.build_desktop_apps(yaml),
+            "Sequence" => self.build_sequence(yaml),
+            "Pipeline" => self.build_pipeline(yaml),
+            "Copy" => self.build_copy(yaml),
+            "OpenUrl" => self.build_open_url(yaml),
+            "OpenFile" => self.build_open_file(yaml),
+            _ => match self.sub_builders.get(t) {
+                Some(sub_builder) => sub_builder(self, yaml),
+                None => self.plugins.build(self, t, yaml, self.cache(yaml, t)?),
+            },
+        }
+    }
+}
+
+/// Parses the rofi appearance settings in `yaml`.
+fn parse_appearance(yaml: &serde_yaml::Value) -> Result<Appearance, Error> {
+    // Bare numbers are widths in pixels, as in rofi themes.
+    let width = match yaml.get("width") {
+        None => None,
+        Some(serde_yaml::Value::Number(n)) => Some(format!("{}px", n)),
+        Some(serde_yaml::Value::String(s)) => Some(s.clone()),
+        Some(_) => return Err(Error::Invalid(tr("width is not a string or a number"))),
+    };
+    let location = optional_u64(yaml, "location")?
+        .map(|x| {
+            if x > u64::from(MAX_LOCATION) {
+                return Err(Error::Invalid(trf(
+                    "location must be between 0 and {}",
+                    &[&MAX_LOCATION],
+                )));
+            }
+            Ok(x as u8)
+        })
+        .transpose()?;
+    let extra_args = yaml
+        .get("extra_args")
+        .map(|x| {
+            x.as_sequence()
+                .ok_or_else(|| Error::Invalid(tr("extra_args is not a sequence")))?
+                .iter()
+                .map(|x| {
+                    x.as_str().map(String::from).ok_or_else(|| {
+                        Error::Invalid(tr("extra_args is not a sequence of strings"))
+                    })
+                })
+                .collect::<Result<Vec<String>, Error>>()
+        })
+        .transpose()?;
+    Ok(Appearance {
+        theme: optional_str(yaml, "theme")?.map(String::from),
+        theme_str: optional_str(yaml, "theme_str")?.map(String::from),
+        width,
+        location,
+        lines: optional_u64(yaml, "lines")?.map(|x| x as usize),
+        mesg: optional_str(yaml, "mesg")?.map(String::from),
+        case_sensitive: optional_bool(yaml, "case_sensitive")?,
+        auto_select: optional_bool(yaml, "auto_select")?,
+        extra_args,
+    })
+}
+
+/// Builds the `columns` of a `RofiMenu` from their YAML description.
+fn build_columns(yaml_columns: &serde_yaml::Value) -> Result<Columns, Error> {
+    let columns = yaml_columns
+        .as_sequence()
+        .ok_or_else(|| Error::Invalid(tr("columns is not a sequence")))?
+        .iter()
+        .map(|x| {
+            if !x.is_mapping() {
+                return Err(Error::Invalid(tr("Column is not a map")));
+            }
+            let max_width = match optional_u64(x, "max_width")? {
+                Some(0) => return Err(Error::Invalid(tr("max_width must be positive"))),
+                max_width => max_width.map(|x| x as usize),
+            };
+            let align = optional_str(x, "align")?
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or(Align::Left);
+            Ok(Column::new(max_width, align))
+        })
+        .collect::<Result<Vec<Column>, Error>>()?;
+    if columns.is_empty() {
+        return Err(Error::Invalid(tr("columns is empty")));
+    }
+    Ok(Columns::new(columns))
+}
+
+/// Builds the `secret` of a `RofiCommand` from its YAML description.
+fn build_secret(yaml_secret: &serde_yaml::Value) -> Result<Secret, Error> {
+    if !yaml_secret.is_mapping() {
+        return Err(Error::Invalid(tr("secret is not a map")));
+    }
+    let prompt = optional_text(yaml_secret, "prompt")?.unwrap_or("password");
+    let input = optional_str(yaml_secret, "input")?
+        .map(str::parse)
+        .transpose()?
+        .unwrap_or(SecretInput::Rofi);
+    let env = optional_str(yaml_secret, "env")?;
+    if let Some(env) = env {
+        if env.is_empty() || env.contains('=') || env.contains('\0') {
+            return Err(Error::Invalid(trf(
+                "Invalid environment variable name {}",
+                &[&env],
+            )));
+        }
+    }
+    let mut secret = Secret::new(String::from(prompt), input);
+    secret.set_env(env.map(String::from));
+    secret.set_newline(optional_bool(yaml_secret, "newline")?.unwrap_or(true));
+    Ok(secret)
+}
+
+/// Returns the string value of `key` in `yaml`, if it is present.
+fn optional_str<'a>(yaml: &'a serde_yaml::Value, key: &str) -> Result<Option<&'a str>, Error> {
+    yaml.get(key)
+        .map(|x| {
+            x.as_str()
+                .ok_or_else(|| Error::Invalid(trf("{} is not a string", &[&key])))
+        })
+        .transpose()
+}
+
+/// Returns the text of `key` in `yaml`, if it is present. The text is
+/// either a string or a map of locale names to strings, chosen from.
+fn optional_text<'a>(yaml: &'a serde_yaml::Value, key: &str) -> Result<Option<&'a str>, Error> {
+    yaml.get(key)
+        .map(|x| localize(x).ok_or_else(|| Error::Invalid(trf("{} is not a text", &[&key]))))
+        .transpose()
+}
+
+/// Returns the boolean value of `key` in `yaml`, if it is present.
+fn optional_bool(yaml: &serde_yaml::Value, key: &str) -> Result<Option<bool>, Error> {
+    yaml.get(key)
+        .map(|x| {
+            x.as_bool()
+                .ok_or_else(|| Error::Invalid(trf("{} is not a boolean", &[&key])))
+        })
+        .transpose()
+}
+
+/// Returns the non-negative integer value of `key` in `yaml`, if it is
+/// present.
+fn optional_u64(yaml: &serde_yaml::Value, key: &str) -> Result<Option<u64>, Error> {
+    yaml.get(key)
+        .map(|x| {
+            x.as_u64()
+                .ok_or_else(|| Error::Invalid(trf("{} is not a non-negative integer", &[&key])))
+        })
+        .transpose()
+}
+
+/// Returns the duration under `key` in `yaml`, given in seconds, if it is
+/// present.
+fn optional_seconds(yaml: &serde_yaml::Value, key: &str) -> Result<Option<Duration>, Error> {
+    yaml.get(key)
+        .map(|x| {
+            x.as_f64()
+                .filter(|x| x.is_finite() && *x >= 0.0)
+                .map(Duration::from_secs_f64)
+                .ok_or_else(|| Error::Invalid(trf("{} is not a number of seconds", &[&key])))
+        })
+        .transpose()
+}
+
+/// Parses the sequence of strings under `key` in `yaml`, if it is present.
+fn optional_list<T>(yaml: &serde_yaml::Value, key: &str) -> Result<Option<Vec<T>>, Error>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    yaml.get(key)
+        .map(|x| {
+            x.as_sequence()
+                .ok_or_else(|| Error::Invalid(trf("{} is not a sequence", &[&key])))?
+                .iter()
+                .map(|x| {
+                    x.as_str()
+                        .ok_or_else(|| Error::Invalid(trf("{} item is not a string", &[&key])))?
+                        .parse()
+                        .map_err(|e: T::Err| Error::Invalid(e.to_string()))
+                })
+                .collect()
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a menu of options running `true`, one for each of `accels`,
+    /// with the accelerator if there is one.
+    fn build_with_accels(accels: &[Option<&str>]) -> Result<RofiMenu, Error> {
+        let options: Vec<serde_yaml::Value> = accels
+            .iter()
+            .enumerate()
+            .map(|(i, accel)| {
+                let mut yaml = format!(
+                    "{{string: option {}, action: {{type: RofiCommand, command: 'true', args: []}}",
+                    i
+                );
+                if let Some(accel) = accel {
+                    yaml.push_str(&format!(", accel: '{}'", accel));
+                }
+                yaml.push('}');
+                serde_yaml::from_str(&yaml).unwrap()
+            })
+            .collect();
+        Builder::new().build_options("test", "test", &options, false, None)
+    }
+
+    /// Returns the confirmation question of the command built from `yaml`.
+    fn confirm_of(yaml: &str) -> Option<String> {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(yaml).unwrap();
+        let action = Builder::new().build_action(&yaml).unwrap();
+        action.as_command().unwrap().confirm().map(String::from)
+    }
+
+    #[test]
+    fn elevated_commands_are_confirmed_unless_told_otherwise() {
+        let command = "{type: RofiCommand, command: reboot, args: []";
+        assert_eq!(confirm_of(&format!("{}}}", command)), None);
+        assert_eq!(
+            confirm_of(&format!("{}, confirm: true}}", command)),
+            Some(String::from("Run reboot?"))
+        );
+        for method in ["pkexec", "sudo", "doas"] {
+            let elevated = format!("{}, elevate: {}", command, method);
+            assert_eq!(
+                confirm_of(&format!("{}}}", elevated)),
+                Some(String::from("Run reboot as root?"))
+            );
+            assert_eq!(confirm_of(&format!("{}, confirm: false}}", elevated)), None);
+            assert_eq!(
+                confirm_of(&format!("{}, confirm: 'Really?'}}", elevated)),
+                Some(String::from("Really?"))
+            );
+        }
+    }
+
+    #[test]
+    fn build_options_sets_accelerators() {
+        let menu = build_with_accels(&[Some("Alt+1"), None, Some("Control+y")]).unwrap();
+        assert_eq!(menu.accel("option 0"), Some("Alt+1"));
+        assert_eq!(menu.accel("option 1"), None);
+        assert_eq!(menu.accel("option 2"), Some("Control+y"));
+    }
+
+    #[test]
+    fn build_options_rejects_accelerators_taken_by_rofi() {
+        for accel in ["Control+k", "return", "Shift+Control+Return", "Super+1"] {
+            let result = build_with_accels(&[Some(accel)]);
+            assert!(
+                matches!(&result, Err(Error::Invalid(x)) if x.contains("is taken by rofi's kb-")),
+                "{:?} was accepted",
+                accel
+            );
+        }
+    }
+
+    #[test]
+    fn build_options_rejects_invalid_accelerators() {
+        for accel in ["", "Alt+1,Alt+2"] {
+            let result = build_with_accels(&[Some(accel)]);
+            assert!(
+                matches!(&result, Err(Error::Invalid(x)) if x.starts_with("Invalid accelerator")),
+                "{:?} was accepted",
+                accel
+            );
+        }
+    }
+
+    #[test]
+    fn build_options_rejects_duplicate_accelerators() {
+        match build_with_accels(&[Some("Alt+1"), None, Some("Alt+1")]) {
+            Err(Error::Invalid(message)) => assert_eq!(
+                message,
+                "Accelerator Alt+1 is used by both option 0 and option 2"
+            ),
+            _ => panic!("the duplicate accelerator was accepted"),
+        }
+    }
+
+    #[test]
+    fn build_options_limits_the_number_of_accelerators() {
+        let keys: Vec<String> = (0..=MAX_ACCELS).map(|x| format!("Alt+F{}", x)).collect();
+        let mut accels: Vec<Option<&str>> = keys.iter().map(|x| Some(x.as_str())).collect();
+        assert!(matches!(build_with_accels(&accels), Err(Error::Invalid(_))));
+        accels.pop();
+        assert!(build_with_accels(&accels).is_ok());
+    }
+}
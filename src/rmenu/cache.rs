@@ -0,0 +1,449 @@
+use std::io::prelude::*;
+
+use std::env;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use super::desktop::DesktopApps;
+use super::locale::{tr, trf};
+use super::plugin::Plugin;
+use super::Error;
+
+/// The directory of the cached output, inside `$XDG_CACHE_HOME/i3utils`.
+const CACHE_DIR: &str = "menus";
+
+/// How long a background refresh may run before another one is started for
+/// the same key, in case it died.
+const REFRESH_LOCK_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// What generates cached output, described well enough to generate it again
+/// from another process.
+pub enum Source {
+    /// The reply of a plugin to a `build` request.
+    Plugin {
+        path: PathBuf,
+        node: serde_json::Value,
+        timeout: Duration,
+    },
+    /// The applications listed by `DesktopApps`.
+    DesktopApps { terminal: String },
+}
+
+impl Source {
+    /// Generates the output.
+    pub fn generate(&self) -> Result<serde_json::Value, Error> {
+        match self {
+            Source::Plugin {
+                path,
+                node,
+                timeout,
+            } => Plugin::new(path.clone(), node.clone(), *timeout).call("build"),
+            Source::DesktopApps { terminal } => {
+                let apps = DesktopApps::new(String::new(), false, false, terminal.clone());
+                let apps = apps.applications().iter().map(|x| x.to_json()).collect();
+                Ok(serde_json::Value::Array(apps))
+            }
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Source::Plugin {
+                path,
+                node,
+                timeout,
+            } => json!({
+                "plugin": path,
+                "node": node,
+                "timeout": timeout.as_secs_f64(),
+            }),
+            Source::DesktopApps { terminal } => json!({ "desktop_apps": terminal }),
+        }
+    }
+
+    fn from_json(json: &serde_json::Value) -> Option<Source> {
+        if let Some(terminal) = json.get("desktop_apps") {
+            return Some(Source::DesktopApps {
+                terminal: String::from(terminal.as_str()?),
+            });
+        }
+        Some(Source::Plugin {
+            path: PathBuf::from(json.get("plugin")?.as_str()?),
+            node: json.get("node")?.clone(),
+            timeout: Duration::from_secs_f64(json.get("timeout")?.as_f64()?),
+        })
+    }
+}
+
+/// The `cache` setting of a generator.
+///
+/// Output younger than the TTL is used as is. Older output is still used,
+/// so that the menu shows right away, but it is generated again in the
+/// background for the next time.
+pub struct Cache {
+    /// The name the output is cached under.
+    key: String,
+    ttl: Duration,
+}
+
+impl Cache {
+    pub fn new(key: String, ttl: Duration) -> Cache {
+        Cache { key, ttl }
+    }
+
+    /// Returns the output of `source`, from the cache if it is there.
+    pub fn get(&self, source: &Source) -> Result<serde_json::Value, Error> {
+        if let Some((output, age)) = self.load() {
+            if age > self.ttl {
+                if let Err(e) = self.refresh_in_background(source) {
+                    eprintln!("rmenu: {}", e);
+                }
+            }
+            return Ok(output);
+        }
+        let output = source.generate()?;
+        // The menu can be shown without the cache.
+        if let Err(e) = self.store(&output) {
+            eprintln!("rmenu: {}", e);
+        }
+        Ok(output)
+    }
+
+    /// Returns the path of the file the output is cached in.
+    ///
+    /// The bytes of the key that are not safe in file names are written as
+    /// `%` and their hexadecimal value, so that each key has its own file.
+    fn path(&self) -> Result<PathBuf, Error> {
+        let mut name = String::new();
+        for byte in self.key.bytes() {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                    name.push(char::from(byte))
+                }
+                _ => name.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        Ok(cache_dir()?.join(format!("{}.json", name)))
+    }
+
+    /// Returns the cached output and its age, if there is any.
+    fn load(&self) -> Option<(serde_json::Value, Duration)> {
+        let path = self.path().ok()?;
+        let age = fs::metadata(&path).ok()?.modified().ok()?.elapsed();
+        let output = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+        // A file from the future is as good as new.
+        Some((output, age.unwrap_or_default()))
+    }
+
+    /// Caches `output`.
+    fn store(&self, output: &serde_json::Value) -> Result<(), Error> {
+        let path = self.path()?;
+        let error = |e| Error::io(trf("Could not cache {}", &[&path.display()]), e);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(error)?;
+        }
+        // Write to a temporary file first, so that a concurrent rmenu never
+        // reads half of the output.
+        let tmp = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&tmp, output.to_string())
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(error)
+    }
+
+    /// Starts `rmenu cache refresh` to generate the output again, unless a
+    /// refresh is already running.
+    fn refresh_in_background(&self, source: &Source) -> Result<(), Error> {
+        let lock = self.path()?.with_extension("refresh");
+        let refreshing = fs::metadata(&lock)
+            .and_then(|x| x.modified())
+            .ok()
+            .and_then(|x| x.elapsed().ok())
+            .is_some_and(|x| x < REFRESH_LOCK_TIMEOUT);
+        if refreshing {
+            return Ok(());
+        }
+        fs::write(&lock, "")
+            .map_err(|e| Error::io(trf("Could not create {}", &[&lock.display()]), e))?;
+
+        let request = json!({
+            "key": self.key,
+            "source": source.to_json(),
+        });
+        let exe = env::current_exe().map_err(|e| Error::io(tr("Could not find rmenu"), e))?;
+        let mut child = Command::new(exe)
+            .arg("cache")
+            .arg("refresh")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            // Keep the refresh out of the terminal's signals and the timeout
+            // of the command it may be run from.
+            .process_group(0)
+            .spawn()
+            .map_err(|e| Error::io(tr("Could not refresh the cache"), e))?;
+        let mut stdin = super::process::pipe(child.stdin.take(), "stdin")?;
+        stdin
+            .write_all(request.to_string().as_bytes())
+            .map_err(|e| Error::io(tr("Failed to write in stdin"), e))?;
+        drop(stdin);
+        // A daemon must reap the refresh, while a single run of rmenu leaves
+        // it behind as it exits.
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
+
+/// Returns the default cache key of the generator of type `type_str`
+/// described by `yaml`, which differs for each description.
+///
+/// The description is hashed with 64-bit FNV-1a, which unlike the hasher of
+/// the standard library gives the same key with every build of rmenu.
+pub fn default_key(type_str: &str, yaml: &serde_yaml::Value) -> String {
+    let description = serde_json::to_string(yaml).unwrap_or_default();
+    let hash = description
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash: u64, x| {
+            (hash ^ u64::from(x)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{}-{:016x}", type_str, hash)
+}
+
+/// Returns the directory holding the cached output.
+pub fn cache_dir() -> Result<PathBuf, Error> {
+    let cache_home = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&env::var_os("HOME").ok_or_else(|| Error::Failed(tr("HOME is not set")))?)
+            .join(".cache"),
+    };
+    Ok(cache_home.join("i3utils").join(CACHE_DIR))
+}
+
+/// Generates the output described by `request` and caches it. This is what
+/// `rmenu cache refresh` does in the background.
+pub fn refresh(request: &str) -> Result<(), Error> {
+    let request: serde_json::Value =
+        serde_json::from_str(request).map_err(|e| Error::json(tr("Invalid refresh request"), e))?;
+    let key = request
+        .get("key")
+        .and_then(|x| x.as_str())
+        .ok_or_else(|| Error::Invalid(tr("Refresh request has no key")))?;
+    let source = request
+        .get("source")
+        .and_then(Source::from_json)
+        .ok_or_else(|| Error::Invalid(tr("Refresh request has no valid source")))?;
+
+    let cache = Cache::new(String::from(key), Duration::from_secs(0));
+    let stored = source.generate().and_then(|x| cache.store(&x));
+    let _ = fs::remove_file(cache.path()?.with_extension("refresh"));
+    stored
+}
+
+/// Removes the output cached under `key`, or all of it.
+pub fn clear(key: Option<&str>) -> Result<(), Error> {
+    let dir = cache_dir()?;
+    let result = match key {
+        Some(key) => {
+            let path = Cache::new(String::from(key), Duration::from_secs(0)).path()?;
+            fs::remove_file(&path)
+        }
+        None => fs::remove_dir_all(&dir),
+    };
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(Error::io(trf("Could not clear {}", &[&dir.display()]), e))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Mutex;
+
+    /// Held by the tests using the cache directory, which is set in the
+    /// environment.
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    /// Points the cache directory to a new empty directory for a test, and
+    /// returns it.
+    fn temp_cache(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rmenu-cache-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        env::set_var("XDG_CACHE_HOME", &dir);
+        dir
+    }
+
+    /// Returns a plugin source replying with `action`, in `dir`.
+    fn plugin_source(dir: &Path, action: &str) -> Source {
+        let path = dir.join("rmenu-plugin-test");
+        fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\ncat > /dev/null\necho '{{\"version\": 1, \"action\": {}}}'\n",
+                action
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        Source::Plugin {
+            path,
+            node: json!({"type": "test"}),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Returns a source that fails to generate anything.
+    fn failing_source(dir: &Path) -> Source {
+        Source::Plugin {
+            path: dir.join("missing"),
+            node: json!({}),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn get_generates_missing_output_and_keeps_it() {
+        let _lock = LOCK.lock().unwrap();
+        let dir = temp_cache("generate");
+        let cache = Cache::new(String::from("menu"), Duration::from_secs(3600));
+        let output = cache.get(&plugin_source(&dir, "1")).unwrap();
+        assert_eq!(output["action"], json!(1));
+        assert_eq!(cache.load().unwrap().0, output);
+
+        // Fresh output is used without generating it again.
+        let output = cache.get(&failing_source(&dir)).unwrap();
+        assert_eq!(output["action"], json!(1));
+        assert!(!cache.path().unwrap().with_extension("refresh").exists());
+        assert!(Cache::new(String::from("other"), Duration::from_secs(3600))
+            .get(&failing_source(&dir))
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn get_uses_stale_output_while_it_is_refreshed() {
+        let _lock = LOCK.lock().unwrap();
+        let dir = temp_cache("stale");
+        let cache = Cache::new(String::from("menu"), Duration::from_secs(0));
+        cache.store(&json!([1, 2])).unwrap();
+        // A running refresh is not started again, which keeps the test from
+        // starting one.
+        let lock = cache.path().unwrap().with_extension("refresh");
+        fs::write(&lock, "").unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(cache.get(&failing_source(&dir)).unwrap(), json!([1, 2]));
+        assert!(lock.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn store_replaces_the_output_at_once() {
+        let _lock = LOCK.lock().unwrap();
+        let dir = temp_cache("store");
+        let cache = Cache::new(String::from("menu"), Duration::from_secs(3600));
+        cache.store(&json!({"a": 1})).unwrap();
+        cache.store(&json!({"a": 2})).unwrap();
+        let (output, age) = cache.load().unwrap();
+        assert_eq!(output, json!({"a": 2}));
+        assert!(age < Duration::from_secs(60));
+        // Only the output is left, without its temporary files.
+        let names: Vec<_> = fs::read_dir(cache_dir().unwrap())
+            .unwrap()
+            .map(|x| x.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["menu.json"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clear_removes_one_key_or_all() {
+        let _lock = LOCK.lock().unwrap();
+        let dir = temp_cache("clear");
+        let first = Cache::new(String::from("first"), Duration::from_secs(3600));
+        let second = Cache::new(String::from("second"), Duration::from_secs(3600));
+        first.store(&json!(1)).unwrap();
+        second.store(&json!(2)).unwrap();
+        clear(Some("first")).unwrap();
+        assert!(first.load().is_none());
+        assert!(second.load().is_some());
+        clear(Some("first")).unwrap();
+        clear(None).unwrap();
+        assert!(second.load().is_none());
+        assert!(!cache_dir().unwrap().exists());
+        clear(None).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keys_have_their_own_file_names() {
+        let _lock = LOCK.lock().unwrap();
+        let dir = temp_cache("keys");
+        let name = |key: &str| {
+            let path = Cache::new(String::from(key), Duration::from_secs(0))
+                .path()
+                .unwrap();
+            assert_eq!(path.parent().unwrap(), cache_dir().unwrap());
+            path.file_name().unwrap().to_string_lossy().into_owned()
+        };
+        assert_eq!(name("Plugin-1.0_x"), "Plugin-1.0_x.json");
+        assert_eq!(name("a/b"), "a%2Fb.json");
+        assert_eq!(name("a_b"), "a_b.json");
+        assert_eq!(name("a%2Fb"), "a%252Fb.json");
+        assert_eq!(name("../x"), "..%2Fx.json");
+        assert_eq!(name("é"), "%C3%A9.json");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn default_key_is_a_stable_hash_of_the_description() {
+        let yaml: serde_yaml::Value = serde_yaml::from_str("type: Test\nargs: [1]").unwrap();
+        let key = default_key("Test", &yaml);
+        let description = serde_json::to_string(&yaml).unwrap();
+        assert_eq!(description, r#"{"type":"Test","args":[1]}"#);
+        assert_eq!(key, "Test-003f03326c51c145");
+        assert_ne!(key, default_key("Test", &serde_yaml::Value::Null));
+        assert_eq!(
+            default_key("Test", &serde_yaml::Value::Null),
+            "Test-5b9bc4ba528108e4"
+        );
+    }
+
+    #[test]
+    fn sources_round_trip_through_json() {
+        let plugin = Source::Plugin {
+            path: PathBuf::from("/usr/bin/rmenu-plugin-test"),
+            node: json!({"type": "test", "n": 1}),
+            timeout: Duration::from_millis(1500),
+        };
+        match Source::from_json(&plugin.to_json()) {
+            Some(Source::Plugin {
+                path,
+                node,
+                timeout,
+            }) => {
+                assert_eq!(path, Path::new("/usr/bin/rmenu-plugin-test"));
+                assert_eq!(node, json!({"type": "test", "n": 1}));
+                assert_eq!(timeout, Duration::from_millis(1500));
+            }
+            _ => panic!("not a plugin source"),
+        }
+        let apps = Source::DesktopApps {
+            terminal: String::from("xterm"),
+        };
+        match Source::from_json(&apps.to_json()) {
+            Some(Source::DesktopApps { terminal }) => assert_eq!(terminal, "xterm"),
+            _ => panic!("not a desktop apps source"),
+        }
+        assert!(Source::from_json(&json!({"plugin": "x"})).is_none());
+        assert!(Source::from_json(&json!({"desktop_apps": 1})).is_none());
+    }
+}
@@ -0,0 +1,281 @@
+use std::io::prelude::*;
+
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use super::audit::{self, Outcome};
+use super::locale::{tr, trf};
+use super::{process, Error, RofiAction};
+
+/// The helper copying text when none is configured.
+pub const DEFAULT_HELPER: &str = "xclip";
+
+/// The selection text is copied into.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Selection {
+    /// The selection pasted with Ctrl+V.
+    Clipboard,
+    /// The selection pasted with the middle mouse button.
+    Primary,
+}
+
+impl FromStr for Selection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Selection, Error> {
+        match s {
+            "clipboard" => Ok(Selection::Clipboard),
+            "primary" => Ok(Selection::Primary),
+            _ => Err(Error::Invalid(trf("Unknown selection {}", &[&s]))),
+        }
+    }
+}
+
+/// Where the copied text comes from.
+pub enum Content {
+    Text(String),
+    /// The output of a command and its arguments, without its last newline.
+    Command(String, Vec<String>),
+}
+
+/// An action copying text, or the output of a command, into a selection.
+///
+/// The text is handed to a helper program on its stdin. The arguments of
+/// `xclip`, `xsel` and `wl-copy` are known, while other helpers need theirs
+/// to be given.
+pub struct Copy {
+    content: Content,
+    helper: String,
+    helper_args: Vec<String>,
+    /// How long the command giving the text may run before it is stopped.
+    timeout: Option<Duration>,
+}
+
+impl Copy {
+    /// Creates an action copying `content` into `selection` with `helper`,
+    /// which is given `helper_args` or else its usual arguments.
+    pub fn new(
+        content: Content,
+        selection: Selection,
+        helper: String,
+        helper_args: Option<Vec<String>>,
+    ) -> Result<Copy, Error> {
+        let helper_args = match helper_args {
+            Some(args) => args,
+            None => default_args(&helper, selection).ok_or_else(|| {
+                Error::Invalid(trf(
+                    "Unknown clipboard helper {}, its helper_args must be given",
+                    &[&helper],
+                ))
+            })?,
+        };
+        Ok(Copy {
+            content,
+            helper,
+            helper_args,
+            timeout: None,
+        })
+    }
+
+    /// Sets how long the command giving the text may run before it is
+    /// stopped.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the helper program.
+    pub fn helper(&self) -> &str {
+        &self.helper
+    }
+
+    /// Returns the arguments of the helper program.
+    pub fn helper_args(&self) -> &[String] {
+        &self.helper_args
+    }
+
+    /// Returns the text to copy.
+    fn text(&self) -> Result<Vec<u8>, Error> {
+        let (command, args) = match &self.content {
+            Content::Text(text) => return Ok(text.clone().into_bytes()),
+            Content::Command(command, args) => (command, args),
+        };
+        let start = Instant::now();
+        let mut process = Command::new(command);
+        process
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if self.timeout.is_some() {
+            process.process_group(0);
+        }
+        let mut child = process.spawn().map_err(|e| Error::spawn(command, e))?;
+        let stdout = process::PipeReader::spawn(process::pipe(child.stdout.take(), "stdout")?);
+        let stderr = process::PipeReader::spawn(process::pipe(child.stderr.take(), "stderr")?);
+
+        let deadline = self.timeout.map(|x| start + x);
+        let status = process::wait_until(&mut child, deadline)
+            .map_err(|e| Error::io(tr("Failed to wait for command"), e))?;
+        let status = match status {
+            Some(status) => status,
+            None => {
+                process::kill_groups(&mut [child]);
+                audit::record(command, args, Outcome::TimedOut, start.elapsed(), &[]);
+                return Err(Error::TimedOut {
+                    command: command.clone(),
+                    timeout: self.timeout.unwrap_or_default(),
+                });
+            }
+        };
+        // Whatever the command left running in the background may still hold
+        // its pipes, so they are not waited for past the deadline.
+        let pipe_deadline = process::pipe_deadline(deadline);
+        stdout.wait_closed(pipe_deadline);
+        stderr.wait_closed(pipe_deadline);
+        audit::record(
+            command,
+            args,
+            Outcome::Exited(status),
+            start.elapsed(),
+            &stderr.take(),
+        );
+        if !status.success() {
+            return Err(Error::CommandFailed {
+                command: command.clone(),
+                status,
+            });
+        }
+        let mut text = stdout.take();
+        if text.last() == Some(&b'\n') {
+            text.pop();
+        }
+        Ok(text)
+    }
+}
+
+impl RofiAction for Copy {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        let text = self.text()?;
+        // Helpers fork into the background to serve the selection, so the
+        // process waited for below exits once it has read the text. Their
+        // output is discarded, as the background process keeps it open.
+        let mut helper = Command::new(&self.helper)
+            .args(&self.helper_args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| Error::spawn(&self.helper, e))?;
+        let mut stdin = process::pipe(helper.stdin.take(), "stdin")?;
+        stdin
+            .write_all(&text)
+            .map_err(|e| Error::io(tr("Failed to write in stdin"), e))?;
+        drop(stdin);
+        let status = helper
+            .wait()
+            .map_err(|e| Error::io(tr("Failed to wait for process"), e))?;
+        if !status.success() {
+            return Err(Error::CommandFailed {
+                command: self.helper.clone(),
+                status,
+            });
+        }
+        Ok(None)
+    }
+}
+
+/// Returns the arguments making the known `helper` copy its stdin into
+/// `selection`.
+fn default_args(helper: &str, selection: Selection) -> Option<Vec<String>> {
+    let primary = selection == Selection::Primary;
+    let name = Path::new(helper).file_name()?.to_str()?;
+    let args: &[&str] = match name {
+        "xclip" if primary => &["-selection", "primary", "-in"],
+        "xclip" => &["-selection", "clipboard", "-in"],
+        "xsel" if primary => &["--primary", "--input"],
+        "xsel" => &["--clipboard", "--input"],
+        "wl-copy" if primary => &["--primary"],
+        "wl-copy" => &[],
+        _ => return None,
+    };
+    Some(args.iter().map(|x| String::from(*x)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the arguments of `helper` copying into `selection`.
+    fn args_of(helper: &str, selection: &str) -> Option<Vec<String>> {
+        default_args(helper, selection.parse().unwrap())
+    }
+
+    /// Returns a `Copy` of the output of `sh -c script`.
+    fn copy_of(script: &str) -> Copy {
+        let content = Content::Command(
+            String::from("sh"),
+            vec![String::from("-c"), String::from(script)],
+        );
+        Copy::new(content, Selection::Clipboard, String::from("xclip"), None).unwrap()
+    }
+
+    #[test]
+    fn selection_parses_its_names() {
+        assert!("clipboard".parse::<Selection>().unwrap() == Selection::Clipboard);
+        assert!("primary".parse::<Selection>().unwrap() == Selection::Primary);
+        assert!("Primary".parse::<Selection>().is_err());
+        assert!("secondary".parse::<Selection>().is_err());
+    }
+
+    #[test]
+    fn default_args_knows_the_usual_helpers() {
+        assert_eq!(
+            args_of("xclip", "clipboard").unwrap(),
+            ["-selection", "clipboard", "-in"]
+        );
+        assert_eq!(
+            args_of("/usr/bin/xclip", "primary").unwrap(),
+            ["-selection", "primary", "-in"]
+        );
+        assert_eq!(
+            args_of("xsel", "clipboard").unwrap(),
+            ["--clipboard", "--input"]
+        );
+        assert_eq!(
+            args_of("xsel", "primary").unwrap(),
+            ["--primary", "--input"]
+        );
+        assert!(args_of("wl-copy", "clipboard").unwrap().is_empty());
+        assert_eq!(args_of("wl-copy", "primary").unwrap(), ["--primary"]);
+        assert_eq!(args_of("pbcopy", "clipboard"), None);
+        assert!(Copy::new(
+            Content::Text(String::new()),
+            Selection::Clipboard,
+            String::from("pbcopy"),
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn text_is_the_output_of_the_command_without_its_last_newline() {
+        assert_eq!(copy_of("printf 'a\\n\\n'").text().unwrap(), b"a\n");
+        assert_eq!(copy_of("printf a").text().unwrap(), b"a");
+        assert!(matches!(
+            copy_of("echo a; exit 3").text(),
+            Err(Error::CommandFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn text_stops_the_command_at_its_timeout() {
+        let mut copy = copy_of("sleep 5");
+        copy.set_timeout(Some(Duration::from_millis(100)));
+        let start = Instant::now();
+        assert!(matches!(copy.text(), Err(Error::TimedOut { .. })));
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,187 @@
+use std::str::FromStr;
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use super::locale::trf;
+use super::Error;
+
+/// The space between two columns.
+const COLUMN_GAP: &str = "  ";
+
+/// The mark ending a truncated cell.
+const ELLIPSIS: char = '…';
+
+/// How the cells of a column are aligned.
+#[derive(Clone, Copy)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+impl FromStr for Align {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Align, Error> {
+        match s {
+            "left" => Ok(Align::Left),
+            "right" => Ok(Align::Right),
+            _ => Err(Error::Invalid(trf("Unknown alignment {}", &[&s]))),
+        }
+    }
+}
+
+/// A column of the rows of a menu.
+pub struct Column {
+    /// The width past which cells are truncated, in terminal cells.
+    max_width: Option<usize>,
+    align: Align,
+}
+
+impl Column {
+    pub fn new(max_width: Option<usize>, align: Align) -> Column {
+        Column { max_width, align }
+    }
+}
+
+/// The columns of the rows of a menu, the first of which holds the option
+/// strings.
+///
+/// Cells are padded with spaces, so the columns only line up with a
+/// monospace font. Widths are counted in terminal cells, so that wide
+/// characters count twice and combining ones not at all.
+pub struct Columns {
+    columns: Vec<Column>,
+}
+
+impl Columns {
+    pub fn new(columns: Vec<Column>) -> Columns {
+        Columns { columns }
+    }
+
+    /// The number of columns.
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Whether there are no columns.
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    /// Lays out `rows` of cells, each as wide as the widest cell of its
+    /// column. Missing cells are left blank and columns with no text at all
+    /// are left out.
+    pub fn format(&self, rows: &[Vec<&str>]) -> Vec<String> {
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let widest = rows
+                    .iter()
+                    .filter_map(|x| x.get(i))
+                    .map(|x| x.width())
+                    .max()
+                    .unwrap_or(0);
+                column.max_width.map_or(widest, |x| widest.min(x))
+            })
+            .collect();
+        rows.iter()
+            .map(|row| {
+                let cells: Vec<String> = self
+                    .columns
+                    .iter()
+                    .zip(&widths)
+                    .enumerate()
+                    .filter(|(_, (_, width))| **width > 0)
+                    .map(|(i, (column, width))| {
+                        let cell = truncate(row.get(i).copied().unwrap_or_default(), *width);
+                        let fill = " ".repeat(width - cell.width());
+                        match column.align {
+                            Align::Left => cell + &fill,
+                            Align::Right => fill + &cell,
+                        }
+                    })
+                    .collect();
+                String::from(cells.join(COLUMN_GAP).trim_end())
+            })
+            .collect()
+    }
+}
+
+/// Returns `text` cut to fit in `width` terminal cells, ending with an
+/// ellipsis if it was cut.
+fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return String::from(text);
+    }
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + ELLIPSIS.width().unwrap_or(1) > width {
+            break;
+        }
+        truncated.push(c);
+        used += w;
+    }
+    if width > 0 {
+        truncated.push(ELLIPSIS);
+    }
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_counts_terminal_cells() {
+        assert_eq!(truncate("firefox", 7), "firefox");
+        assert_eq!(truncate("firefox", 5), "fire…");
+        // Wide characters take two cells, so one that does not fit leaves a
+        // cell blank.
+        assert_eq!(truncate("日本語", 6), "日本語");
+        assert_eq!(truncate("日本語", 4), "日…");
+        // Combining characters take none and stay with their base.
+        assert_eq!(truncate("cafe\u{301} au lait", 5), "cafe\u{301}…");
+    }
+
+    #[test]
+    fn truncate_to_no_space_leaves_nothing_but_the_ellipsis() {
+        assert_eq!(truncate("firefox", 1), "…");
+        assert_eq!(truncate("firefox", 0), "");
+        assert_eq!(truncate("", 0), "");
+    }
+
+    #[test]
+    fn format_pads_cells_by_their_width_and_aligns_them() {
+        let columns = Columns::new(vec![
+            Column::new(None, Align::Left),
+            Column::new(None, Align::Right),
+        ]);
+        let rows = vec![vec!["日本", "1"], vec!["cafe\u{301}", "10"], vec!["x"]];
+        assert_eq!(
+            columns.format(&rows),
+            vec!["日本   1", "cafe\u{301}  10", "x"]
+        );
+    }
+
+    #[test]
+    fn format_truncates_cells_and_leaves_out_empty_columns() {
+        let columns = Columns::new(vec![
+            Column::new(Some(4), Align::Left),
+            Column::new(None, Align::Left),
+            Column::new(Some(3), Align::Right),
+        ]);
+        let rows = vec![vec!["firefox", "", "日本語"], vec!["vi", ""]];
+        assert_eq!(columns.format(&rows), vec!["fir…  日…", "vi"]);
+    }
+
+    #[test]
+    fn align_parses_its_names() {
+        assert!(matches!("left".parse(), Ok(Align::Left)));
+        assert!(matches!("right".parse(), Ok(Align::Right)));
+        assert!(matches!("centre".parse::<Align>(), Err(Error::Invalid(_))));
+    }
+}
@@ -0,0 +1,561 @@
+use std::io::prelude::*;
+use std::io::BufReader;
+
+use std::env;
+use std::ffi::{CString, OsString};
+use std::fs;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::Duration;
+
+use super::locale::{tr, trf};
+use super::{audit, find_action, follow, get_config, run_chain, Builder, Error, RofiAction};
+
+/// The name of the socket the daemon listens on, inside `$XDG_RUNTIME_DIR`.
+const SOCKET_NAME: &str = "rmenu.sock";
+
+/// How long a client may take to send its request or read the reply, so
+/// that a stuck client does not block the daemon.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The signals stopping the daemon, which then removes its socket.
+const STOP_SIGNALS: &[libc::c_int] = &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// Returns the path of the socket used by `rmenu daemon` and `rmenu show`.
+pub fn socket_path() -> Result<PathBuf, Error> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+        .ok_or_else(|| Error::Failed(tr("XDG_RUNTIME_DIR is not set")))?;
+    Ok(Path::new(&runtime_dir).join(SOCKET_NAME))
+}
+
+/// An inotify watch on the directory holding a config file.
+///
+/// The directory is watched instead of the file itself, so that editors that
+/// save by renaming a new file over the old one are noticed too. When the
+/// config is a symlink, the directory of its target is watched as well.
+pub struct ConfigWatch {
+    fd: RawFd,
+    /// The watch descriptors of the directories, with the name of the file
+    /// watched in each.
+    files: Vec<(libc::c_int, OsString)>,
+}
+
+impl ConfigWatch {
+    pub fn new(filename: &str) -> Result<ConfigWatch, Error> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(Error::io(
+                tr("Failed to initialize inotify"),
+                std::io::Error::last_os_error(),
+            ));
+        }
+        let mut watch = ConfigWatch {
+            fd,
+            files: Vec::new(),
+        };
+        let path = Path::new(filename);
+        watch.add(path)?;
+        if let Ok(target) = fs::canonicalize(path) {
+            if fs::symlink_metadata(path).is_ok_and(|x| x.file_type().is_symlink()) {
+                watch.add(&target)?;
+            }
+        }
+        Ok(watch)
+    }
+
+    /// Watches the directory holding the file at `path`.
+    fn add(&mut self, path: &Path) -> Result<(), Error> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| Error::Invalid(tr("Config path has no file name")))?
+            .to_os_string();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|_| Error::Invalid(trf("Invalid config directory {}", &[&dir.display()])))?;
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, dir.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(Error::io(
+                tr("Failed to watch config directory"),
+                std::io::Error::last_os_error(),
+            ));
+        }
+        self.files.push((wd, file_name));
+        Ok(())
+    }
+
+    /// Reads the pending events and returns whether any of them concerns the
+    /// config file.
+    fn changed(&self) -> Result<bool, Error> {
+        let mut buf = [0u8; 4096];
+        let len = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if len < 0 {
+            return Err(Error::io(
+                tr("Failed to read inotify events"),
+                std::io::Error::last_os_error(),
+            ));
+        }
+
+        let header_len = mem::size_of::<libc::inotify_event>();
+        let mut changed = false;
+        let mut offset = 0;
+        while offset + header_len <= len as usize {
+            let event: libc::inotify_event =
+                unsafe { ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+            let name = &buf[offset + header_len..offset + header_len + event.len as usize];
+            let name = name.split(|x| *x == 0).next().unwrap_or(&[]);
+            if self
+                .files
+                .iter()
+                .any(|(wd, file_name)| event.wd == *wd && name == file_name.as_bytes())
+            {
+                changed = true;
+            }
+            offset += header_len + event.len as usize;
+        }
+        Ok(changed)
+    }
+}
+
+impl Drop for ConfigWatch {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// A signalfd receiving the signals that stop the daemon, which are blocked
+/// so that they are only read from it.
+struct StopSignals {
+    fd: RawFd,
+}
+
+impl StopSignals {
+    fn new() -> Result<StopSignals, Error> {
+        let fd = unsafe {
+            let mut set: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            for signal in STOP_SIGNALS {
+                libc::sigaddset(&mut set, *signal);
+            }
+            // Commands started by the daemon get an empty signal mask from
+            // `Command`, so they can still be stopped.
+            libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+            libc::signalfd(-1, &set, libc::SFD_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(Error::io(
+                tr("Failed to handle signals"),
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok(StopSignals { fd })
+    }
+
+    /// Lets the signals stop this process again, in a child that serves a
+    /// request.
+    fn unblock() {
+        unsafe {
+            let mut set: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            for signal in STOP_SIGNALS {
+                libc::sigaddset(&mut set, *signal);
+            }
+            libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, ptr::null_mut());
+        }
+    }
+}
+
+impl Drop for StopSignals {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// A long-running process that keeps the built menu in memory and shows it
+/// when asked through its socket.
+pub struct Daemon {
+    conf_filename: String,
+    builder: Builder,
+    menu: Box<dyn RofiAction>,
+    /// Whether menus are shown flat, listing the actions of their submenus.
+    flat: bool,
+    socket: PathBuf,
+    listener: UnixListener,
+    watch: ConfigWatch,
+    signals: StopSignals,
+    /// The children serving requests, which are reaped once they exit.
+    servers: Vec<libc::pid_t>,
+}
+
+impl Daemon {
+    pub fn new(conf_filename: &str, builder: Builder) -> Result<Daemon, Error> {
+        let yaml_menu = get_config(conf_filename)?;
+        let menu = builder
+            .build_action(&yaml_menu)
+            .map_err(Error::into_invalid)?;
+        let watch = ConfigWatch::new(conf_filename)?;
+        let signals = StopSignals::new()?;
+
+        let socket = socket_path()?;
+        if UnixStream::connect(&socket).is_ok() {
+            return Err(Error::io(
+                tr("rmenu daemon is already running"),
+                std::io::ErrorKind::AddrInUse.into(),
+            ));
+        }
+        // Nobody is listening, so any existing file is left over from a
+        // daemon that did not exit cleanly.
+        let _ = fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).map_err(|e| {
+            Error::io(
+                trf("Could not bind daemon socket {}", &[&socket.display()]),
+                e,
+            )
+        })?;
+
+        Ok(Daemon {
+            conf_filename: String::from(conf_filename),
+            builder,
+            menu,
+            flat: false,
+            socket,
+            listener,
+            watch,
+            signals,
+            servers: Vec::new(),
+        })
+    }
+
+    /// Sets whether menus are shown flat, unless a request says otherwise.
+    pub fn set_flat(&mut self, flat: bool) {
+        self.flat = flat;
+    }
+
+    /// Waits for requests and config changes until an error occurs or the
+    /// daemon is stopped by a signal.
+    pub fn run(&mut self) -> Result<(), Error> {
+        loop {
+            self.servers
+                .retain(|pid| unsafe { libc::waitpid(*pid, ptr::null_mut(), libc::WNOHANG) } == 0);
+            let mut fds = [
+                libc::pollfd {
+                    fd: self.listener.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: self.watch.fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: self.signals.fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(Error::io(
+                    tr("Failed to wait for daemon events"),
+                    std::io::Error::last_os_error(),
+                ));
+            }
+
+            if fds[2].revents & libc::POLLIN != 0 {
+                return Ok(());
+            }
+            if fds[1].revents & libc::POLLIN != 0 && self.watch.changed()? {
+                self.reload();
+            }
+            if fds[0].revents & libc::POLLIN != 0 {
+                let stream = match self.listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(_) => continue,
+                };
+                self.spawn_server(stream);
+            }
+        }
+    }
+
+    /// Serves a request from a child process, so that the daemon can show
+    /// the menu again, or reload it, while the selected action runs.
+    ///
+    /// The child has a copy of the menu, which is not changed by reloads.
+    fn spawn_server(&mut self, stream: UnixStream) {
+        match unsafe { libc::fork() } {
+            0 => {
+                StopSignals::unblock();
+                report(self.serve(stream));
+                // Exiting right away keeps the child from removing the
+                // socket of the daemon as it is dropped.
+                unsafe { libc::_exit(0) }
+            }
+            pid if pid > 0 => self.servers.push(pid),
+            _ => {
+                let e = std::io::Error::last_os_error();
+                eprintln!("rmenu: {}", Error::io(tr("Failed to fork"), e));
+                report(self.serve(stream));
+            }
+        }
+    }
+
+    /// Rebuilds the menu from the config file, keeping the previous one if
+    /// the new config is invalid.
+    fn reload(&mut self) {
+        // A symlink may now point somewhere else.
+        match ConfigWatch::new(&self.conf_filename) {
+            Ok(watch) => self.watch = watch,
+            Err(e) => eprintln!("rmenu: {}", e),
+        }
+        let menu = get_config(&self.conf_filename).and_then(|x| self.builder.build_action(&x));
+        match menu {
+            Ok(menu) => self.menu = menu,
+            Err(e) => eprintln!(
+                "rmenu: {}",
+                trf(
+                    "Could not reload config, keeping the previous menu: {}",
+                    &[&e]
+                )
+            ),
+        }
+    }
+
+    /// Handles a single `show` or `show-flat` request and replies with its
+    /// outcome.
+    ///
+    /// The reply is `ok`, `cancelled`, or `error` followed by the exit code
+    /// and message of the error, whose line breaks are escaped.
+    fn serve(&self, stream: UnixStream) -> Result<(), Error> {
+        stream
+            .set_read_timeout(Some(REQUEST_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(REQUEST_TIMEOUT)))
+            .map_err(|e| Error::io(tr("Failed to set request timeout"), e))?;
+        let mut request = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut request)
+            .map_err(|e| Error::io(tr("Failed to read request"), e))?;
+        let request = request.trim_end_matches('\n');
+
+        let result = dispatch(self.menu.as_ref(), request, self.flat);
+        (&stream)
+            .write_all(reply(&result).as_bytes())
+            .map_err(|e| Error::io(tr("Failed to send reply"), e))?;
+        result
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket);
+    }
+}
+
+/// Asks the running daemon to show the menu found at `path`, flat if `flat`
+/// is set.
+pub fn show(path: &str, flat: bool) -> Result<(), Error> {
+    let stream = UnixStream::connect(socket_path()?)
+        .map_err(|e| Error::io(tr("rmenu daemon is not running"), e))?;
+    let verb = if flat { "show-flat" } else { "show" };
+    (&stream)
+        .write_all(format!("{} {}\n", verb, path).as_bytes())
+        .map_err(|e| Error::io(tr("Failed to send request"), e))?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut reply)
+        .map_err(|e| Error::io(tr("Failed to read reply"), e))?;
+    parse_reply(reply.trim_end_matches('\n'))
+}
+
+/// Runs `request`, a `show` or `show-flat` request without its line break,
+/// on `menu`, whose submenus are shown flat if `flat` is set.
+fn dispatch(menu: &dyn RofiAction, request: &str, flat: bool) -> Result<(), Error> {
+    let (verb, path) = request.split_once(' ').unwrap_or((request, ""));
+    match verb {
+        "show" => show_menu(menu, path, flat),
+        "show-flat" => show_menu(menu, path, true),
+        _ => Err(Error::Invalid(trf("Unknown request {}", &[&request]))),
+    }
+}
+
+/// Shows the menu found at `path` from `root`, listing the actions of its
+/// submenus if `flat` is set.
+fn show_menu(root: &dyn RofiAction, path: &str, flat: bool) -> Result<(), Error> {
+    audit::begin(path);
+    let action = find_action(root, path)?;
+    if flat {
+        let menu = action
+            .as_menu()
+            .ok_or_else(|| Error::Invalid(tr("Only menus can be flattened")))?;
+        return follow(menu.run_flat()?);
+    }
+    run_chain(action)
+}
+
+/// Returns the reply telling the client the outcome `result` of its request.
+fn reply(result: &Result<(), Error>) -> String {
+    match result {
+        Ok(()) => String::from("ok\n"),
+        Err(Error::Cancelled) => String::from("cancelled\n"),
+        Err(e) => format!("error {} {}\n", e.exit_code(), escape(&e.to_string())),
+    }
+}
+
+/// Returns the outcome of a request from `reply`, the reply of the daemon
+/// without its line break.
+fn parse_reply(reply: &str) -> Result<(), Error> {
+    let invalid = || Error::Invalid(tr("Invalid reply from rmenu daemon"));
+    match reply.strip_prefix("error ") {
+        Some(e) => {
+            let (exit_code, message) = e.split_once(' ').ok_or_else(invalid)?;
+            Err(Error::Reported {
+                exit_code: exit_code.parse().map_err(|_| invalid())?,
+                message: unescape(message),
+            })
+        }
+        None if reply == "ok" => Ok(()),
+        None if reply == "cancelled" => Err(Error::Cancelled),
+        None => Err(invalid()),
+    }
+}
+
+/// Prints the error a request failed with, if it was not just dismissed.
+fn report(result: Result<(), Error>) {
+    match result {
+        Ok(()) | Err(Error::Cancelled) => (),
+        Err(e) => eprintln!("rmenu: {}", e),
+    }
+}
+
+/// Escapes the line breaks and backslashes of `message`, so that it fits in
+/// a single line of a reply.
+fn escape(message: &str) -> String {
+    message.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Undoes `escape`.
+fn unescape(message: &str) -> String {
+    let mut unescaped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rmenu::error::{EXIT_COMMAND_FAILED, EXIT_INVALID};
+
+    /// Returns the menu served in the tests, whose options run `true` and
+    /// `false`.
+    fn menu() -> Box<dyn RofiAction> {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(
+            "{type: RofiMenu, name: test, prompt: test, options: [
+                {string: 'ok', action: {type: RofiCommand, command: 'true', args: []}},
+                {string: 'a/b', action: {type: RofiCommand, command: 'false', args: []}}]}",
+        )
+        .unwrap();
+        Builder::new().build_action(&yaml).unwrap()
+    }
+
+    /// Returns the reply to `request` with the menu of the tests, and the
+    /// outcome the client reads from it.
+    fn served(request: &str) -> (String, Result<(), Error>) {
+        let reply = reply(&dispatch(menu().as_ref(), request, false));
+        let outcome = parse_reply(reply.trim_end_matches('\n'));
+        (reply, outcome)
+    }
+
+    #[test]
+    fn dispatch_runs_the_action_at_the_path() {
+        let (reply, outcome) = served("show ok");
+        assert_eq!(reply, "ok\n");
+        assert!(outcome.is_ok());
+
+        let (reply, outcome) = served("show a\\/b");
+        assert_eq!(
+            reply,
+            format!(
+                "error {} Command false failed: exit status: 1\n",
+                EXIT_COMMAND_FAILED
+            )
+        );
+        match outcome {
+            Err(Error::Reported { exit_code, message }) => {
+                assert_eq!(exit_code, EXIT_COMMAND_FAILED);
+                assert_eq!(message, "Command false failed: exit status: 1");
+            }
+            _ => panic!("the failure was not reported"),
+        }
+    }
+
+    #[test]
+    fn dispatch_rejects_invalid_requests() {
+        for (request, message) in [
+            ("show missing", "Menu has no option missing"),
+            ("show-flat ok", "Only menus can be flattened"),
+            ("hide ok", "Unknown request hide ok"),
+            ("", "Unknown request "),
+        ] {
+            assert_eq!(
+                served(request).0,
+                format!("error {} {}\n", EXIT_INVALID, message)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_reply_reads_each_outcome() {
+        assert!(parse_reply("ok").is_ok());
+        assert!(matches!(parse_reply("cancelled"), Err(Error::Cancelled)));
+        assert_eq!(reply(&Err(Error::Cancelled)), "cancelled\n");
+        for reply in ["", "okay", "error", "error x message", "error 2"] {
+            match parse_reply(reply) {
+                Err(Error::Invalid(message)) => {
+                    assert_eq!(message, "Invalid reply from rmenu daemon")
+                }
+                _ => panic!("{:?} was accepted", reply),
+            }
+        }
+        match parse_reply("error 3 line one\\nline two") {
+            Err(e) => {
+                assert_eq!(e.exit_code(), 3);
+                assert_eq!(e.to_string(), "line one\nline two");
+            }
+            Ok(()) => panic!("the error was not reported"),
+        }
+    }
+
+    #[test]
+    fn escape_keeps_messages_on_one_line() {
+        let message = "Plugin failed: exit status: 1: line one\nline two \\n\\";
+        let escaped = escape(message);
+        assert!(!escaped.contains('\n'));
+        assert_eq!(unescape(&escaped), message);
+    }
+}
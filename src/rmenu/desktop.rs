@@ -0,0 +1,734 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use linked_hash_map::LinkedHashMap;
+use serde_json::json;
+
+use super::appearance::Appearance;
+use super::cache::{Cache, Source};
+use super::locale::{locales, tr};
+use super::process::find_executable;
+use super::{insert_unique, run_chain, Error, RofiAction, RofiCommand, RofiMenu};
+
+/// The group holding the application itself in a desktop entry.
+const ENTRY_GROUP: &str = "Desktop Entry";
+
+/// The prefix of the groups holding desktop actions.
+const ACTION_GROUP_PREFIX: &str = "Desktop Action ";
+
+/// The main categories of the desktop menu specification, in the order
+/// their submenus are listed.
+const MAIN_CATEGORIES: &[&str] = &[
+    "AudioVideo",
+    "Audio",
+    "Video",
+    "Development",
+    "Education",
+    "Game",
+    "Graphics",
+    "Network",
+    "Office",
+    "Science",
+    "Settings",
+    "System",
+    "Utility",
+];
+
+/// The submenu of applications without a main category.
+const OTHER_CATEGORY: &str = "Other";
+
+/// A parsed desktop entry file, as a map from group to keys.
+///
+/// Other files of the same format, such as `mimeapps.list`, are read with it
+/// too.
+pub struct DesktopFile {
+    groups: HashMap<String, HashMap<String, String>>,
+}
+
+impl DesktopFile {
+    pub fn parse(contents: &str) -> DesktopFile {
+        let mut groups: HashMap<String, HashMap<String, String>> = HashMap::new();
+        let mut group = None;
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = String::from(&line[1..line.len() - 1]);
+                groups.entry(name.clone()).or_default();
+                group = Some(name);
+                continue;
+            }
+            if let (Some(group), Some(index)) = (&group, line.find('=')) {
+                let key = line[..index].trim();
+                let value = line[index + 1..].trim();
+                if let Some(keys) = groups.get_mut(group) {
+                    // Only the first occurrence of a key counts.
+                    keys.entry(String::from(key))
+                        .or_insert_with(|| unescape(value));
+                }
+            }
+        }
+        DesktopFile { groups }
+    }
+
+    /// Returns the value of `key` in `group`.
+    pub fn get(&self, group: &str, key: &str) -> Option<&str> {
+        self.groups
+            .get(group)
+            .and_then(|x| x.get(key))
+            .map(String::as_str)
+    }
+
+    /// Returns the value of `key` in `group` best matching `locales`, which
+    /// are listed from the most to the least specific.
+    fn get_localized(&self, group: &str, key: &str, locales: &[String]) -> Option<&str> {
+        locales
+            .iter()
+            .find_map(|x| self.get(group, &format!("{}[{}]", key, x)))
+            .or_else(|| self.get(group, key))
+    }
+
+    /// Returns the boolean value of `key` in `group`, false if it is absent.
+    fn get_bool(&self, group: &str, key: &str) -> bool {
+        self.get(group, key) == Some("true")
+    }
+
+    /// Returns the values of the list `key` in `group`.
+    pub fn get_list(&self, group: &str, key: &str) -> Vec<&str> {
+        self.get(group, key)
+            .map(|x| x.split(';').filter(|x| !x.is_empty()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// An application found in a desktop entry.
+pub struct Application {
+    /// The desktop file id, such as `org.gnome.Nautilus.desktop`.
+    pub id: String,
+    pub name: String,
+    pub icon: Option<String>,
+    pub categories: Vec<String>,
+    pub command: Vec<String>,
+    /// The name and command of each desktop action.
+    pub actions: Vec<(String, Vec<String>)>,
+}
+
+impl Application {
+    /// Describes the application in JSON, for caching.
+    pub fn to_json(&self) -> serde_json::Value {
+        let actions: Vec<serde_json::Value> = self
+            .actions
+            .iter()
+            .map(|(name, command)| json!({ "name": name, "command": command }))
+            .collect();
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "icon": self.icon,
+            "categories": self.categories,
+            "command": self.command,
+            "actions": actions,
+        })
+    }
+
+    /// Reads an application described by `to_json`.
+    pub fn from_json(json: &serde_json::Value) -> Option<Application> {
+        let get_str = |json: &serde_json::Value, key| json.get(key)?.as_str().map(String::from);
+        let get_list = |json: &serde_json::Value, key| -> Option<Vec<String>> {
+            json.get(key)?
+                .as_array()?
+                .iter()
+                .map(|x| x.as_str().map(String::from))
+                .collect()
+        };
+        let actions = json
+            .get("actions")?
+            .as_array()?
+            .iter()
+            .map(|x| Some((get_str(x, "name")?, get_list(x, "command")?)))
+            .collect::<Option<_>>()?;
+        Some(Application {
+            id: get_str(json, "id")?,
+            name: get_str(json, "name")?,
+            icon: get_str(json, "icon"),
+            categories: get_list(json, "categories")?,
+            command: get_list(json, "command")?,
+            actions,
+        })
+    }
+}
+
+/// A generator listing the applications installed with desktop entries.
+pub struct DesktopApps {
+    /// The prompt displayed in the menu.
+    prompt: String,
+    /// Whether applications are grouped in submenus by category.
+    group: bool,
+    /// Whether applications with desktop actions open a submenu of them.
+    actions: bool,
+    /// The terminal emulator running applications that need one.
+    terminal: String,
+    /// The look and behaviour of the generated menus.
+    appearance: Appearance,
+    /// Where the list of applications is cached, if it is.
+    cache: Option<Cache>,
+    /// The only commands launched, if they have been checked against a
+    /// policy.
+    checked: Option<Vec<Vec<String>>>,
+}
+
+impl DesktopApps {
+    pub fn new(prompt: String, group: bool, actions: bool, terminal: String) -> DesktopApps {
+        DesktopApps {
+            prompt,
+            group,
+            actions,
+            terminal,
+            appearance: Appearance::default(),
+            cache: None,
+            checked: None,
+        }
+    }
+
+    /// Only launches `checked`, the commands checked against a policy, and
+    /// leaves out the applications and actions running anything else.
+    pub fn set_checked(&mut self, checked: Option<Vec<Vec<String>>>) {
+        self.checked = checked;
+    }
+
+    /// Returns whether `command` may be launched.
+    fn is_checked(&self, command: &[String]) -> bool {
+        self.checked
+            .as_ref()
+            .is_none_or(|x| x.iter().any(|x| x == command))
+    }
+
+    /// Sets where the list of applications is cached.
+    pub fn set_cache(&mut self, cache: Option<Cache>) {
+        self.cache = cache;
+    }
+
+    /// Sets the look and behaviour of the generated menus.
+    pub fn set_appearance(&mut self, appearance: Appearance) {
+        self.appearance = appearance;
+    }
+
+    /// Returns the applications currently installed that are meant to be
+    /// shown, sorted by name.
+    pub fn applications(&self) -> Vec<Application> {
+        let locales = locales();
+        let desktops: Vec<String> = env::var("XDG_CURRENT_DESKTOP")
+            .unwrap_or_default()
+            .split(':')
+            .filter(|x| !x.is_empty())
+            .map(String::from)
+            .collect();
+
+        let mut apps: Vec<Application> = desktop_files()
+            .into_iter()
+            .filter_map(|(id, path)| self.application(id, &path, &locales, &desktops))
+            .collect();
+        apps.sort_by_key(|x| x.name.to_lowercase());
+        apps
+    }
+
+    /// Builds the menu of the applications currently installed.
+    pub fn menu(&self) -> Result<RofiMenu, Error> {
+        let apps = match &self.cache {
+            Some(cache) => {
+                let source = Source::DesktopApps {
+                    terminal: self.terminal.clone(),
+                };
+                cache
+                    .get(&source)?
+                    .as_array()
+                    .and_then(|x| x.iter().map(Application::from_json).collect())
+                    .ok_or_else(|| {
+                        Error::Invalid(tr(
+                            "The cached applications are invalid, see rmenu cache clear",
+                        ))
+                    })?
+            }
+            None => self.applications(),
+        };
+
+        if !self.group {
+            return Ok(self.app_menu(String::from("applications"), self.prompt.clone(), apps));
+        }
+        let mut categories: LinkedHashMap<&str, Vec<Application>> = MAIN_CATEGORIES
+            .iter()
+            .chain(&[OTHER_CATEGORY])
+            .map(|x| (*x, Vec::new()))
+            .collect();
+        for app in apps {
+            let category = MAIN_CATEGORIES
+                .iter()
+                .find(|x| app.categories.iter().any(|y| y == *x))
+                .unwrap_or(&OTHER_CATEGORY);
+            if let Some(apps) = categories.get_mut(category) {
+                apps.push(app);
+            }
+        }
+        let mut options: LinkedHashMap<String, Box<dyn RofiAction>> = LinkedHashMap::new();
+        for (category, apps) in categories {
+            if !apps.is_empty() {
+                let menu = self.app_menu(String::from(category), String::from(category), apps);
+                options.insert(String::from(category), Box::new(menu));
+            }
+        }
+        let mut menu = RofiMenu::new(
+            String::from("applications"),
+            self.prompt.clone(),
+            options,
+            false,
+        );
+        menu.set_appearance(self.appearance.clone());
+        Ok(menu)
+    }
+
+    /// Builds a menu launching `apps`.
+    fn app_menu(&self, name: String, prompt: String, apps: Vec<Application>) -> RofiMenu {
+        let mut options: LinkedHashMap<String, Box<dyn RofiAction>> = LinkedHashMap::new();
+        let mut icons = Vec::new();
+        for mut app in apps {
+            if !self.is_checked(&app.command) {
+                continue;
+            }
+            app.actions.retain(|(_, x)| self.is_checked(x));
+            let action: Box<dyn RofiAction> = if self.actions && !app.actions.is_empty() {
+                let mut actions: LinkedHashMap<String, Box<dyn RofiAction>> = LinkedHashMap::new();
+                actions.insert(app.name.clone(), launch(app.command));
+                for (name, command) in app.actions {
+                    insert_unique(&mut actions, name, launch(command));
+                }
+                let mut menu = RofiMenu::new(app.name.clone(), app.name.clone(), actions, false);
+                menu.set_appearance(self.appearance.clone());
+                Box::new(menu)
+            } else {
+                launch(app.command)
+            };
+            let label = insert_unique(&mut options, app.name, action);
+            if let Some(icon) = app.icon {
+                icons.push((label, icon));
+            }
+        }
+        let mut menu = RofiMenu::new(name, prompt, options, false);
+        for (label, icon) in icons {
+            menu.set_icon(&label, icon);
+        }
+        menu.set_appearance(self.appearance.clone());
+        menu
+    }
+
+    /// Reads the application described by the desktop entry at `path`, unless
+    /// it is not meant to be shown.
+    fn application(
+        &self,
+        id: String,
+        path: &Path,
+        locales: &[String],
+        desktops: &[String],
+    ) -> Option<Application> {
+        let file = DesktopFile::parse(&fs::read_to_string(path).ok()?);
+        if file.get(ENTRY_GROUP, "Type") != Some("Application")
+            || file.get_bool(ENTRY_GROUP, "NoDisplay")
+            || file.get_bool(ENTRY_GROUP, "Hidden")
+        {
+            return None;
+        }
+        let only_show_in = file.get_list(ENTRY_GROUP, "OnlyShowIn");
+        if !only_show_in.is_empty() && !only_show_in.iter().any(|x| desktops.iter().any(|y| y == x))
+        {
+            return None;
+        }
+        let not_show_in = file.get_list(ENTRY_GROUP, "NotShowIn");
+        if not_show_in.iter().any(|x| desktops.iter().any(|y| y == x)) {
+            return None;
+        }
+        if let Some(try_exec) = file.get(ENTRY_GROUP, "TryExec") {
+            find_executable(try_exec)?;
+        }
+
+        let name = String::from(file.get_localized(ENTRY_GROUP, "Name", locales)?);
+        let icon = file.get(ENTRY_GROUP, "Icon").map(String::from);
+        let terminal = file.get_bool(ENTRY_GROUP, "Terminal");
+        let expand = |exec: &str| {
+            let command = expand_exec(exec, &name, icon.as_deref(), path, None)?;
+            Some(if terminal {
+                let mut wrapped = vec![self.terminal.clone(), String::from("-e")];
+                wrapped.extend(command);
+                wrapped
+            } else {
+                command
+            })
+        };
+        let command = expand(file.get(ENTRY_GROUP, "Exec")?)?;
+
+        let mut actions = Vec::new();
+        for action in file.get_list(ENTRY_GROUP, "Actions") {
+            let group = format!("{}{}", ACTION_GROUP_PREFIX, action);
+            let action_name = file.get_localized(&group, "Name", locales);
+            let exec = file.get(&group, "Exec").and_then(&expand);
+            if let (Some(action_name), Some(exec)) = (action_name, exec) {
+                actions.push((String::from(action_name), exec));
+            }
+        }
+
+        Some(Application {
+            id,
+            icon: icon.clone(),
+            categories: file
+                .get_list(ENTRY_GROUP, "Categories")
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            name,
+            command,
+            actions,
+        })
+    }
+}
+
+impl RofiAction for DesktopApps {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        run_chain(&self.menu()?)?;
+        Ok(None)
+    }
+
+    fn generate(&self) -> Option<Result<RofiMenu, Error>> {
+        Some(self.menu())
+    }
+
+    fn needs_input(&self) -> bool {
+        true
+    }
+}
+
+/// Returns a detached command launching `command`.
+fn launch(mut command: Vec<String>) -> Box<dyn RofiAction> {
+    let program = command.remove(0);
+    Box::new(RofiCommand::detached(program, command))
+}
+
+/// Returns the name and icon of the directory entry `filename`, which
+/// describes a submenu, looked up in the `desktop-directories` data
+/// directories.
+pub fn directory_entry(filename: &str) -> Option<(String, Option<String>)> {
+    let locales = locales();
+    data_dirs()
+        .into_iter()
+        .map(|x| x.join("desktop-directories").join(filename))
+        .find_map(|x| fs::read_to_string(x).ok())
+        .and_then(|contents| {
+            let file = DesktopFile::parse(&contents);
+            let name = file.get_localized(ENTRY_GROUP, "Name", &locales)?;
+            let icon = file.get(ENTRY_GROUP, "Icon").map(String::from);
+            Some((String::from(name), icon))
+        })
+}
+
+/// Returns the directories holding desktop entries, the most important
+/// first.
+fn application_dirs() -> Vec<PathBuf> {
+    data_dirs()
+        .into_iter()
+        .map(|x| x.join("applications"))
+        .collect()
+}
+
+/// Returns the XDG data directories, the most important first.
+pub fn data_dirs() -> Vec<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|x| Path::new(&x).join(".local/share")));
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| String::from("/usr/local/share:/usr/share"));
+    data_home
+        .into_iter()
+        .chain(data_dirs.split(':').map(PathBuf::from))
+        .collect()
+}
+
+/// Returns the command opening `target`, a file or URL, with the
+/// application of the desktop entry `id`, if it is installed.
+///
+/// Applications whose command takes no file or URL get it as their last
+/// argument.
+pub fn open_command(id: &str, target: &str, terminal: &str) -> Option<Vec<String>> {
+    let (_, path) = desktop_files().into_iter().find(|(x, _)| x == id)?;
+    let file = DesktopFile::parse(&fs::read_to_string(&path).ok()?);
+    let exec = file.get(ENTRY_GROUP, "Exec")?;
+    let name = file
+        .get_localized(ENTRY_GROUP, "Name", &locales())
+        .unwrap_or(id);
+    let icon = file.get(ENTRY_GROUP, "Icon");
+    let mut command = expand_exec(exec, name, icon, &path, Some(target))?;
+    let takes_target = split_exec(exec)?
+        .iter()
+        .any(|(arg, quoted)| !quoted && ["%f", "%F", "%u", "%U"].contains(&arg.as_str()));
+    if !takes_target {
+        command.push(String::from(target));
+    }
+    if file.get_bool(ENTRY_GROUP, "Terminal") {
+        let mut wrapped = vec![String::from(terminal), String::from("-e")];
+        wrapped.extend(command);
+        return Some(wrapped);
+    }
+    Some(command)
+}
+
+/// Returns the ids of the desktop entries declaring that they open files of
+/// `mime_type`, whether they are shown or not.
+pub fn mime_handlers(mime_type: &str) -> Vec<String> {
+    desktop_files()
+        .into_iter()
+        .filter(|(_, path)| {
+            fs::read_to_string(path)
+                .map(|x| {
+                    let file = DesktopFile::parse(&x);
+                    !file.get_bool(ENTRY_GROUP, "Hidden")
+                        && file.get_list(ENTRY_GROUP, "MimeType").contains(&mime_type)
+                })
+                .unwrap_or(false)
+        })
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Returns the desktop file ids and paths of the desktop entries, keeping
+/// only the most important one for each id.
+pub fn desktop_files() -> Vec<(String, PathBuf)> {
+    let mut files: LinkedHashMap<String, PathBuf> = LinkedHashMap::new();
+    for dir in application_dirs() {
+        collect_desktop_files(&dir, &dir, &mut files);
+    }
+    files.into_iter().collect()
+}
+
+/// Adds the desktop entries below `dir` to `files`, under their desktop file
+/// id relative to `root`.
+fn collect_desktop_files(root: &Path, dir: &Path, files: &mut LinkedHashMap<String, PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for path in entries.filter_map(|x| x.ok()).map(|x| x.path()) {
+        if path.is_dir() {
+            collect_desktop_files(root, &path, files);
+        } else if path.extension().is_some_and(|x| x == "desktop") {
+            // Files in subdirectories have the directories in their id, so
+            // `kde/foo.desktop` is `kde-foo.desktop`.
+            let id = path
+                .strip_prefix(root)
+                .map(|x| x.to_string_lossy().replace('/', "-"))
+                .unwrap_or_default();
+            files.entry(id).or_insert(path);
+        }
+    }
+}
+
+/// Unescapes a string value of a desktop entry.
+fn unescape(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some(c) => {
+                // Other escapes, like those of the `Exec` quoting rules, are
+                // kept for the next level of parsing.
+                result.push('\\');
+                result.push(c);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Splits an `Exec` value into arguments and expands its field codes.
+///
+/// `%f`, `%F`, `%u` and `%U` expand to `target`, the file or URL opened, and
+/// to nothing if there is none. `%i` expands to the icon, `%c` to the name
+/// and `%k` to the path of the desktop entry.
+fn expand_exec(
+    exec: &str,
+    name: &str,
+    icon: Option<&str>,
+    path: &Path,
+    target: Option<&str>,
+) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    for (arg, quoted) in split_exec(exec)? {
+        // Quoted arguments can not hold field codes, but still write literal
+        // percent signs as `%%`.
+        if quoted {
+            args.push(arg.replace("%%", "%"));
+            continue;
+        }
+        match arg.as_str() {
+            "%f" | "%F" | "%u" | "%U" => args.extend(target.map(String::from)),
+            "%i" => {
+                if let Some(icon) = icon {
+                    args.push(String::from("--icon"));
+                    args.push(String::from(icon));
+                }
+            }
+            _ => {
+                let mut expanded = String::new();
+                let mut chars = arg.chars();
+                while let Some(c) = chars.next() {
+                    if c != '%' {
+                        expanded.push(c);
+                        continue;
+                    }
+                    match chars.next() {
+                        Some('%') => expanded.push('%'),
+                        Some('c') => expanded.push_str(name),
+                        Some('k') => expanded.push_str(&path.to_string_lossy()),
+                        // The other codes are either deprecated or expand to
+                        // files, so they are dropped.
+                        _ => (),
+                    }
+                }
+                args.push(expanded);
+            }
+        }
+    }
+    if args.is_empty() {
+        return None;
+    }
+    Some(args)
+}
+
+/// Splits an `Exec` value into arguments, returning whether each was quoted.
+fn split_exec(exec: &str) -> Option<Vec<(String, bool)>> {
+    let mut args = Vec::new();
+    let mut chars = exec.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|x| *x == ' ') {
+            chars.next();
+        }
+        let first = match chars.peek() {
+            Some(c) => *c,
+            None => break,
+        };
+        let mut arg = String::new();
+        if first == '"' {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => arg.push(chars.next()?),
+                    c => arg.push(c),
+                }
+            }
+            args.push((arg, true));
+        } else {
+            while let Some(c) = chars.peek() {
+                if *c == ' ' {
+                    break;
+                }
+                arg.push(*c);
+                chars.next();
+            }
+            args.push((arg, false));
+        }
+    }
+    Some(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expands `exec` for an entry named `Editor` with the icon `editor`,
+    /// read from `/usr/share/applications/editor.desktop`.
+    fn expand(exec: &str, target: Option<&str>) -> Option<Vec<String>> {
+        expand_exec(
+            exec,
+            "Editor",
+            Some("editor"),
+            Path::new("/usr/share/applications/editor.desktop"),
+            target,
+        )
+    }
+
+    /// Returns the owned form of `args`.
+    fn args(args: &[&str]) -> Option<Vec<String>> {
+        Some(args.iter().map(|x| String::from(*x)).collect())
+    }
+
+    #[test]
+    fn expand_exec_replaces_field_codes() {
+        assert_eq!(
+            expand("editor %F", Some("/tmp/notes.txt")),
+            args(&["editor", "/tmp/notes.txt"])
+        );
+        assert_eq!(expand("editor %u", None), args(&["editor"]));
+        assert_eq!(
+            expand("editor %i --title=%c", None),
+            args(&["editor", "--icon", "editor", "--title=Editor"])
+        );
+        assert_eq!(
+            expand("editor --entry %k", None),
+            args(&[
+                "editor",
+                "--entry",
+                "/usr/share/applications/editor.desktop"
+            ])
+        );
+    }
+
+    #[test]
+    fn expand_exec_keeps_literal_percents_and_drops_deprecated_codes() {
+        assert_eq!(
+            expand("printf 100%% --device=%d%n%v", None),
+            args(&["printf", "100%", "--device="])
+        );
+    }
+
+    #[test]
+    fn expand_exec_keeps_field_codes_of_quoted_arguments() {
+        assert_eq!(
+            expand(r#"sh -c "echo \"%f\" \$HOME \\ done"  %f"#, Some("a b")),
+            args(&["sh", "-c", r#"echo "%f" $HOME \ done"#, "a b"])
+        );
+        assert_eq!(
+            expand(r#"sh -c "date +%%H:%%M%%%%""#, None),
+            args(&["sh", "-c", "date +%H:%M%%"])
+        );
+    }
+
+    #[test]
+    fn expand_exec_rejects_unterminated_quotes_and_empty_commands() {
+        assert_eq!(expand(r#"sh -c "echo"#, None), None);
+        assert_eq!(expand(r#"sh -c "echo \"#, None), None);
+        assert_eq!(expand("%f", None), None);
+        assert_eq!(expand("   ", None), None);
+    }
+
+    #[test]
+    fn unescape_leaves_exec_escapes_to_split_exec() {
+        let exec = unescape(r#"sh -c "printf \\"%%s\\\\n\\" x""#);
+        assert_eq!(exec, r#"sh -c "printf \"%%s\\n\" x""#);
+        assert_eq!(
+            expand(&exec, None),
+            args(&["sh", "-c", r#"printf "%s\n" x"#])
+        );
+    }
+}
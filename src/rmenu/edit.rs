@@ -0,0 +1,644 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+
+use super::locale::{localize, select, tr, trf};
+use super::{
+    ask_confirmation, choose, edit_text, get_config, prompt_text, show_message, Builder, Error,
+};
+
+/// The entries listed after the options of a menu, before they are
+/// translated.
+const ADD_COMMAND: &str = "+ Add command";
+const ADD_SUBMENU: &str = "+ Add submenu";
+const SAVE: &str = "Save and quit";
+const QUIT: &str = "Quit without saving";
+const BACK: &str = "..";
+
+/// An operation on a single option.
+#[derive(Clone, Copy)]
+enum OptionEdit {
+    Open,
+    Rename,
+    EditCommand,
+    EditArgs,
+    MoveUp,
+    MoveDown,
+    Remove,
+}
+
+impl OptionEdit {
+    fn label(self) -> &'static str {
+        match self {
+            OptionEdit::Open => "Open submenu",
+            OptionEdit::Rename => "Rename",
+            OptionEdit::EditCommand => "Edit command",
+            OptionEdit::EditArgs => "Edit arguments",
+            OptionEdit::MoveUp => "Move up",
+            OptionEdit::MoveDown => "Move down",
+            OptionEdit::Remove => "Remove",
+        }
+    }
+}
+
+/// An editor of config files driven through rofi.
+///
+/// Menus are browsed like in rmenu itself, with entries to add commands and
+/// submenus after their options. Selecting an option offers to rename, move
+/// or remove it, and to edit its command. The config is checked with the
+/// `Builder` before it is written back.
+///
+/// The file is written back in canonical form. The YAML parser drops
+/// comments, so only the comments before and after the config are kept, and
+/// saving asks first if there are others.
+pub struct Editor<'a> {
+    /// The path of the config file.
+    filename: &'a str,
+    /// The config being edited.
+    root: Value,
+    /// Whether the config was changed since it was loaded.
+    modified: bool,
+    /// The comment lines before the config.
+    header: String,
+    /// The comment lines after the config.
+    footer: String,
+    /// Whether there are comments within the config, which are lost when it
+    /// is saved.
+    inner_comments: bool,
+}
+
+impl<'a> Editor<'a> {
+    /// Loads the config in `filename` for editing.
+    pub fn new(filename: &'a str) -> Result<Editor<'a>, Error> {
+        let root = get_config(filename)?;
+        if root.get("type").and_then(|x| x.as_str()) != Some("RofiMenu") {
+            return Err(Error::Invalid(tr(
+                "Only configs holding a RofiMenu can be edited",
+            )));
+        }
+        let contents = fs::read_to_string(filename)
+            .map_err(|e| Error::io(trf("Could not read {}", &[&filename]), e))?;
+        let lines: Vec<&str> = contents.lines().collect();
+        let is_comment = |x: &str| x.trim().is_empty() || x.trim_start().starts_with('#');
+        let start = lines.iter().take_while(|x| is_comment(x)).count();
+        let end = lines.len()
+            - lines[start..]
+                .iter()
+                .rev()
+                .take_while(|x| is_comment(x))
+                .count();
+        let block = |lines: &[&str]| lines.iter().map(|x| format!("{}\n", x)).collect();
+        Ok(Editor {
+            filename,
+            root,
+            modified: false,
+            header: block(&lines[..start]),
+            footer: block(&lines[end..]),
+            // A `#` inside a value, as in a color, is not a comment.
+            inner_comments: lines[start..end]
+                .iter()
+                .any(|x| x.trim_start().starts_with('#') || x.contains(" #")),
+        })
+    }
+
+    /// Runs the editor until the config is saved or the editor quit.
+    pub fn run(&mut self, builder: &Builder) -> Result<(), Error> {
+        // The indices of the options leading to the displayed menu.
+        let mut path: Vec<usize> = Vec::new();
+        loop {
+            let mut rows: Vec<String> = self.options(&path)?.iter().map(describe).collect();
+            let count = rows.len();
+            let entries = if path.is_empty() {
+                [ADD_COMMAND, ADD_SUBMENU, SAVE, QUIT].as_slice()
+            } else {
+                [ADD_COMMAND, ADD_SUBMENU, BACK].as_slice()
+            };
+            rows.extend(entries.iter().map(|x| tr(x)));
+
+            let prompt = match self.breadcrumb(&path)? {
+                breadcrumb if breadcrumb.is_empty() => tr("edit"),
+                breadcrumb => trf("edit {}", &[&breadcrumb]),
+            };
+            let selected = match choose(&prompt, &rows)? {
+                Some(index) if index < count => {
+                    self.edit_option(&mut path, index)?;
+                    continue;
+                }
+                Some(index) => entries[index - count],
+                None if path.is_empty() => QUIT,
+                None => BACK,
+            };
+            match selected {
+                ADD_COMMAND => self.add_command(&path)?,
+                ADD_SUBMENU => self.add_submenu(&path)?,
+                BACK => {
+                    path.pop();
+                }
+                SAVE => {
+                    if self.save(builder)? {
+                        return Ok(());
+                    }
+                }
+                _ => {
+                    if !self.modified || ask_confirmation(&tr("Discard the changes?"))? {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Offers the operations on the option at `index` of the menu at `path`.
+    fn edit_option(&mut self, path: &mut Vec<usize>, index: usize) -> Result<(), Error> {
+        let options = self.options(path)?;
+        let option = &options[index];
+        let action_type = option
+            .get("action")
+            .and_then(|x| x.get("type"))
+            .and_then(|x| x.as_str());
+
+        let mut edits = Vec::new();
+        if action_type == Some("RofiMenu") {
+            edits.push(OptionEdit::Open);
+        }
+        edits.push(OptionEdit::Rename);
+        if action_type == Some("RofiCommand") {
+            edits.push(OptionEdit::EditCommand);
+            edits.push(OptionEdit::EditArgs);
+        }
+        if index > 0 {
+            edits.push(OptionEdit::MoveUp);
+        }
+        if index + 1 < options.len() {
+            edits.push(OptionEdit::MoveDown);
+        }
+        edits.push(OptionEdit::Remove);
+
+        let label = option_string(option).to_string();
+        // Separators are renamed through their label.
+        let string_key = if option.get("separator").is_some() {
+            "separator"
+        } else {
+            "string"
+        };
+        // Strings given for several locales only have the text of the
+        // current one renamed.
+        let locale = match option.get(string_key) {
+            Some(Value::Mapping(texts)) => select(texts)
+                .and_then(|(x, _)| x.as_str())
+                .map(String::from),
+            _ => None,
+        };
+        let rows: Vec<String> = edits.iter().map(|x| tr(x.label())).collect();
+        let edit = match choose(&label, &rows)? {
+            Some(i) => edits[i],
+            None => return Ok(()),
+        };
+        match edit {
+            OptionEdit::Open => path.push(index),
+            OptionEdit::Rename => {
+                if let Some(string) = edit_text(&tr(string_key), &label)? {
+                    match &locale {
+                        Some(locale) => {
+                            self.set(path, index, &[string_key, locale], Value::from(string))?
+                        }
+                        None => self.set(path, index, &[string_key], Value::from(string))?,
+                    }
+                }
+            }
+            OptionEdit::EditCommand => {
+                let current = self.options(path)?[index]
+                    .get("action")
+                    .and_then(|x| x.get("command"))
+                    .and_then(|x| x.as_str())
+                    .unwrap_or("")
+                    .to_string();
+                if let Some(command) = edit_text(&tr("command"), &current)? {
+                    self.set(path, index, &["action", "command"], Value::from(command))?;
+                }
+            }
+            OptionEdit::EditArgs => {
+                let current = self.options(path)?[index]
+                    .get("action")
+                    .and_then(|x| x.get("args"))
+                    .and_then(|x| x.as_sequence())
+                    .map(|x| {
+                        x.iter()
+                            .filter_map(|x| x.as_str())
+                            .map(quote)
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    })
+                    .unwrap_or_default();
+                if let Some(args) = prompt_args(&current)? {
+                    let args = args.into_iter().map(Value::from).collect();
+                    self.set(path, index, &["action", "args"], Value::Sequence(args))?;
+                }
+            }
+            OptionEdit::MoveUp => {
+                self.options_mut(path)?.swap(index - 1, index);
+                self.modified = true;
+            }
+            OptionEdit::MoveDown => {
+                self.options_mut(path)?.swap(index, index + 1);
+                self.modified = true;
+            }
+            OptionEdit::Remove => {
+                if ask_confirmation(&trf("Remove {}?", &[&label]))? {
+                    self.options_mut(path)?.remove(index);
+                    self.modified = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks for a new command and appends it to the menu at `path`.
+    fn add_command(&mut self, path: &[usize]) -> Result<(), Error> {
+        let string = match prompt_text(&tr("string"))? {
+            Some(string) => string,
+            None => return Ok(()),
+        };
+        let command = match prompt_text(&tr("command"))? {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+        let args = prompt_args("")?.unwrap_or_default();
+
+        let mut action = Mapping::new();
+        action.insert(Value::from("type"), Value::from("RofiCommand"));
+        action.insert(Value::from("command"), Value::from(command));
+        action.insert(
+            Value::from("args"),
+            Value::Sequence(args.into_iter().map(Value::from).collect()),
+        );
+        self.options_mut(path)?
+            .push(new_option(string, Value::Mapping(action)));
+        self.modified = true;
+        Ok(())
+    }
+
+    /// Asks for the string of a new, empty submenu and appends it to the menu
+    /// at `path`.
+    fn add_submenu(&mut self, path: &[usize]) -> Result<(), Error> {
+        let string = match prompt_text(&tr("string"))? {
+            Some(string) => string,
+            None => return Ok(()),
+        };
+        let mut menu = Mapping::new();
+        menu.insert(Value::from("type"), Value::from("RofiMenu"));
+        menu.insert(Value::from("name"), Value::from(string.as_str()));
+        menu.insert(Value::from("prompt"), Value::from(string.as_str()));
+        menu.insert(Value::from("options"), Value::Sequence(Vec::new()));
+        self.options_mut(path)?
+            .push(new_option(string, Value::Mapping(menu)));
+        self.modified = true;
+        Ok(())
+    }
+
+    /// Checks the config and writes it back, returning whether it was
+    /// written.
+    fn save(&mut self, builder: &Builder) -> Result<bool, Error> {
+        if let Err(e) = builder.build_action(&self.root) {
+            if !ask_confirmation(&trf("The config is invalid ({}). Save anyway?", &[&e]))? {
+                return Ok(false);
+            }
+        }
+        if self.inner_comments
+            && !ask_confirmation(&tr(
+                "The comments within the config will be lost. Save anyway?",
+            ))?
+        {
+            return Ok(false);
+        }
+        self.write()?;
+        Ok(true)
+    }
+
+    /// Writes the config back, with the comments before and after it.
+    fn write(&mut self) -> Result<(), Error> {
+        let contents = serde_yaml::to_string(&self.root)
+            .map_err(|e| Error::Failed(trf("Could not serialize config: {}", &[&e])))?;
+        let contents = format!("{}{}\n{}", self.header, contents, self.footer);
+        // A symlinked config is written where it points, so that the link
+        // stays.
+        let target =
+            fs::canonicalize(self.filename).unwrap_or_else(|_| PathBuf::from(self.filename));
+        let written = write_atomically(&target, &contents);
+        written.map_err(|e| Error::io(trf("Could not write {}", &[&target.display()]), e))?;
+        self.modified = false;
+        Ok(())
+    }
+
+    /// Replaces the value under `keys` in the option at `index` of the menu
+    /// at `path`.
+    fn set(
+        &mut self,
+        path: &[usize],
+        index: usize,
+        keys: &[&str],
+        value: Value,
+    ) -> Result<(), Error> {
+        let mut node = &mut self.options_mut(path)?[index];
+        for key in keys {
+            // Existing keys are changed in place, so that they keep their
+            // position in the file.
+            node = node
+                .as_mapping_mut()
+                .ok_or_else(|| Error::Invalid(tr("Menu option is not a mapping")))?
+                .get_mut(&Value::from(*key))
+                .ok_or_else(|| Error::Invalid(trf("Menu option has no {}", &[&key])))?;
+        }
+        *node = value;
+        self.modified = true;
+        Ok(())
+    }
+
+    /// The options of the menu at `path`.
+    fn options(&self, path: &[usize]) -> Result<&Vec<Value>, Error> {
+        let mut menu = &self.root;
+        for index in path {
+            menu = menu
+                .get("options")
+                .and_then(|x| x.as_sequence())
+                .and_then(|x| x.get(*index))
+                .and_then(|x| x.get("action"))
+                .ok_or_else(|| Error::Invalid(tr("Invalid menu path")))?;
+        }
+        menu.get("options")
+            .and_then(|x| x.as_sequence())
+            .ok_or_else(|| Error::Invalid(tr("Options is not a sequence")))
+    }
+
+    /// The options of the menu at `path`, for changing them.
+    fn options_mut(&mut self, path: &[usize]) -> Result<&mut Vec<Value>, Error> {
+        let options = Value::from("options");
+        let action = Value::from("action");
+        let mut menu = &mut self.root;
+        for index in path {
+            menu = menu
+                .as_mapping_mut()
+                .and_then(|x| x.get_mut(&options))
+                .and_then(|x| x.as_sequence_mut())
+                .and_then(|x| x.get_mut(*index))
+                .and_then(|x| x.as_mapping_mut())
+                .and_then(|x| x.get_mut(&action))
+                .ok_or_else(|| Error::Invalid(tr("Invalid menu path")))?;
+        }
+        menu.as_mapping_mut()
+            .and_then(|x| x.get_mut(&options))
+            .and_then(|x| x.as_sequence_mut())
+            .ok_or_else(|| Error::Invalid(tr("Options is not a sequence")))
+    }
+
+    /// The option strings leading to the menu at `path`.
+    fn breadcrumb(&self, path: &[usize]) -> Result<String, Error> {
+        let mut strings = Vec::new();
+        for i in 0..path.len() {
+            strings.push(option_string(&self.options(&path[..i])?[path[i]]).to_string());
+        }
+        Ok(strings.join("/"))
+    }
+}
+
+/// Asks for the arguments of a command, starting from `current`, until
+/// their quotes are balanced.
+///
+/// Returns `None` if the prompt was dismissed.
+fn prompt_args(current: &str) -> Result<Option<Vec<String>>, Error> {
+    let mut current = String::from(current);
+    loop {
+        let typed = match edit_text(&tr("args"), &current)? {
+            Some(typed) => typed,
+            None => return Ok(None),
+        };
+        match split_args(&typed) {
+            Some(args) => return Ok(Some(args)),
+            // The mistake is shown, and then the arguments as they were
+            // typed, so that the edits in progress are kept.
+            None => {
+                show_message(&tr("Unbalanced quotes in arguments"))?;
+                current = typed;
+            }
+        }
+    }
+}
+
+/// Replaces the file at `path` by one holding `contents`, keeping its
+/// permissions.
+///
+/// The contents are written to a temporary file next to it first, so that an
+/// error does not leave a truncated file behind.
+fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+    fs::write(&tmp, contents)?;
+    let renamed = fs::metadata(path)
+        .and_then(|x| fs::set_permissions(&tmp, x.permissions()))
+        .or_else(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+    if renamed.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    renamed
+}
+
+/// Creates a menu option.
+fn new_option(string: String, action: Value) -> Value {
+    let mut option = Mapping::new();
+    option.insert(Value::from("string"), Value::from(string));
+    option.insert(Value::from("action"), action);
+    Value::Mapping(option)
+}
+
+/// The string displayed for `option`.
+fn option_string(option: &Value) -> &str {
+    option
+        .get("string")
+        .or_else(|| option.get("separator"))
+        .and_then(localize)
+        .unwrap_or("")
+}
+
+/// Describes `option` in the list of the options of its menu.
+fn describe(option: &Value) -> String {
+    let action = option.get("action");
+    let get_str = |key| action.and_then(|x| x.get(key)).and_then(|x| x.as_str());
+    let detail = match get_str("type") {
+        Some("RofiMenu") => tr("submenu"),
+        Some("RofiCommand") => {
+            let args = action
+                .and_then(|x| x.get("args"))
+                .and_then(|x| x.as_sequence())
+                .into_iter()
+                .flatten()
+                .filter_map(|x| x.as_str())
+                .map(quote);
+            std::iter::once(String::from(get_str("command").unwrap_or("")))
+                .chain(args)
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        Some(t) => String::from(t),
+        None if option.get("separator").is_some() => tr("separator"),
+        None => tr("no action"),
+    };
+    format!("{}  [{}]", option_string(option), detail)
+}
+
+/// Quotes `arg` for display, if it would not be read back as a single
+/// argument otherwise.
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return String::from(arg);
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Splits `args` at whitespace, except inside double quotes, with `\`
+/// escaping the next character.
+///
+/// Returns `None` if a quote is not closed.
+fn split_args(args: &str) -> Option<Vec<String>> {
+    let mut result = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => arg.get_or_insert_with(String::new).push(chars.next()?),
+            '"' => {
+                quoted = !quoted;
+                arg.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => result.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    result.extend(arg);
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DIRS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Creates an empty directory of its own for a test.
+    fn test_dir() -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "rmenu-edit-test-{}-{}",
+            process::id(),
+            DIRS.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    const CONFIG: &str = "# The main menu.\n\
+                          # Generated by hand.\n\
+                          \n\
+                          type: RofiMenu\n\
+                          name: main\n\
+                          prompt: main\n\
+                          options: []\n\
+                          # The end.\n";
+
+    #[test]
+    fn quote_is_read_back_by_split_args() {
+        let args = [
+            "plain",
+            "",
+            "two words",
+            "say \"hi\"",
+            "back\\slash",
+            "tab\there",
+        ];
+        let quoted: Vec<String> = args.iter().map(|x| quote(x)).collect();
+        assert_eq!(quoted[0], "plain");
+        assert_eq!(split_args(&quoted.join(" ")).unwrap(), args);
+    }
+
+    #[test]
+    fn split_args_joins_quoted_parts_and_rejects_open_quotes() {
+        assert_eq!(
+            split_args("  a \"b c\"d \\\"e  ").unwrap(),
+            vec!["a", "b cd", "\"e"]
+        );
+        assert!(split_args("").unwrap().is_empty());
+        assert_eq!(split_args("a \"b"), None);
+        assert_eq!(split_args("a\\"), None);
+    }
+
+    #[test]
+    fn new_keeps_the_comments_around_the_config() {
+        let dir = test_dir();
+        let path = dir.join("config.yaml");
+        fs::write(&path, CONFIG).unwrap();
+        let editor = Editor::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(editor.header, "# The main menu.\n# Generated by hand.\n\n");
+        assert_eq!(editor.footer, "# The end.\n");
+        assert!(!editor.inner_comments);
+
+        fs::write(&path, "type: RofiMenu # the root\ncolor: '#fff'\n").unwrap();
+        let editor = Editor::new(path.to_str().unwrap()).unwrap();
+        assert!(editor.header.is_empty() && editor.footer.is_empty());
+        assert!(editor.inner_comments);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_replaces_the_target_of_a_symlink() {
+        let dir = test_dir();
+        let target = dir.join("config.yaml");
+        let link = dir.join("link.yaml");
+        fs::write(&target, CONFIG).unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+        symlink(&target, &link).unwrap();
+
+        let mut editor = Editor::new(link.to_str().unwrap()).unwrap();
+        editor
+            .options_mut(&[])
+            .unwrap()
+            .push(new_option(String::from("Lock"), Value::from("lock")));
+        editor.write().unwrap();
+
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        let contents = fs::read_to_string(&target).unwrap();
+        assert!(contents.starts_with("# The main menu.\n# Generated by hand.\n\n"));
+        assert!(contents.ends_with("\n# The end.\n"));
+        let written: Value = serde_yaml::from_str(&contents).unwrap();
+        assert_eq!(written, editor.root);
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Only the config and its link are left.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_atomically_creates_missing_files() {
+        let dir = test_dir();
+        let path = dir.join("new.yaml");
+        write_atomically(&path, "a: 1\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a: 1\n");
+        assert!(write_atomically(&dir.join("missing/new.yaml"), "").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+use std::io::prelude::*;
+
+use std::env;
+use std::process::Command;
+
+use super::locale::{tr, trf};
+use super::secret::{Secret, SecretInput};
+use super::Error;
+
+/// The askpass helper, installed next to rmenu.
+const ASKPASS: &str = "rmenu-askpass";
+
+/// The environment variable telling the askpass helper how to ask for the
+/// password. Sudo runs the helper in the environment of the user, but resets
+/// it for the command, which never sees the variable.
+pub const ASKPASS_INPUT_VAR: &str = "RMENU_ASKPASS_INPUT";
+
+/// How a command is run as root.
+#[derive(Clone, Copy)]
+pub enum Elevate {
+    /// Polkit, which asks for the password with its own agent.
+    Pkexec,
+    /// Sudo, which asks for the password with the askpass helper.
+    Sudo { askpass: SecretInput },
+    /// Doas, which has no askpass and so must not need a password, as with
+    /// its `nopass` or `persist` options.
+    Doas,
+}
+
+impl Elevate {
+    /// Creates the elevation of the program `method`, where sudo asks for
+    /// the password with `askpass`.
+    pub fn new(method: &str, askpass: Option<SecretInput>) -> Result<Elevate, Error> {
+        let elevate = match method {
+            "pkexec" => Elevate::Pkexec,
+            "sudo" => Elevate::Sudo {
+                askpass: askpass.unwrap_or(SecretInput::Rofi),
+            },
+            "doas" => Elevate::Doas,
+            _ => return Err(Error::Invalid(trf("Unknown elevation {}", &[&method]))),
+        };
+        match elevate {
+            Elevate::Sudo { .. } => Ok(elevate),
+            _ if askpass.is_some() => Err(Error::Invalid(trf(
+                "askpass only applies to sudo, not {}",
+                &[&method],
+            ))),
+            _ => Ok(elevate),
+        }
+    }
+
+    /// Returns the program and arguments running `command` with `args` as
+    /// root.
+    ///
+    /// The environment of the command is reset by the programs, so none of
+    /// it is passed on.
+    pub fn wrap(&self, command: &str, args: &[String]) -> (String, Vec<String>) {
+        let (program, options): (&str, &[&str]) = match self {
+            Elevate::Pkexec => ("pkexec", &[]),
+            Elevate::Sudo { .. } => ("sudo", &["-A", "--"]),
+            // Fail rather than wait for a password on a terminal there is
+            // none of.
+            Elevate::Doas => ("doas", &["-n", "--"]),
+        };
+        let mut wrapped: Vec<String> = options.iter().map(|x| String::from(*x)).collect();
+        wrapped.push(String::from(command));
+        wrapped.extend(args.iter().cloned());
+        (String::from(program), wrapped)
+    }
+
+    /// Prepares `process`, which runs the program returned by `wrap`.
+    pub fn prepare(&self, process: &mut Command) -> Result<(), Error> {
+        if let Elevate::Sudo { askpass } = self {
+            let askpass_path = env::current_exe()
+                .map_err(|e| Error::io(tr("Could not find rmenu"), e))?
+                .with_file_name(ASKPASS);
+            let input = match askpass {
+                SecretInput::Rofi => "rofi",
+                SecretInput::Adir => "adir",
+            };
+            process
+                .env("SUDO_ASKPASS", askpass_path)
+                .env(ASKPASS_INPUT_VAR, input);
+        }
+        Ok(())
+    }
+}
+
+/// Asks for a password with the prompt given by sudo and prints it, which
+/// is what `rmenu-askpass` does.
+pub fn askpass(prompt: Option<&str>) -> Result<(), Error> {
+    let input = match env::var(ASKPASS_INPUT_VAR) {
+        Ok(input) => input.parse()?,
+        Err(_) => SecretInput::Rofi,
+    };
+    // Rofi adds its own separator after the prompt.
+    let prompt = prompt
+        .map(|x| x.trim_end().trim_end_matches(':'))
+        .filter(|x| !x.is_empty())
+        .unwrap_or("password");
+    let value = Secret::new(String::from(prompt), input)
+        .read()?
+        .ok_or_else(|| Error::Failed(tr("The password prompt was dismissed")))?;
+    let mut stdout = std::io::stdout();
+    stdout
+        .write_all(value.as_bytes())
+        .and_then(|_| stdout.write_all(b"\n"))
+        .and_then(|_| stdout.flush())
+        .map_err(|e| Error::io(tr("Failed to write the password"), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+    use std::path::Path;
+
+    /// Returns the program and arguments running `reboot now` with
+    /// `method`.
+    fn wrapped(method: &str) -> (String, Vec<String>) {
+        let args = [String::from("now")];
+        Elevate::new(method, None).unwrap().wrap("reboot", &args)
+    }
+
+    #[test]
+    fn wrap_runs_the_command_through_each_program() {
+        let (program, args) = wrapped("pkexec");
+        assert_eq!(program, "pkexec");
+        assert_eq!(args, ["reboot", "now"]);
+        let (program, args) = wrapped("sudo");
+        assert_eq!(program, "sudo");
+        assert_eq!(args, ["-A", "--", "reboot", "now"]);
+        let (program, args) = wrapped("doas");
+        assert_eq!(program, "doas");
+        assert_eq!(args, ["-n", "--", "reboot", "now"]);
+    }
+
+    #[test]
+    fn new_rejects_unknown_programs_and_misplaced_askpass() {
+        assert!(Elevate::new("su", None).is_err());
+        assert!(Elevate::new("doas", Some(SecretInput::Adir)).is_err());
+        assert!(Elevate::new("pkexec", Some(SecretInput::Rofi)).is_err());
+        assert!(matches!(
+            Elevate::new("sudo", Some(SecretInput::Adir)),
+            Ok(Elevate::Sudo {
+                askpass: SecretInput::Adir
+            })
+        ));
+    }
+
+    #[test]
+    fn prepare_points_sudo_to_the_askpass_helper() {
+        let env_of = |elevate: Elevate| {
+            let mut process = Command::new("true");
+            elevate.prepare(&mut process).unwrap();
+            process
+                .get_envs()
+                .map(|(key, value)| (key.to_os_string(), value.map(OsStr::to_os_string)))
+                .collect::<Vec<_>>()
+        };
+        let env = env_of(Elevate::new("sudo", Some(SecretInput::Adir)).unwrap());
+        let askpass = env
+            .iter()
+            .find(|(key, _)| key == "SUDO_ASKPASS")
+            .and_then(|(_, value)| value.clone())
+            .unwrap();
+        assert_eq!(Path::new(&askpass).file_name().unwrap(), ASKPASS);
+        assert!(env
+            .iter()
+            .any(|(key, value)| key == ASKPASS_INPUT_VAR
+                && value.as_deref() == Some(OsStr::new("adir"))));
+        assert!(env_of(Elevate::Pkexec).is_empty());
+        assert!(env_of(Elevate::Doas).is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use super::locale::{tr, trf};
+
+/// The exit code of rmenu when it fails for another reason than the ones
+/// below.
+pub const EXIT_FAILURE: i32 = 1;
+/// The exit code of rmenu when the config describes an invalid menu, or a
+/// message it reads is not what was expected.
+pub const EXIT_INVALID: i32 = 2;
+/// The exit code of rmenu when the config file can not be read.
+pub const EXIT_CONFIG: i32 = 3;
+/// The exit code of rmenu when the config file is not valid YAML.
+pub const EXIT_PARSE: i32 = 4;
+/// The exit code of rmenu when a program, such as rofi, can not be started.
+pub const EXIT_SPAWN: i32 = 5;
+/// The exit code of rmenu when a command it ran failed.
+pub const EXIT_COMMAND_FAILED: i32 = 6;
+/// The exit code of rmenu when a command timed out, as used by timeout(1).
+pub const EXIT_TIMED_OUT: i32 = 124;
+
+/// An error of rmenu.
+///
+/// Each kind of error exits rmenu with its own code, so that scripts can
+/// tell them apart. A cancelled menu is not a failure and exits with 0.
+///
+/// The messages and contexts held by errors are translated with `tr` or
+/// `trf` when they are made, so that their templates are looked up before
+/// the varying texts are filled in.
+#[derive(Debug)]
+pub enum Error {
+    /// The config file could not be read.
+    Config { path: PathBuf, source: io::Error },
+    /// The config file is not valid YAML, with the location of the error
+    /// in `source`.
+    Parse {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    /// The config describes an invalid menu, or a message, such as a reply of
+    /// i3 or of a plugin, is not what was expected.
+    Invalid(String),
+    /// A program could not be started.
+    Spawn { program: String, source: io::Error },
+    /// A menu or prompt was dismissed, or a confirmation declined.
+    Cancelled,
+    /// A command exited unsuccessfully.
+    CommandFailed { command: String, status: ExitStatus },
+    /// A command ran for longer than its timeout.
+    TimedOut { command: String, timeout: Duration },
+    /// Reading or writing a file, socket or pipe failed.
+    Io { context: String, source: io::Error },
+    /// A message, such as a reply of i3 or of a plugin, is not valid JSON.
+    Json {
+        context: String,
+        source: serde_json::Error,
+    },
+    /// An error known only by its message and exit code, such as one
+    /// reported by the daemon or one summing up several others.
+    Reported { exit_code: i32, message: String },
+    /// Any other failure.
+    Failed(String),
+}
+
+impl Error {
+    /// Returns the exit code of rmenu for the error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config { .. } => EXIT_CONFIG,
+            Error::Parse { .. } => EXIT_PARSE,
+            Error::Invalid(_) => EXIT_INVALID,
+            Error::Spawn { .. } => EXIT_SPAWN,
+            Error::Cancelled => 0,
+            Error::CommandFailed { .. } => EXIT_COMMAND_FAILED,
+            Error::TimedOut { .. } => EXIT_TIMED_OUT,
+            Error::Io { .. } | Error::Json { .. } => EXIT_FAILURE,
+            Error::Reported { exit_code, .. } => *exit_code,
+            Error::Failed(_) => EXIT_FAILURE,
+        }
+    }
+
+    /// Turns a failure while building a menu into a validation error,
+    /// keeping the more specific kinds as they are.
+    pub fn into_invalid(self) -> Error {
+        match self {
+            Error::Failed(message) => Error::Invalid(message),
+            e => e,
+        }
+    }
+
+    /// Creates the error of `program` failing to start.
+    pub fn spawn(program: &str, source: io::Error) -> Error {
+        Error::Spawn {
+            program: String::from(program),
+            source,
+        }
+    }
+
+    /// Creates the error of an input or output operation, described by
+    /// `context`, failing with `source`.
+    pub fn io(context: impl Into<String>, source: io::Error) -> Error {
+        Error::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Creates the error of the message described by `context` not being
+    /// valid JSON.
+    pub fn json(context: impl Into<String>, source: serde_json::Error) -> Error {
+        Error::Json {
+            context: context.into(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The messages of the other kinds are translated as they are made.
+        let message = match self {
+            Error::Config { path, source } => trf(
+                "Could not read config file {}: {}",
+                &[&path.display(), source],
+            ),
+            // The YAML errors tell where they are.
+            Error::Parse { path, source } => trf(
+                "Could not parse config file {}: {}",
+                &[&path.display(), source],
+            ),
+            Error::Invalid(message) | Error::Failed(message) => return f.write_str(message),
+            Error::Spawn { program, source } => trf("Failed to run {}: {}", &[program, source]),
+            Error::Cancelled => tr("Cancelled"),
+            Error::CommandFailed { command, status } => {
+                trf("Command {} failed: {}", &[command, status])
+            }
+            Error::TimedOut { command, timeout } => trf(
+                "Command {} timed out after {} seconds",
+                &[command, &timeout.as_secs_f64()],
+            ),
+            Error::Io { context, source } => return write!(f, "{}: {}", context, source),
+            Error::Json { context, source } => return write!(f, "{}: {}", context, source),
+            Error::Reported { message, .. } => return f.write_str(message),
+        };
+        f.write_str(&message)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Config { source, .. }
+            | Error::Spawn { source, .. }
+            | Error::Io { source, .. } => Some(source),
+            Error::Parse { source, .. } => Some(source),
+            Error::Json { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
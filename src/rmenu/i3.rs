@@ -0,0 +1,413 @@
+use std::io::prelude::*;
+
+use std::env;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::locale::{tr, trf};
+use super::{prompt_text, Error, RofiAction};
+
+pub mod keybindings;
+pub mod windows;
+pub mod workspaces;
+
+/// The magic string starting every i3 IPC message.
+const MAGIC: &[u8] = b"i3-ipc";
+
+/// The length of an i3 IPC message header: the magic string, then the payload
+/// length and the message type as native-endian 32-bit integers.
+const HEADER_LEN: usize = 14;
+
+/// Runs the payload as i3 commands.
+pub const RUN_COMMAND: u32 = 0;
+
+/// Returns the list of workspaces.
+pub const GET_WORKSPACES: u32 = 1;
+
+/// Subscribes the connection to events.
+pub const SUBSCRIBE: u32 = 2;
+
+/// Returns the list of outputs.
+pub const GET_OUTPUTS: u32 = 3;
+
+/// Returns the layout tree.
+pub const GET_TREE: u32 = 4;
+
+/// Returns the config of the running window manager.
+pub const GET_CONFIG: u32 = 9;
+
+/// Returns the path of the IPC socket of the running i3 or sway.
+///
+/// `$I3SOCK` and `$SWAYSOCK` are used if set, otherwise the window manager
+/// itself is asked.
+pub fn socket_path() -> Result<PathBuf, Error> {
+    for var in &["I3SOCK", "SWAYSOCK"] {
+        match env::var_os(var) {
+            Some(path) if !path.is_empty() => return Ok(PathBuf::from(path)),
+            _ => (),
+        }
+    }
+    for wm in &["i3", "sway"] {
+        let output = match Command::new(wm).arg("--get-socketpath").output() {
+            Ok(output) if output.status.success() => output,
+            _ => continue,
+        };
+        let path = String::from_utf8_lossy(&output.stdout);
+        if !path.trim().is_empty() {
+            return Ok(PathBuf::from(path.trim()));
+        }
+    }
+    Err(Error::io(
+        tr("Could not find the i3 IPC socket"),
+        std::io::ErrorKind::NotFound.into(),
+    ))
+}
+
+/// Quotes `s` as a string argument of an i3 command.
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Returns the shell commands started by the `exec` commands among
+/// `commands`, as they would be passed to `i3-msg`.
+pub fn exec_commands(commands: &str) -> Vec<String> {
+    split_commands(commands)
+        .into_iter()
+        .filter_map(|command| {
+            // Skip the criteria, which may quote any character.
+            let command = command.trim_start();
+            let command = match command.strip_prefix('[') {
+                Some(rest) => &rest[end_of_criteria(rest)..],
+                None => command,
+            };
+            let rest = command.trim_start().strip_prefix("exec")?;
+            if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                return None;
+            }
+            let rest = rest.trim_start();
+            let rest = rest.strip_prefix("--no-startup-id").unwrap_or(rest).trim();
+            match rest.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+                Some(quoted) => Some(quoted.replace("\\\"", "\"").replace("\\\\", "\\")),
+                None => Some(String::from(rest)),
+            }
+        })
+        .collect()
+}
+
+/// Splits `commands` at the `;` and `,` separating them, outside of quotes
+/// and criteria.
+fn split_commands(commands: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut criteria = false;
+    for (i, c) in commands.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '[' if !quoted => criteria = true,
+            ']' if !quoted => criteria = false,
+            ';' | ',' if !quoted && !criteria => {
+                split.push(&commands[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    split.push(&commands[start..]);
+    split
+}
+
+/// Returns the index just after the `]` closing the criteria that start
+/// `s`, or the length of `s` if they are not closed.
+fn end_of_criteria(s: &str) -> usize {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ']' if !quoted => return i + 1,
+            _ => (),
+        }
+    }
+    s.len()
+}
+
+/// A connection to the IPC socket of i3, or of sway.
+pub struct I3Ipc {
+    stream: UnixStream,
+}
+
+impl I3Ipc {
+    /// Connects to the socket of the running window manager.
+    pub fn connect() -> Result<I3Ipc, Error> {
+        I3Ipc::connect_to(&socket_path()?)
+    }
+
+    /// Connects to the socket at `path`.
+    pub fn connect_to(path: &Path) -> Result<I3Ipc, Error> {
+        let stream = UnixStream::connect(path)
+            .map_err(|e| Error::io(trf("Could not connect to i3 at {}", &[&path.display()]), e))?;
+        Ok(I3Ipc { stream })
+    }
+
+    /// Sends a message of type `msg_type` and returns the reply payload.
+    pub fn request(&mut self, msg_type: u32, payload: &str) -> Result<serde_json::Value, Error> {
+        let mut message = Vec::with_capacity(HEADER_LEN + payload.len());
+        message.extend_from_slice(MAGIC);
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&msg_type.to_ne_bytes());
+        message.extend_from_slice(payload.as_bytes());
+        self.stream
+            .write_all(&message)
+            .map_err(|e| Error::io(tr("Failed to send i3 IPC message"), e))?;
+
+        let (reply_type, reply) = self.read_message()?;
+        if reply_type != msg_type {
+            return Err(Error::Failed(trf(
+                "i3 replied with message type {}, expected {}",
+                &[&reply_type, &msg_type],
+            )));
+        }
+        serde_json::from_slice(&reply).map_err(|e| Error::json(tr("Invalid i3 IPC reply"), e))
+    }
+
+    /// Reads a single message and returns its type and payload.
+    pub fn read_message(&mut self) -> Result<(u32, Vec<u8>), Error> {
+        let mut header = [0u8; HEADER_LEN];
+        self.stream
+            .read_exact(&mut header)
+            .map_err(|e| Error::io(tr("Failed to read i3 IPC reply"), e))?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(Error::Invalid(tr("Invalid i3 IPC reply")));
+        }
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&header[6..10]);
+        let mut msg_type = [0u8; 4];
+        msg_type.copy_from_slice(&header[10..14]);
+
+        let mut payload = vec![0u8; u32::from_ne_bytes(len) as usize];
+        self.stream
+            .read_exact(&mut payload)
+            .map_err(|e| Error::io(tr("Failed to read i3 IPC reply"), e))?;
+        Ok((u32::from_ne_bytes(msg_type), payload))
+    }
+
+    /// Runs `command` and checks that every command in it succeeded.
+    pub fn run_command(&mut self, command: &str) -> Result<(), Error> {
+        let reply = self.request(RUN_COMMAND, command)?;
+        let results = reply
+            .as_array()
+            .ok_or_else(|| Error::Invalid(tr("Invalid i3 IPC reply")))?;
+        for result in results {
+            if result.get("success").and_then(|x| x.as_bool()) != Some(true) {
+                let error = result
+                    .get("error")
+                    .and_then(|x| x.as_str())
+                    .unwrap_or("unknown error");
+                return Err(Error::Failed(trf(
+                    "i3 command `{}` failed: {}",
+                    &[&command, &error],
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A struct for running i3 commands through the IPC socket.
+pub struct I3Command {
+    /// The commands to be run, as they would be passed to `i3-msg`.
+    command: String,
+}
+
+impl I3Command {
+    pub fn new(command: String) -> I3Command {
+        I3Command { command }
+    }
+}
+
+impl RofiAction for I3Command {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        I3Ipc::connect()?.run_command(&self.command)?;
+        Ok(None)
+    }
+}
+
+/// A struct for running an i3 command built from text typed in rofi.
+pub struct I3PromptCommand {
+    /// The prompt displayed while asking for the text.
+    prompt: String,
+    /// Builds the command to be run from the quoted text.
+    command: Box<dyn Fn(&str) -> String>,
+}
+
+impl I3PromptCommand {
+    pub fn new(prompt: String, command: Box<dyn Fn(&str) -> String>) -> I3PromptCommand {
+        I3PromptCommand { prompt, command }
+    }
+}
+
+impl RofiAction for I3PromptCommand {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        let text = prompt_text(&self.prompt)?.ok_or(Error::Cancelled)?;
+        I3Ipc::connect()?.run_command(&(self.command)(&quote(&text)))?;
+        Ok(None)
+    }
+
+    fn needs_input(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::{self, JoinHandle};
+
+    /// Numbers the directories of the fake servers of a test run.
+    static SERVERS: AtomicUsize = AtomicUsize::new(0);
+
+    /// A fake i3 listening on a socket in a temporary directory, which
+    /// answers the messages it receives with its replies, in order.
+    pub struct FakeI3 {
+        dir: PathBuf,
+        socket: PathBuf,
+        server: Option<JoinHandle<Vec<(u32, String)>>>,
+    }
+
+    impl FakeI3 {
+        /// Starts a server sending `replies`, as message types and payloads.
+        pub fn start(replies: Vec<(u32, &str)>) -> FakeI3 {
+            let dir = env::temp_dir().join(format!(
+                "rmenu-test-{}-{}",
+                process::id(),
+                SERVERS.fetch_add(1, Ordering::SeqCst)
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            let socket = dir.join("ipc.sock");
+            let listener = UnixListener::bind(&socket).unwrap();
+            let replies: Vec<(u32, String)> = replies
+                .into_iter()
+                .map(|(msg_type, payload)| (msg_type, String::from(payload)))
+                .collect();
+            let server = thread::spawn(move || serve(listener, replies));
+            FakeI3 {
+                dir,
+                socket,
+                server: Some(server),
+            }
+        }
+
+        /// Connects to the server.
+        pub fn connect(&self) -> I3Ipc {
+            I3Ipc::connect_to(&self.socket).unwrap()
+        }
+
+        /// Waits for every reply to be sent and returns the messages
+        /// received, as message types and payloads.
+        pub fn received(mut self) -> Vec<(u32, String)> {
+            self.server.take().unwrap().join().unwrap()
+        }
+    }
+
+    impl Drop for FakeI3 {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Answers the messages of the clients of `listener` with `replies`,
+    /// framing them by hand rather than through `I3Ipc`.
+    fn serve(listener: UnixListener, replies: Vec<(u32, String)>) -> Vec<(u32, String)> {
+        let mut received = Vec::new();
+        let mut replies = replies.into_iter().peekable();
+        while replies.peek().is_some() {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8; 14];
+            // Once a client leaves, the next one is answered.
+            while replies.peek().is_some() && stream.read_exact(&mut header).is_ok() {
+                assert_eq!(&header[..6], b"i3-ipc");
+                let len = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]);
+                let msg_type = u32::from_ne_bytes([header[10], header[11], header[12], header[13]]);
+                let mut payload = vec![0u8; len as usize];
+                stream.read_exact(&mut payload).unwrap();
+                received.push((msg_type, String::from_utf8(payload).unwrap()));
+
+                let (reply_type, reply) = replies.next().unwrap();
+                let mut message = b"i3-ipc".to_vec();
+                message.extend_from_slice(&(reply.len() as u32).to_ne_bytes());
+                message.extend_from_slice(&reply_type.to_ne_bytes());
+                message.extend_from_slice(reply.as_bytes());
+                stream.write_all(&message).unwrap();
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn request_frames_the_message_and_reads_the_reply() {
+        let server = FakeI3::start(vec![(GET_TREE, "{\"id\": 1}"), (GET_TREE, "[]")]);
+        let mut ipc = server.connect();
+        let reply = ipc.request(GET_TREE, "payload").unwrap();
+        assert_eq!(reply, serde_json::json!({"id": 1}));
+        // An empty payload is framed with a zero length.
+        assert_eq!(ipc.request(GET_TREE, "").unwrap(), serde_json::json!([]));
+        assert_eq!(
+            server.received(),
+            vec![
+                (GET_TREE, String::from("payload")),
+                (GET_TREE, String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn read_message_returns_events() {
+        let server = FakeI3::start(vec![(0x8000_0003, "{\"change\": \"focus\"}")]);
+        let mut ipc = server.connect();
+        // The server only replies once it has received a message.
+        ipc.stream.write_all(b"i3-ipc\0\0\0\0\0\0\0\0").unwrap();
+        let (msg_type, payload) = ipc.read_message().unwrap();
+        assert_eq!(msg_type, 0x8000_0003);
+        assert_eq!(payload, b"{\"change\": \"focus\"}");
+    }
+
+    #[test]
+    fn request_rejects_a_reply_of_another_type() {
+        let server = FakeI3::start(vec![(GET_WORKSPACES, "[]")]);
+        let error = server.connect().request(RUN_COMMAND, "nop").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "i3 replied with message type 1, expected 0"
+        );
+    }
+
+    #[test]
+    fn run_command_reports_the_failed_command() {
+        let server = FakeI3::start(vec![
+            (RUN_COMMAND, "[{\"success\": true}]"),
+            (
+                RUN_COMMAND,
+                "[{\"success\": true}, {\"success\": false, \"error\": \"No such mark\"}]",
+            ),
+        ]);
+        let mut ipc = server.connect();
+        ipc.run_command("focus left").unwrap();
+        let error = ipc.run_command("nop; [con_mark=x] focus").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "i3 command `nop; [con_mark=x] focus` failed: No such mark"
+        );
+    }
+}
@@ -0,0 +1,464 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{CStr, CString, OsStr};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+
+use super::{I3Ipc, GET_CONFIG};
+use crate::rmenu::locale::{tr, trf};
+use crate::rmenu::Error;
+
+/// The name of the mode i3 starts in.
+const DEFAULT_MODE: &str = "default";
+
+/// How deeply `include` directives may be nested.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// A key binding found in an i3 config.
+pub struct Binding {
+    /// The key combination, with variables substituted.
+    pub key: String,
+    /// The i3 command run by the binding.
+    pub command: String,
+}
+
+/// The key bindings of a binding mode.
+pub struct BindingMode {
+    /// The name of the mode.
+    pub name: String,
+    /// The bindings of the mode, in config order.
+    pub bindings: Vec<Binding>,
+}
+
+/// A block of an i3 config, opened by a line ending with `{`.
+enum Block {
+    /// The block of the binding mode at this index.
+    Mode(usize),
+    /// Any other block, such as `bar`, whose bindings are not key bindings.
+    Other,
+}
+
+/// A parser collecting the key bindings of an i3 config.
+pub struct ConfigParser {
+    /// The variables defined with `set`.
+    vars: Vec<(String, String)>,
+    /// The binding modes found so far, the default mode first.
+    modes: Vec<BindingMode>,
+    /// The contents of included files, as returned by i3.
+    included: HashMap<PathBuf, String>,
+}
+
+impl Default for ConfigParser {
+    fn default() -> ConfigParser {
+        ConfigParser::new()
+    }
+}
+
+impl ConfigParser {
+    pub fn new() -> ConfigParser {
+        ConfigParser {
+            vars: Vec::new(),
+            modes: vec![BindingMode {
+                name: String::from(DEFAULT_MODE),
+                bindings: Vec::new(),
+            }],
+            included: HashMap::new(),
+        }
+    }
+
+    /// Parses the config of the running i3, as returned by `GET_CONFIG`.
+    pub fn parse_running(mut self) -> Result<Vec<BindingMode>, Error> {
+        let reply = I3Ipc::connect()?.request(GET_CONFIG, "")?;
+        let config = reply
+            .get("config")
+            .and_then(|x| x.as_str())
+            .ok_or_else(|| Error::Invalid(tr("Invalid i3 IPC reply")))?;
+
+        // Since i3 4.20 the reply also holds every config file read, the main
+        // one first, which lets includes be resolved as i3 saw them.
+        let included = reply.get("included_configs").and_then(|x| x.as_array());
+        for file in included.into_iter().flatten() {
+            let path = file.get("path").and_then(|x| x.as_str());
+            let contents = file.get("raw_contents").and_then(|x| x.as_str());
+            if let (Some(path), Some(contents)) = (path, contents) {
+                self.included
+                    .insert(PathBuf::from(path), String::from(contents));
+            }
+        }
+        let main_path = included
+            .and_then(|x| x.first())
+            .and_then(|x| x.get("path"))
+            .and_then(|x| x.as_str())
+            .map(PathBuf::from)
+            .unwrap_or_else(default_config_path);
+
+        self.parse(config, &main_path, 0)?;
+        Ok(self.modes)
+    }
+
+    /// Parses the config file at `path`.
+    pub fn parse_file(mut self, path: &Path) -> Result<Vec<BindingMode>, Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::io(trf("Could not read {}", &[&path.display()]), e))?;
+        self.parse(&contents, path, 0)?;
+        Ok(self.modes)
+    }
+
+    /// Parses `contents`, read from the file at `path`.
+    fn parse(&mut self, contents: &str, path: &Path, depth: usize) -> Result<(), Error> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(Error::Invalid(trf(
+                "Includes nested too deeply in {}",
+                &[&path.display()],
+            )));
+        }
+        let lines = join_lines(contents);
+
+        // i3 defines the variables of a file before reading the rest of it,
+        // so they can be used before their definition.
+        for line in &lines {
+            let (word, rest) = split_word(line);
+            let (name, value) = split_word(rest);
+            match word {
+                "set" => {
+                    let value = self.substitute(value);
+                    self.set(name, &value);
+                }
+                "set_from_resource" => {
+                    // Resources are not read, so use the fallback value.
+                    let value = self.substitute(split_word(value).1);
+                    self.set(name, &value);
+                }
+                _ => (),
+            }
+        }
+
+        let mut blocks = Vec::new();
+        for line in &lines {
+            let line = self.substitute(line);
+            let (word, rest) = split_word(&line);
+            match word {
+                "include" => {
+                    for include in expand_include(rest, path)? {
+                        let contents = match self.included.get(&include) {
+                            Some(contents) => contents.clone(),
+                            None => fs::read_to_string(&include).map_err(|e| {
+                                Error::io(trf("Could not read {}", &[&include.display()]), e)
+                            })?,
+                        };
+                        self.parse(&contents, &include, depth + 1)?;
+                    }
+                }
+                "mode" if rest.ends_with('{') => {
+                    let name = rest[..rest.len() - 1]
+                        .split_whitespace()
+                        .filter(|x| !x.starts_with("--"))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    blocks.push(Block::Mode(self.mode(unquote(&name))));
+                }
+                "}" => {
+                    blocks.pop();
+                }
+                "bindsym" | "bindcode" => {
+                    let mode = match blocks.last() {
+                        None => 0,
+                        Some(Block::Mode(mode)) => *mode,
+                        Some(Block::Other) => continue,
+                    };
+                    let mut rest = rest;
+                    let mut release = false;
+                    loop {
+                        let (option, tail) = split_word(rest);
+                        if !option.starts_with("--") {
+                            break;
+                        }
+                        release |= option == "--release";
+                        rest = tail;
+                    }
+                    let (key, command) = split_word(rest);
+                    if key.is_empty() || command.is_empty() {
+                        return Err(Error::Invalid(trf(
+                            "Invalid binding in {}: {}",
+                            &[&path.display(), &line],
+                        )));
+                    }
+                    let key = if release {
+                        format!("{} (release)", key)
+                    } else {
+                        String::from(key)
+                    };
+                    self.modes[mode].bindings.push(Binding {
+                        key,
+                        command: String::from(command),
+                    });
+                }
+                _ if line.ends_with('{') => blocks.push(Block::Other),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Defines the variable `name`.
+    fn set(&mut self, name: &str, value: &str) {
+        self.vars.retain(|(x, _)| x != name);
+        self.vars.push((String::from(name), String::from(value)));
+        // Longer names are substituted first, so that `$mod` does not
+        // replace the start of `$mod_alt`.
+        self.vars.sort_by_key(|x| Reverse(x.0.len()));
+    }
+
+    /// Replaces the variables in `line` by their values.
+    fn substitute(&self, line: &str) -> String {
+        let mut line = String::from(line);
+        for (name, value) in &self.vars {
+            line = line.replace(name.as_str(), value);
+        }
+        line
+    }
+
+    /// Returns the index of the mode `name`, adding it if it is new.
+    fn mode(&mut self, name: &str) -> usize {
+        match self.modes.iter().position(|x| x.name == name) {
+            Some(index) => index,
+            None => {
+                self.modes.push(BindingMode {
+                    name: String::from(name),
+                    bindings: Vec::new(),
+                });
+                self.modes.len() - 1
+            }
+        }
+    }
+}
+
+/// Describes the bindings of `modes` as a menu, in the config format read by
+/// `Builder`.
+///
+/// The bindings of the default mode are listed first, followed by a submenu
+/// for each other mode.
+pub fn to_yaml(modes: &[BindingMode]) -> Value {
+    let mut options = Vec::new();
+    for mode in modes {
+        if mode.bindings.is_empty() {
+            continue;
+        }
+        let bindings = mode.bindings.iter().map(|binding| {
+            let mut action = Mapping::new();
+            action.insert(Value::from("type"), Value::from("I3Command"));
+            action.insert(
+                Value::from("command"),
+                Value::from(binding.command.as_str()),
+            );
+            option(
+                format!("{}  {}", binding.key, binding.command),
+                Value::Mapping(action),
+            )
+        });
+        if mode.name == DEFAULT_MODE {
+            options.extend(bindings);
+        } else {
+            let submenu = menu(
+                &mode.name,
+                &format!("mode {}", mode.name),
+                bindings.collect(),
+            );
+            options.push(option(format!("mode: {}", mode.name), submenu));
+        }
+    }
+    menu("keybindings", "keybinding", options)
+}
+
+/// Describes a `RofiMenu`.
+fn menu(name: &str, prompt: &str, options: Vec<Value>) -> Value {
+    let mut menu = Mapping::new();
+    menu.insert(Value::from("type"), Value::from("RofiMenu"));
+    menu.insert(Value::from("name"), Value::from(name));
+    menu.insert(Value::from("prompt"), Value::from(prompt));
+    menu.insert(Value::from("options"), Value::Sequence(options));
+    Value::Mapping(menu)
+}
+
+/// Describes a menu option.
+fn option(string: String, action: Value) -> Value {
+    let mut option = Mapping::new();
+    option.insert(Value::from("string"), Value::from(string));
+    option.insert(Value::from("action"), action);
+    Value::Mapping(option)
+}
+
+/// Returns the path i3 reads its config from by default.
+fn default_config_path() -> PathBuf {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|x| Path::new(&x).join(".config")))
+        .unwrap_or_default();
+    config_home.join("i3").join("config")
+}
+
+/// Splits `contents` into lines, joining those continued with a trailing
+/// backslash and dropping comments and blank lines.
+fn join_lines(contents: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for line in contents.lines() {
+        if let Some(line) = line.strip_suffix('\\') {
+            current.push_str(line);
+            continue;
+        }
+        current.push_str(line);
+        let line = current.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            lines.push(String::from(line));
+        }
+        current.clear();
+    }
+    lines
+}
+
+/// Splits the first word off `line`, returning it and the trimmed rest.
+fn split_word(line: &str) -> (&str, &str) {
+    let line = line.trim_start();
+    match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    }
+}
+
+/// Removes the double quotes around `s`, if any.
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|x| x.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+/// Returns the files matched by the pattern of an `include` directive in the
+/// file at `path`.
+fn expand_include(pattern: &str, path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let pattern = unquote(pattern);
+    let pattern = match pattern.strip_prefix("~/") {
+        Some(rest) => Path::new(&env::var_os("HOME").unwrap_or_default()).join(rest),
+        None => PathBuf::from(pattern),
+    };
+    // Relative paths are relative to the directory of the including file.
+    let pattern = path.parent().unwrap_or_else(|| Path::new("")).join(pattern);
+    let pattern = CString::new(pattern.as_os_str().as_bytes())
+        .map_err(|_| Error::Invalid(trf("Invalid include pattern {}", &[&pattern.display()])))?;
+
+    let mut paths = Vec::new();
+    unsafe {
+        let mut glob: libc::glob_t = std::mem::zeroed();
+        if libc::glob(pattern.as_ptr(), 0, None, &mut glob) == 0 {
+            for i in 0..glob.gl_pathc {
+                let path = CStr::from_ptr(*glob.gl_pathv.add(i));
+                paths.push(PathBuf::from(OsStr::from_bytes(path.to_bytes())));
+            }
+        }
+        libc::globfree(&mut glob);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `contents` and returns the bindings of each mode, as keys and
+    /// commands.
+    fn parse(contents: &str) -> Vec<(String, Vec<(String, String)>)> {
+        let mut parser = ConfigParser::new();
+        parser
+            .parse(contents, Path::new("/nonexistent/config"), 0)
+            .unwrap();
+        parser
+            .modes
+            .into_iter()
+            .map(|mode| {
+                let bindings = mode
+                    .bindings
+                    .into_iter()
+                    .map(|x| (x.key, x.command))
+                    .collect();
+                (mode.name, bindings)
+            })
+            .collect()
+    }
+
+    /// Returns the owned form of `bindings`.
+    fn bindings(bindings: &[(&str, &str)]) -> Vec<(String, String)> {
+        bindings
+            .iter()
+            .map(|(key, command)| (String::from(*key), String::from(*command)))
+            .collect()
+    }
+
+    #[test]
+    fn parse_substitutes_variables_and_joins_lines() {
+        let modes = parse(
+            "bindsym $mod+Return exec $term\n\
+             # bindsym $mod+x nop\n\
+             set $mod Mod4\n\
+             set $term i3-sensible-terminal\n\
+             bindsym --release $mod+q \\\n    kill\n\
+             bindcode --border 24 nop\n",
+        );
+        assert_eq!(
+            modes,
+            vec![(
+                String::from("default"),
+                bindings(&[
+                    ("Mod4+Return", "exec i3-sensible-terminal"),
+                    ("Mod4+q (release)", "kill"),
+                    ("24", "nop"),
+                ])
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_tracks_nested_blocks() {
+        let modes = parse(
+            "mode --pango_markup \"resize\" {\n\
+                 bindsym h resize shrink width\n\
+                 nested {\n\
+                 }\n\
+                 bindsym Escape mode \"default\"\n\
+             }\n\
+             bindsym Mod4+r mode \"resize\"\n\
+             bar {\n\
+                 bindsym button4 nop\n\
+                 colors {\n\
+                     background #000000\n\
+                 }\n\
+                 bindsym button5 nop\n\
+             }\n\
+             bindsym Mod4+Return exec term\n",
+        );
+        assert_eq!(
+            modes,
+            vec![
+                (
+                    String::from("default"),
+                    bindings(&[("Mod4+r", "mode \"resize\""), ("Mod4+Return", "exec term"),])
+                ),
+                (
+                    String::from("resize"),
+                    bindings(&[("h", "resize shrink width"), ("Escape", "mode \"default\""),])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_bindings_without_a_command() {
+        let mut parser = ConfigParser::new();
+        assert!(parser
+            .parse("bindsym Mod4+x\n", Path::new("/nonexistent/config"), 0)
+            .is_err());
+    }
+}
@@ -0,0 +1,464 @@
+use std::io::prelude::*;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use linked_hash_map::LinkedHashMap;
+
+use super::{quote, I3Command, I3Ipc, GET_TREE, SUBSCRIBE};
+use crate::rmenu::appearance::Appearance;
+use crate::rmenu::locale::{tr, trf};
+use crate::rmenu::{insert_unique, run_chain, Error, RofiAction, RofiMenu};
+
+/// The type of window events, with the bit marking event messages set.
+const WINDOW_EVENT: u32 = 0x8000_0003;
+
+/// The name of the focus history file, inside `$XDG_RUNTIME_DIR`.
+const HISTORY_NAME: &str = "rmenu-focus-history";
+
+/// How many windows the focus history remembers.
+const HISTORY_LEN: usize = 256;
+
+/// An operation performed on the selected window.
+#[derive(Clone, Copy, PartialEq)]
+pub enum WindowAction {
+    /// Focuses the window.
+    Focus,
+    /// Moves the window to the current workspace and focuses it.
+    Move,
+    /// Closes the window.
+    Kill,
+    /// Marks the window.
+    Mark,
+}
+
+impl FromStr for WindowAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<WindowAction, Error> {
+        match s {
+            "focus" => Ok(WindowAction::Focus),
+            "move" => Ok(WindowAction::Move),
+            "kill" => Ok(WindowAction::Kill),
+            "mark" => Ok(WindowAction::Mark),
+            _ => Err(Error::Invalid(trf("Unknown window action {}", &[&s]))),
+        }
+    }
+}
+
+impl WindowAction {
+    /// The translated label of the action, when several are offered.
+    fn label(self) -> String {
+        tr(match self {
+            WindowAction::Focus => "Focus",
+            WindowAction::Move => "Move to current workspace",
+            WindowAction::Kill => "Kill",
+            WindowAction::Mark => "Mark",
+        })
+    }
+
+    /// The i3 command performing the action on the container `id`.
+    fn command(self, id: u64, mark: &str) -> String {
+        match self {
+            WindowAction::Focus => format!("[con_id={}] focus", id),
+            WindowAction::Move => format!(
+                "[con_id={0}] move container to workspace current, [con_id={0}] focus",
+                id
+            ),
+            WindowAction::Kill => format!("[con_id={}] kill", id),
+            WindowAction::Mark => format!("[con_id={}] mark --add {}", id, quote(mark)),
+        }
+    }
+}
+
+/// The order in which windows are listed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum WindowOrder {
+    /// The order of the layout tree.
+    Tree,
+    /// Most recently focused first, as recorded by `record_history`.
+    Recent,
+}
+
+/// A window found in the layout tree.
+struct Window {
+    id: u64,
+    output: String,
+    workspace: String,
+    class: String,
+    title: String,
+    focused: bool,
+    urgent: bool,
+}
+
+impl Window {
+    /// The label of the window in the menu.
+    fn label(&self) -> String {
+        let marker = if self.urgent {
+            "! "
+        } else if self.focused {
+            "* "
+        } else {
+            ""
+        };
+        format!(
+            "{}[{}] {}: {}",
+            marker, self.workspace, self.class, self.title
+        )
+    }
+}
+
+/// A generator listing the open windows, queried from i3 every time it runs.
+pub struct I3Windows {
+    /// The prompt displayed in the menu.
+    prompt: String,
+    /// The operations offered for the selected window. With more than one, a
+    /// window opens a submenu of them.
+    actions: Vec<WindowAction>,
+    /// The mark set by `WindowAction::Mark`.
+    mark: String,
+    /// Only windows on this workspace are listed.
+    workspace: Option<String>,
+    /// Only windows on this output are listed.
+    output: Option<String>,
+    /// Only windows of this class are listed.
+    class: Option<String>,
+    /// The order of the listed windows.
+    order: WindowOrder,
+    /// The look and behaviour of the generated menus.
+    appearance: Appearance,
+}
+
+impl I3Windows {
+    pub fn new(
+        prompt: String,
+        actions: Vec<WindowAction>,
+        mark: String,
+        workspace: Option<String>,
+        output: Option<String>,
+        class: Option<String>,
+        order: WindowOrder,
+    ) -> I3Windows {
+        I3Windows {
+            prompt,
+            actions,
+            mark,
+            workspace,
+            output,
+            class,
+            order,
+            appearance: Appearance::default(),
+        }
+    }
+
+    /// Sets the look and behaviour of the generated menus.
+    pub fn set_appearance(&mut self, appearance: Appearance) {
+        self.appearance = appearance;
+    }
+
+    /// Builds the menu of the windows currently open.
+    pub fn menu(&self) -> Result<RofiMenu, Error> {
+        let history = match self.order {
+            WindowOrder::Recent => read_history(),
+            WindowOrder::Tree => Vec::new(),
+        };
+        self.menu_from(&mut I3Ipc::connect()?, &history)
+    }
+
+    /// Builds the menu of the windows in the layout tree of `ipc`, with the
+    /// focus history `history` for `WindowOrder::Recent`.
+    fn menu_from(&self, ipc: &mut I3Ipc, history: &[u64]) -> Result<RofiMenu, Error> {
+        let tree = ipc.request(GET_TREE, "")?;
+        let mut windows = Vec::new();
+        collect_windows(&tree, "", "", &mut windows);
+        windows.retain(|x| {
+            self.workspace.as_ref().is_none_or(|w| *w == x.workspace)
+                && self.output.as_ref().is_none_or(|o| *o == x.output)
+                && self.class.as_ref().is_none_or(|c| *c == x.class)
+        });
+        if self.order == WindowOrder::Recent {
+            // Windows missing from the history keep their tree order, after
+            // the ones that have been focused.
+            windows.sort_by_key(|x| {
+                history
+                    .iter()
+                    .position(|y| *y == x.id)
+                    .unwrap_or(history.len())
+            });
+        }
+
+        let mut options: LinkedHashMap<String, Box<dyn RofiAction>> = LinkedHashMap::new();
+        for window in windows {
+            // Windows with the same class and title would otherwise replace
+            // each other.
+            insert_unique(&mut options, window.label(), self.window_action(&window));
+        }
+        let mut menu = RofiMenu::new(String::from("windows"), self.prompt.clone(), options, false);
+        menu.set_appearance(self.appearance.clone());
+        Ok(menu)
+    }
+
+    /// Returns the action performed when `window` is selected.
+    fn window_action(&self, window: &Window) -> Box<dyn RofiAction> {
+        if let [action] = self.actions.as_slice() {
+            return Box::new(I3Command::new(action.command(window.id, &self.mark)));
+        }
+        let mut options: LinkedHashMap<String, Box<dyn RofiAction>> = LinkedHashMap::new();
+        for action in &self.actions {
+            options.insert(
+                action.label(),
+                Box::new(I3Command::new(action.command(window.id, &self.mark))),
+            );
+        }
+        let mut menu = RofiMenu::new(String::from("window"), window.title.clone(), options, false);
+        menu.set_appearance(self.appearance.clone());
+        Box::new(menu)
+    }
+}
+
+impl RofiAction for I3Windows {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        run_chain(&self.menu()?)?;
+        Ok(None)
+    }
+
+    fn generate(&self) -> Option<Result<RofiMenu, Error>> {
+        Some(self.menu())
+    }
+
+    fn needs_input(&self) -> bool {
+        true
+    }
+}
+
+/// Collects the windows below `node`, which is on `output` and `workspace`.
+fn collect_windows(
+    node: &serde_json::Value,
+    output: &str,
+    workspace: &str,
+    windows: &mut Vec<Window>,
+) {
+    let name = node.get("name").and_then(|x| x.as_str()).unwrap_or("");
+    let (output, workspace) = match node.get("type").and_then(|x| x.as_str()) {
+        // Skip i3's internal output, which holds the scratchpad.
+        Some("output") if name.starts_with("__") => return,
+        Some("output") => (name, workspace),
+        Some("workspace") => (output, name),
+        _ => (output, workspace),
+    };
+
+    let children: Vec<&serde_json::Value> = ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|x| node.get(x).and_then(|x| x.as_array()))
+        .flatten()
+        .collect();
+    if children.is_empty() {
+        // i3 windows have an X11 window id, while native Wayland windows in
+        // sway only have an app id.
+        let class = node
+            .get("window_properties")
+            .and_then(|x| x.get("class"))
+            .or_else(|| node.get("app_id"))
+            .and_then(|x| x.as_str());
+        let is_window = node.get("window").is_some_and(|x| x.is_u64()) || class.is_some();
+        if let (true, Some(id)) = (is_window, node.get("id").and_then(|x| x.as_u64())) {
+            windows.push(Window {
+                id,
+                output: String::from(output),
+                workspace: String::from(workspace),
+                class: String::from(class.unwrap_or("")),
+                title: String::from(name),
+                focused: node.get("focused").and_then(|x| x.as_bool()) == Some(true),
+                urgent: node.get("urgent").and_then(|x| x.as_bool()) == Some(true),
+            });
+        }
+    }
+    for child in children {
+        collect_windows(child, output, workspace, windows);
+    }
+}
+
+/// Returns the path of the focus history file.
+fn history_path() -> Result<PathBuf, Error> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+        .ok_or_else(|| Error::Failed(tr("XDG_RUNTIME_DIR is not set")))?;
+    Ok(PathBuf::from(runtime_dir).join(HISTORY_NAME))
+}
+
+/// Reads the container ids of the focus history, most recent first.
+fn read_history() -> Vec<u64> {
+    history_path()
+        .ok()
+        .and_then(|x| fs::read_to_string(x).ok())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|x| x.parse::<u64>().ok())
+        .collect()
+}
+
+/// Records the windows focused in i3, for `WindowOrder::Recent`.
+///
+/// This listens to window events until the connection to i3 is lost.
+pub fn record_history() -> Result<(), Error> {
+    let path = history_path()?;
+    let mut ipc = I3Ipc::connect()?;
+    let reply = ipc.request(SUBSCRIBE, "[\"window\"]")?;
+    if reply.get("success").and_then(|x| x.as_bool()) != Some(true) {
+        return Err(Error::Failed(tr("Could not subscribe to i3 window events")));
+    }
+
+    let mut history = read_history();
+    loop {
+        let (msg_type, payload) = ipc.read_message()?;
+        if msg_type != WINDOW_EVENT {
+            continue;
+        }
+        let event: serde_json::Value = match serde_json::from_slice(&payload) {
+            Ok(event) => event,
+            Err(_) => continue,
+        };
+        let id = event
+            .get("container")
+            .and_then(|x| x.get("id"))
+            .and_then(|x| x.as_u64());
+        let id = match (event.get("change").and_then(|x| x.as_str()), id) {
+            (Some("focus"), Some(id)) => id,
+            _ => continue,
+        };
+
+        history.retain(|x| *x != id);
+        history.insert(0, id);
+        history.truncate(HISTORY_LEN);
+
+        // Write to a temporary file first, so that readers never see a
+        // partially written history.
+        let tmp = path.with_extension("tmp");
+        let written = fs::File::create(&tmp)
+            .and_then(|mut file| history.iter().try_for_each(|id| writeln!(file, "{}", id)))
+            .and_then(|_| fs::rename(&tmp, &path));
+        written.map_err(|e| Error::io(tr("Could not write focus history"), e))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rmenu::i3::tests::FakeI3;
+
+    /// The layout tree of i3 with a tiled, a floating and a scratchpad window
+    /// on one output, and a Wayland window in sway on another.
+    const TREE: &str = r#"{
+        "id": 1, "type": "root", "name": "root", "nodes": [
+            {"id": 2, "type": "output", "name": "__i3", "nodes": [
+                {"id": 3, "type": "workspace", "name": "__i3_scratch", "floating_nodes": [
+                    {"id": 4, "type": "floating_con", "nodes": [
+                        {"id": 5, "type": "con", "name": "Notes", "window": 50,
+                         "window_properties": {"class": "Gedit"}, "nodes": []}
+                    ]}
+                ]}
+            ]},
+            {"id": 6, "type": "output", "name": "DP-1", "nodes": [
+                {"id": 7, "type": "con", "name": "content", "nodes": [
+                    {"id": 8, "type": "workspace", "name": "1", "nodes": [
+                        {"id": 9, "type": "con", "name": null, "marks": ["split"], "nodes": [
+                            {"id": 10, "type": "con", "name": "Web", "window": 100,
+                             "window_properties": {"class": "Firefox"}, "nodes": []},
+                            {"id": 11, "type": "con", "name": "Shell", "window": 110,
+                             "window_properties": {"class": "URxvt"}, "marks": ["rmenu"],
+                             "focused": true, "nodes": []}
+                        ]}
+                    ], "floating_nodes": [
+                        {"id": 12, "type": "floating_con", "nodes": [
+                            {"id": 13, "type": "con", "name": "Volume", "window": 130,
+                             "window_properties": {"class": "Pavucontrol"}, "urgent": true,
+                             "nodes": []}
+                        ]}
+                    ]},
+                    {"id": 14, "type": "workspace", "name": "2", "marks": ["empty"], "nodes": []}
+                ]}
+            ]},
+            {"id": 15, "type": "output", "name": "HDMI-1", "nodes": [
+                {"id": 16, "type": "workspace", "name": "3", "nodes": [
+                    {"id": 17, "type": "con", "name": "Terminal", "app_id": "foot",
+                     "window": null, "nodes": []}
+                ]}
+            ]}
+        ]
+    }"#;
+
+    /// Returns the labels of the windows listed by `windows`, in a tree
+    /// served by a fake i3, with the focus history `history`.
+    fn labels(windows: &I3Windows, history: &[u64]) -> Vec<String> {
+        let server = FakeI3::start(vec![(GET_TREE, TREE)]);
+        let menu = windows.menu_from(&mut server.connect(), history).unwrap();
+        menu.options().keys().cloned().collect()
+    }
+
+    /// Returns a generator focusing windows, listed in `order`.
+    fn windows(workspace: Option<&str>, order: WindowOrder) -> I3Windows {
+        I3Windows::new(
+            String::from("window"),
+            vec![WindowAction::Focus],
+            String::from("rmenu"),
+            workspace.map(String::from),
+            None,
+            None,
+            order,
+        )
+    }
+
+    #[test]
+    fn menu_lists_the_windows_of_the_tree_but_the_scratchpad() {
+        assert_eq!(
+            labels(&windows(None, WindowOrder::Tree), &[]),
+            vec![
+                "[1] Firefox: Web",
+                "* [1] URxvt: Shell",
+                "! [1] Pavucontrol: Volume",
+                "[3] foot: Terminal",
+            ]
+        );
+        assert_eq!(
+            labels(&windows(Some("3"), WindowOrder::Tree), &[11]),
+            vec!["[3] foot: Terminal"]
+        );
+    }
+
+    #[test]
+    fn menu_lists_recent_windows_first() {
+        // Closed windows in the history are ignored, and the others keep the
+        // order of the tree.
+        assert_eq!(
+            labels(&windows(None, WindowOrder::Recent), &[13, 99, 11]),
+            vec![
+                "! [1] Pavucontrol: Volume",
+                "* [1] URxvt: Shell",
+                "[1] Firefox: Web",
+                "[3] foot: Terminal",
+            ]
+        );
+    }
+
+    #[test]
+    fn several_actions_open_a_submenu_of_the_window() {
+        let mut generator = windows(None, WindowOrder::Tree);
+        generator.actions = vec![WindowAction::Focus, WindowAction::Mark];
+        let server = FakeI3::start(vec![(GET_TREE, TREE)]);
+        let menu = generator.menu_from(&mut server.connect(), &[]).unwrap();
+        let submenu = menu.options()["* [1] URxvt: Shell"].as_menu().unwrap();
+        assert_eq!(submenu.prompt(), "Shell");
+        let labels: Vec<&str> = submenu.options().keys().map(String::as_str).collect();
+        assert_eq!(labels, vec!["Focus", "Mark"]);
+    }
+
+    #[test]
+    fn mark_command_quotes_the_mark() {
+        assert_eq!(
+            WindowAction::Mark.command(7, "a b; exec xterm"),
+            "[con_id=7] mark --add \"a b; exec xterm\""
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use std::str::FromStr;
+
+use linked_hash_map::LinkedHashMap;
+
+use super::{quote, I3Command, I3Ipc, I3PromptCommand, GET_OUTPUTS, GET_WORKSPACES};
+use crate::rmenu::appearance::Appearance;
+use crate::rmenu::locale::{tr, trf};
+use crate::rmenu::{run_chain, Error, RofiAction, RofiMenu};
+
+/// The label of the entry creating a new workspace.
+const NEW_WORKSPACE: &str = "New workspace";
+
+/// An operation performed on the selected workspace.
+#[derive(Clone, Copy, PartialEq)]
+pub enum WorkspaceAction {
+    /// Switches to the workspace.
+    Switch,
+    /// Renames the workspace to a name typed in rofi.
+    Rename,
+    /// Moves the workspace to another output.
+    Move,
+    /// Moves the focused container to the workspace.
+    MoveContainer,
+}
+
+impl FromStr for WorkspaceAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<WorkspaceAction, Error> {
+        match s {
+            "switch" => Ok(WorkspaceAction::Switch),
+            "rename" => Ok(WorkspaceAction::Rename),
+            "move" => Ok(WorkspaceAction::Move),
+            "move_container" => Ok(WorkspaceAction::MoveContainer),
+            _ => Err(Error::Invalid(trf("Unknown workspace action {}", &[&s]))),
+        }
+    }
+}
+
+impl WorkspaceAction {
+    /// The translated label of the action, when several are offered.
+    fn label(self) -> String {
+        tr(match self {
+            WorkspaceAction::Switch => "Switch",
+            WorkspaceAction::Rename => "Rename",
+            WorkspaceAction::Move => "Move to output",
+            WorkspaceAction::MoveContainer => "Move focused container here",
+        })
+    }
+}
+
+/// A workspace as listed by i3.
+struct Workspace {
+    name: String,
+    output: String,
+    visible: bool,
+    focused: bool,
+    urgent: bool,
+}
+
+impl Workspace {
+    /// The label of the workspace in the menu.
+    fn label(&self) -> String {
+        let marker = if self.urgent {
+            "! "
+        } else if self.focused {
+            "* "
+        } else if self.visible {
+            "+ "
+        } else {
+            ""
+        };
+        format!("{}{} ({})", marker, self.name, self.output)
+    }
+}
+
+/// A generator listing the workspaces, queried from i3 every time it runs.
+pub struct I3Workspaces {
+    /// The prompt displayed in the menu.
+    prompt: String,
+    /// The operations offered for the selected workspace. With more than one,
+    /// a workspace opens a submenu of them.
+    actions: Vec<WorkspaceAction>,
+    /// Whether an entry creating a new workspace is offered.
+    create: bool,
+    /// The look and behaviour of the generated menus.
+    appearance: Appearance,
+}
+
+impl I3Workspaces {
+    pub fn new(prompt: String, actions: Vec<WorkspaceAction>, create: bool) -> I3Workspaces {
+        I3Workspaces {
+            prompt,
+            actions,
+            create,
+            appearance: Appearance::default(),
+        }
+    }
+
+    /// Sets the look and behaviour of the generated menus.
+    pub fn set_appearance(&mut self, appearance: Appearance) {
+        self.appearance = appearance;
+    }
+
+    /// Builds the menu of the current workspaces.
+    pub fn menu(&self) -> Result<RofiMenu, Error> {
+        self.menu_from(&mut I3Ipc::connect()?)
+    }
+
+    /// Builds the menu of the workspaces listed by `ipc`.
+    fn menu_from(&self, ipc: &mut I3Ipc) -> Result<RofiMenu, Error> {
+        let reply = ipc.request(GET_WORKSPACES, "")?;
+        let workspaces = reply
+            .as_array()
+            .ok_or_else(|| Error::Invalid(tr("Invalid i3 IPC reply")))?
+            .iter()
+            .map(|x| {
+                let get_str = |key| x.get(key).and_then(|x| x.as_str()).unwrap_or("");
+                let get_bool = |key| x.get(key).and_then(|x| x.as_bool()) == Some(true);
+                Workspace {
+                    name: String::from(get_str("name")),
+                    output: String::from(get_str("output")),
+                    visible: get_bool("visible"),
+                    focused: get_bool("focused"),
+                    urgent: get_bool("urgent"),
+                }
+            });
+        let outputs = if self.actions.contains(&WorkspaceAction::Move) {
+            active_outputs(ipc)?
+        } else {
+            Vec::new()
+        };
+        // With a single output, workspaces have nowhere to move to.
+        let actions: Vec<WorkspaceAction> = self
+            .actions
+            .iter()
+            .copied()
+            .filter(|x| *x != WorkspaceAction::Move || outputs.len() > 1)
+            .collect();
+        if actions.is_empty() {
+            return Err(Error::Failed(tr(
+                "There is no other output to move workspaces to",
+            )));
+        }
+
+        let mut options: LinkedHashMap<String, Box<dyn RofiAction>> = LinkedHashMap::new();
+        for workspace in workspaces {
+            options.insert(
+                workspace.label(),
+                self.workspace_action(&actions, &workspace, &outputs),
+            );
+        }
+        if self.create {
+            options.insert(
+                tr(NEW_WORKSPACE),
+                Box::new(I3PromptCommand::new(
+                    tr("workspace"),
+                    Box::new(|name| format!("workspace {}", name)),
+                )),
+            );
+        }
+        Ok(self.new_menu("workspaces", self.prompt.clone(), options))
+    }
+
+    /// Returns the action performed when `workspace` is selected, which
+    /// offers `actions`.
+    fn workspace_action(
+        &self,
+        actions: &[WorkspaceAction],
+        workspace: &Workspace,
+        outputs: &[String],
+    ) -> Box<dyn RofiAction> {
+        if let [action] = actions {
+            return self.operation(*action, workspace, outputs);
+        }
+        let mut options: LinkedHashMap<String, Box<dyn RofiAction>> = LinkedHashMap::new();
+        for action in actions {
+            options.insert(action.label(), self.operation(*action, workspace, outputs));
+        }
+        Box::new(self.new_menu("workspace", workspace.name.clone(), options))
+    }
+
+    /// Returns the action performing `action` on `workspace`.
+    fn operation(
+        &self,
+        action: WorkspaceAction,
+        workspace: &Workspace,
+        outputs: &[String],
+    ) -> Box<dyn RofiAction> {
+        let name = quote(&workspace.name);
+        match action {
+            WorkspaceAction::Switch => Box::new(I3Command::new(format!("workspace {}", name))),
+            WorkspaceAction::Rename => Box::new(I3PromptCommand::new(
+                trf("rename {}", &[&workspace.name]),
+                Box::new(move |new_name| format!("rename workspace {} to {}", name, new_name)),
+            )),
+            WorkspaceAction::Move => {
+                let mut options: LinkedHashMap<String, Box<dyn RofiAction>> = LinkedHashMap::new();
+                for output in outputs.iter().filter(|x| **x != workspace.output) {
+                    // Only the focused workspace can be moved, so switch to it
+                    // first.
+                    let command = format!(
+                        "workspace {}, move workspace to output {}",
+                        name,
+                        quote(output)
+                    );
+                    options.insert(output.clone(), Box::new(I3Command::new(command)));
+                }
+                Box::new(self.new_menu("outputs", tr("output"), options))
+            }
+            WorkspaceAction::MoveContainer => Box::new(I3Command::new(format!(
+                "move container to workspace {}",
+                name
+            ))),
+        }
+    }
+
+    /// Creates a menu with the appearance of the generated menus.
+    fn new_menu(
+        &self,
+        name: &str,
+        prompt: String,
+        options: LinkedHashMap<String, Box<dyn RofiAction>>,
+    ) -> RofiMenu {
+        let mut menu = RofiMenu::new(String::from(name), prompt, options, false);
+        menu.set_appearance(self.appearance.clone());
+        menu
+    }
+}
+
+impl RofiAction for I3Workspaces {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        run_chain(&self.menu()?)?;
+        Ok(None)
+    }
+
+    fn generate(&self) -> Option<Result<RofiMenu, Error>> {
+        Some(self.menu())
+    }
+
+    fn needs_input(&self) -> bool {
+        true
+    }
+}
+
+/// Returns the names of the active outputs.
+fn active_outputs(ipc: &mut I3Ipc) -> Result<Vec<String>, Error> {
+    let reply = ipc.request(GET_OUTPUTS, "")?;
+    let outputs = reply
+        .as_array()
+        .ok_or_else(|| Error::Invalid(tr("Invalid i3 IPC reply")))?
+        .iter()
+        .filter(|x| x.get("active").and_then(|x| x.as_bool()) == Some(true))
+        .filter_map(|x| x.get("name").and_then(|x| x.as_str()))
+        .map(String::from)
+        .collect();
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rmenu::i3::tests::FakeI3;
+
+    /// The reply of i3 listing two workspaces on two outputs.
+    const WORKSPACES: &str = r#"[
+        {"name": "1", "output": "DP-1", "visible": true, "focused": true, "urgent": false},
+        {"name": "2: web", "output": "HDMI-1", "visible": true, "focused": false, "urgent": true}
+    ]"#;
+
+    /// Returns the labels of the options of `menu`.
+    fn labels(menu: &RofiMenu) -> Vec<&str> {
+        menu.options().keys().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn menu_lists_the_workspaces() {
+        let server = FakeI3::start(vec![(GET_WORKSPACES, WORKSPACES)]);
+        let workspaces = I3Workspaces::new(String::from("ws"), vec![WorkspaceAction::Switch], true);
+        let menu = workspaces.menu_from(&mut server.connect()).unwrap();
+        assert_eq!(
+            labels(&menu),
+            vec!["* 1 (DP-1)", "! 2: web (HDMI-1)", NEW_WORKSPACE]
+        );
+        assert_eq!(menu.prompt(), "ws");
+        // A single action is run directly.
+        assert!(menu.options()["* 1 (DP-1)"].as_menu().is_none());
+        // The outputs are only asked for to move workspaces.
+        assert_eq!(server.received(), vec![(GET_WORKSPACES, String::new())]);
+    }
+
+    #[test]
+    fn move_lists_the_other_active_outputs() {
+        let server = FakeI3::start(vec![
+            (GET_WORKSPACES, WORKSPACES),
+            (
+                GET_OUTPUTS,
+                r#"[{"name": "DP-1", "active": true}, {"name": "HDMI-1", "active": true},
+                    {"name": "VGA-1", "active": false}]"#,
+            ),
+        ]);
+        let actions = vec![WorkspaceAction::Switch, WorkspaceAction::Move];
+        let workspaces = I3Workspaces::new(String::from("ws"), actions, false);
+        let menu = workspaces.menu_from(&mut server.connect()).unwrap();
+        let submenu = menu.options()["* 1 (DP-1)"].as_menu().unwrap();
+        assert_eq!(labels(submenu), vec!["Switch", "Move to output"]);
+        let outputs = submenu.options()["Move to output"].as_menu().unwrap();
+        assert_eq!(labels(outputs), vec!["HDMI-1"]);
+    }
+
+    #[test]
+    fn move_is_hidden_with_a_single_output() {
+        let outputs = r#"[{"name": "DP-1", "active": true}]"#;
+        let server = FakeI3::start(vec![(GET_WORKSPACES, WORKSPACES), (GET_OUTPUTS, outputs)]);
+        let actions = vec![WorkspaceAction::Switch, WorkspaceAction::Move];
+        let workspaces = I3Workspaces::new(String::from("ws"), actions, false);
+        let menu = workspaces.menu_from(&mut server.connect()).unwrap();
+        assert!(menu.options()["* 1 (DP-1)"].as_menu().is_none());
+
+        let server = FakeI3::start(vec![(GET_WORKSPACES, WORKSPACES), (GET_OUTPUTS, outputs)]);
+        let workspaces = I3Workspaces::new(String::from("ws"), vec![WorkspaceAction::Move], false);
+        assert!(workspaces.menu_from(&mut server.connect()).is_err());
+    }
+}
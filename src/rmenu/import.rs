@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::Path;
+
+use serde_yaml::{Mapping, Value};
+
+use super::locale::trf;
+use super::Error;
+
+pub mod jgmenu;
+pub mod xdg_menu;
+
+/// The terminal emulator running the commands that need one.
+const TERMINAL: &str = "i3-sensible-terminal";
+
+/// A menu translated from another format.
+pub struct Import {
+    /// The menu, in the config format read by `Builder`.
+    pub menu: Value,
+    /// The parts of the source that could not be translated.
+    pub warnings: Vec<String>,
+}
+
+/// Translates the menu in `filename`, written in `format`, which is either
+/// `jgmenu` or `xdg-menu`.
+pub fn import(format: &str, filename: &str) -> Result<Import, Error> {
+    let contents = fs::read_to_string(filename)
+        .map_err(|e| Error::io(trf("Could not read {}", &[&filename]), e))?;
+    match format {
+        "jgmenu" => {
+            let name = Path::new(filename)
+                .file_stem()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from("jgmenu"));
+            Ok(jgmenu::translate(&contents, &name))
+        }
+        "xdg-menu" => xdg_menu::translate(&contents),
+        _ => Err(Error::Invalid(trf("Unknown menu format {}", &[&format]))),
+    }
+}
+
+/// Options being collected for a menu, with their strings kept unique.
+struct Options {
+    options: Vec<Value>,
+    strings: Vec<String>,
+}
+
+impl Options {
+    fn new() -> Options {
+        Options {
+            options: Vec::new(),
+            strings: Vec::new(),
+        }
+    }
+
+    /// Adds an option, numbering `string` if it is already taken, since
+    /// options with the same string would replace each other.
+    fn push(&mut self, string: &str, action: Value, icon: Option<&str>) {
+        let mut unique = String::from(string);
+        let mut n = 1;
+        while self.strings.contains(&unique) {
+            n += 1;
+            unique = format!("{} ({})", string, n);
+        }
+        let mut option = Mapping::new();
+        option.insert(Value::from("string"), Value::from(unique.as_str()));
+        option.insert(Value::from("action"), action);
+        if let Some(icon) = icon {
+            option.insert(Value::from("icon"), Value::from(icon));
+        }
+        self.options.push(Value::Mapping(option));
+        self.strings.push(unique);
+    }
+
+    /// Adds a non-selectable row.
+    fn push_separator(&mut self, label: &str) {
+        let mut separator = Mapping::new();
+        separator.insert(Value::from("separator"), Value::from(label));
+        self.options.push(Value::Mapping(separator));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// Describes a `RofiMenu` of the options.
+    fn into_menu(self, name: &str, prompt: &str) -> Value {
+        let mut menu = Mapping::new();
+        menu.insert(Value::from("type"), Value::from("RofiMenu"));
+        menu.insert(Value::from("name"), Value::from(name));
+        menu.insert(Value::from("prompt"), Value::from(prompt));
+        menu.insert(Value::from("options"), Value::Sequence(self.options));
+        Value::Mapping(menu)
+    }
+}
+
+/// Describes a detached `RofiCommand` launching `command`, given as a
+/// program followed by its arguments.
+fn launch(mut command: Vec<String>) -> Value {
+    let program = command.remove(0);
+    let mut action = Mapping::new();
+    action.insert(Value::from("type"), Value::from("RofiCommand"));
+    action.insert(Value::from("command"), Value::from(program));
+    action.insert(
+        Value::from("args"),
+        Value::Sequence(command.into_iter().map(Value::from).collect()),
+    );
+    action.insert(Value::from("detach"), Value::from(true));
+    Value::Mapping(action)
+}
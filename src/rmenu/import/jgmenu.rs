@@ -0,0 +1,341 @@
+use linked_hash_map::LinkedHashMap;
+
+use super::{launch, Import, Options, TERMINAL};
+use crate::rmenu::locale::trf;
+
+/// The characters that make a command need a shell to run.
+const SHELL_CHARS: &[char] = &[
+    '|', '&', ';', '<', '>', '(', ')', '$', '`', '\\', '"', '\'', '*', '?', '[', '#', '~', '=', '%',
+];
+
+/// A line of a jgmenu CSV menu.
+enum Entry {
+    /// An item, with its line number.
+    Item {
+        line: usize,
+        description: String,
+        command: String,
+        icon: Option<String>,
+    },
+    /// A non-selectable row.
+    Separator(String),
+}
+
+/// Translates a menu in jgmenu's CSV format.
+///
+/// Items before the first `^tag()` make the root menu, named `name`, and
+/// `^checkout()` items open the submenus marked with `^tag()`.
+// The linked-hash-map 0.5.1 this crate allows has no `Entry::or_default`.
+#[allow(clippy::unwrap_or_default)]
+pub fn translate(contents: &str, name: &str) -> Import {
+    let mut warnings = Vec::new();
+    let mut sections: LinkedHashMap<String, Vec<Entry>> = LinkedHashMap::new();
+    let mut section = String::new();
+    sections.insert(section.clone(), Vec::new());
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('@') {
+            warnings.push(trf("line {}: widgets are not supported", &[&line_number]));
+            continue;
+        }
+        let fields = split_fields(line);
+        let first = fields[0].trim();
+        if let Some(tag) = directive(first, "tag") {
+            section = String::from(tag);
+            sections.entry(section.clone()).or_insert_with(Vec::new);
+            continue;
+        }
+        let entries = sections.entry(section.clone()).or_insert_with(Vec::new);
+        if let Some(label) = directive(first, "sep") {
+            entries.push(Entry::Separator(String::from(label)));
+            continue;
+        }
+        if first.starts_with('^') {
+            warnings.push(trf("line {}: {} is not supported", &[&line_number, &first]));
+            continue;
+        }
+        let command = fields.get(1).map_or("", |x| x.trim());
+        if command.is_empty() {
+            // Items without a command are only labels.
+            entries.push(Entry::Separator(String::from(first)));
+            continue;
+        }
+        if fields.get(3).is_some_and(|x| !x.trim().is_empty()) {
+            warnings.push(trf(
+                "line {}: the working directory of {} is not supported",
+                &[&line_number, &first],
+            ));
+        }
+        entries.push(Entry::Item {
+            line: line_number,
+            description: String::from(first),
+            command: String::from(command),
+            icon: fields
+                .get(2)
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .map(String::from),
+        });
+    }
+
+    let mut translator = Translator {
+        sections: &sections,
+        used: vec![String::new()],
+        warnings,
+    };
+    let menu = translator.menu("", &mut Vec::new()).into_menu(name, name);
+    for tag in sections.keys() {
+        if !translator.used.contains(tag) {
+            translator
+                .warnings
+                .push(trf("submenu {} is never checked out", &[&tag]));
+        }
+    }
+    Import {
+        menu,
+        warnings: translator.warnings,
+    }
+}
+
+/// Translates the sections of a menu into nested menus.
+struct Translator<'a> {
+    sections: &'a LinkedHashMap<String, Vec<Entry>>,
+    /// The sections that have been translated.
+    used: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl<'a> Translator<'a> {
+    /// Translates the section `tag`, opened from the sections in `stack`.
+    fn menu(&mut self, tag: &str, stack: &mut Vec<String>) -> Options {
+        let mut options = Options::new();
+        let entries = match self.sections.get(tag) {
+            Some(entries) => entries,
+            None => return options,
+        };
+        stack.push(String::from(tag));
+        for entry in entries {
+            let (line, description, command, icon) = match entry {
+                Entry::Separator(label) => {
+                    options.push_separator(label);
+                    continue;
+                }
+                Entry::Item {
+                    line,
+                    description,
+                    command,
+                    icon,
+                } => (line, description, command, icon.as_deref()),
+            };
+            let submenu = directive(command, "checkout").or_else(|| directive(command, "root"));
+            if let Some(submenu) = submenu {
+                if stack.iter().any(|x| x == submenu) {
+                    self.warnings.push(trf(
+                        "line {}: submenu {} contains itself",
+                        &[&line, &submenu],
+                    ));
+                } else if !self.sections.contains_key(submenu) {
+                    self.warnings
+                        .push(trf("line {}: there is no submenu {}", &[&line, &submenu]));
+                } else {
+                    self.used.push(String::from(submenu));
+                    let menu = self.menu(submenu, stack).into_menu(submenu, description);
+                    options.push(description, menu, icon);
+                }
+            } else if directive(command, "back").is_some() {
+                self.warnings.push(trf(
+                    "line {}: {} is dropped, as rofi goes back with Escape",
+                    &[&line, &description],
+                ));
+            } else if let Some(command) = directive(command, "term") {
+                let mut wrapped = vec![String::from(TERMINAL), String::from("-e")];
+                wrapped.extend(split_command(command));
+                options.push(description, launch(wrapped), icon);
+            } else if command.starts_with('^') {
+                self.warnings.push(trf(
+                    "line {}: {} of {} is not supported",
+                    &[&line, &command, &description],
+                ));
+            } else {
+                options.push(description, launch(split_command(command)), icon);
+            }
+        }
+        stack.pop();
+        options
+    }
+}
+
+/// Returns the argument of `s` if it is the directive `^name(argument)`.
+fn directive<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+    s.strip_prefix('^')?
+        .strip_prefix(name)?
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+/// Splits a line into its comma separated fields. Fields may be enclosed in
+/// triple quotes to hold commas.
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut rest = line;
+    loop {
+        if let Some(quoted) = rest.trim_start().strip_prefix("\"\"\"") {
+            if let Some(end) = quoted.find("\"\"\"") {
+                fields.push(String::from(&quoted[..end]));
+                rest = &quoted[end + 3..];
+                match rest.find(',') {
+                    Some(index) => rest = &rest[index + 1..],
+                    None => break,
+                }
+                continue;
+            }
+        }
+        match rest.find(',') {
+            Some(index) => {
+                fields.push(String::from(&rest[..index]));
+                rest = &rest[index + 1..];
+            }
+            None => {
+                fields.push(String::from(rest));
+                break;
+            }
+        }
+    }
+    fields
+}
+
+/// Splits a shell command into a program and its arguments, running it
+/// through `sh` if it uses anything more than words.
+fn split_command(command: &str) -> Vec<String> {
+    if command.contains(SHELL_CHARS) {
+        return vec![
+            String::from("sh"),
+            String::from("-c"),
+            String::from(command),
+        ];
+    }
+    command.split_whitespace().map(String::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses the expected menu from YAML.
+    fn yaml(s: &str) -> serde_yaml::Value {
+        serde_yaml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn translate_nests_checked_out_submenus() {
+        let import = translate(
+            "# comment\n\
+             Terminal,xterm,utilities-terminal\n\
+             ^sep(Apps)\n\
+             Browse,^checkout(web)\n\
+             \n\
+             ^tag(web)\n\
+             Firefox,firefox --private-window\n\
+             Top,^term(top -d 1)\n",
+            "main",
+        );
+        assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+        assert_eq!(
+            import.menu,
+            yaml(
+                "type: RofiMenu
+name: main
+prompt: main
+options:
+  - string: Terminal
+    action: {type: RofiCommand, command: xterm, args: [], detach: true}
+    icon: utilities-terminal
+  - separator: Apps
+  - string: Browse
+    action:
+      type: RofiMenu
+      name: web
+      prompt: Browse
+      options:
+        - string: Firefox
+          action:
+            type: RofiCommand
+            command: firefox
+            args: [--private-window]
+            detach: true
+        - string: Top
+          action:
+            type: RofiCommand
+            command: i3-sensible-terminal
+            args: [-e, top, -d, '1']
+            detach: true
+"
+            )
+        );
+    }
+
+    #[test]
+    fn translate_numbers_duplicates_and_runs_shell_commands_through_sh() {
+        let import = translate(
+            "Run,\"\"\"echo a, b | xclip\"\"\"\nRun,true\nLabel only,\n",
+            "main",
+        );
+        assert_eq!(
+            import.menu,
+            yaml(
+                "type: RofiMenu
+name: main
+prompt: main
+options:
+  - string: Run
+    action:
+      type: RofiCommand
+      command: sh
+      args: [-c, 'echo a, b | xclip']
+      detach: true
+  - string: Run (2)
+    action: {type: RofiCommand, command: 'true', args: [], detach: true}
+  - separator: Label only
+"
+            )
+        );
+    }
+
+    #[test]
+    fn translate_warns_about_what_it_drops() {
+        let import = translate(
+            "@rect,,10,10\n\
+             Loop,^checkout(loop)\n\
+             Missing,^checkout(nowhere)\n\
+             Work,make,,/tmp\n\
+             ^tag(loop)\n\
+             Back,^back()\n\
+             Again,^checkout(loop)\n\
+             ^tag(unused)\n\
+             Item,true\n",
+            "main",
+        );
+        assert_eq!(
+            import.warnings,
+            vec![
+                "line 1: widgets are not supported",
+                "line 4: the working directory of Work is not supported",
+                "line 6: Back is dropped, as rofi goes back with Escape",
+                "line 7: submenu loop contains itself",
+                "line 3: there is no submenu nowhere",
+                "submenu unused is never checked out",
+            ]
+        );
+    }
+
+    #[test]
+    fn split_fields_keeps_commas_in_triple_quotes() {
+        assert_eq!(split_fields("a,\"\"\"b,c\"\"\",d"), vec!["a", "b,c", "d"]);
+        assert_eq!(split_fields("a,,"), vec!["a", "", ""]);
+    }
+}
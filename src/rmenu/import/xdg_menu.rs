@@ -0,0 +1,627 @@
+use serde_yaml::Value;
+
+use super::{launch, Import, Options, TERMINAL};
+use crate::rmenu::desktop::{directory_entry, Application, DesktopApps};
+use crate::rmenu::locale::{tr, trf};
+use crate::rmenu::Error;
+
+/// The elements of a menu file that are not supported. The standard
+/// directories are read in place of the ones they name.
+const UNSUPPORTED: &[&str] = &[
+    "AppDir",
+    "DirectoryDir",
+    "MergeFile",
+    "MergeDir",
+    "DefaultMergeDirs",
+    "LegacyDir",
+    "KDELegacyDirs",
+    "Move",
+    "DefaultLayout",
+];
+
+/// The elements that make up the rules of `<Include>` and `<Exclude>`.
+const RULES: &[&str] = &["Filename", "Category", "All", "And", "Or", "Not"];
+
+/// Translates a menu file following the XDG Desktop Menu Specification,
+/// such as `/etc/xdg/menus/applications.menu`.
+///
+/// The applications are those currently installed, as listed by the
+/// `DesktopApps` generator, and the submenus are named by their directory
+/// entries.
+pub fn translate(contents: &str) -> Result<Import, Error> {
+    let root = parse_xml(contents)?;
+    if root.name != "Menu" {
+        return Err(Error::Invalid(tr(
+            "The menu file does not describe a <Menu>",
+        )));
+    }
+    let mut warnings = Vec::new();
+    let menu = Menu::parse(root, &mut warnings);
+
+    let apps = DesktopApps::new(String::new(), false, false, String::from(TERMINAL)).applications();
+    let mut allocated = vec![false; apps.len()];
+    menu.allocate(&apps, &mut allocated);
+    let (_, _, menu) = menu
+        .translate(&apps, &allocated, &mut warnings)
+        .ok_or_else(|| Error::Invalid(tr("The menu holds none of the installed applications")))?;
+    Ok(Import { menu, warnings })
+}
+
+/// A menu described by a `<Menu>` element.
+struct Menu {
+    name: String,
+    /// The directory entry naming the menu.
+    directory: Option<String>,
+    /// The `<Include>` (true) and `<Exclude>` (false) elements, in order.
+    rules: Vec<(bool, Element)>,
+    menus: Vec<Menu>,
+    deleted: Option<bool>,
+    only_unallocated: Option<bool>,
+    layout: Option<Element>,
+}
+
+/// An option of a translated menu, before it is laid out.
+struct Item {
+    /// The `<Name>` of a submenu, or the desktop file id of an application.
+    name: String,
+    string: String,
+    icon: Option<String>,
+    /// The action of the option, taken once it has been laid out.
+    action: Option<Value>,
+    is_menu: bool,
+}
+
+impl Menu {
+    /// Reads a `<Menu>` element, merging the submenus with the same name.
+    fn parse(element: Element, warnings: &mut Vec<String>) -> Menu {
+        let mut menu = Menu {
+            name: String::new(),
+            directory: None,
+            rules: Vec::new(),
+            menus: Vec::new(),
+            deleted: None,
+            only_unallocated: None,
+            layout: None,
+        };
+        for child in element.children {
+            match child.name.as_str() {
+                "Name" => menu.name = String::from(child.text()),
+                "Directory" => menu.directory = Some(String::from(child.text())),
+                "Include" | "Exclude" => {
+                    check_rules(&child, warnings);
+                    menu.rules.push((child.name == "Include", child));
+                }
+                "Menu" => {
+                    let submenu = Menu::parse(child, warnings);
+                    menu.add_menu(submenu);
+                }
+                "Deleted" => menu.deleted = Some(true),
+                "NotDeleted" => menu.deleted = Some(false),
+                "OnlyUnallocated" => menu.only_unallocated = Some(true),
+                "NotOnlyUnallocated" => menu.only_unallocated = Some(false),
+                "Layout" => menu.layout = Some(child),
+                "DefaultAppDirs" | "DefaultDirectoryDirs" => {}
+                name if UNSUPPORTED.contains(&name) => {
+                    warnings.push(trf("<{}> is not supported", &[&name]));
+                }
+                name => warnings.push(trf("unknown element <{}>", &[&name])),
+            }
+        }
+        menu
+    }
+
+    /// Adds `submenu`, merging it into the submenu of the same name if there
+    /// is one. The elements read last take precedence.
+    fn add_menu(&mut self, submenu: Menu) {
+        let existing = match self.menus.iter_mut().find(|x| x.name == submenu.name) {
+            Some(existing) => existing,
+            None => return self.menus.push(submenu),
+        };
+        existing.directory = submenu.directory.or(existing.directory.take());
+        existing.rules.extend(submenu.rules);
+        existing.deleted = submenu.deleted.or(existing.deleted);
+        existing.only_unallocated = submenu.only_unallocated.or(existing.only_unallocated);
+        existing.layout = submenu.layout.or(existing.layout.take());
+        for menu in submenu.menus {
+            existing.add_menu(menu);
+        }
+    }
+
+    /// Returns the indices of the applications in `apps` the rules of the
+    /// menu select.
+    fn entries(&self, apps: &[Application]) -> Vec<usize> {
+        let mut entries = Vec::new();
+        for (include, rule) in &self.rules {
+            for (i, app) in apps.iter().enumerate() {
+                if !rule.children.iter().any(|x| matches(x, app)) {
+                    continue;
+                }
+                if !include {
+                    entries.retain(|x| *x != i);
+                } else if !entries.contains(&i) {
+                    entries.push(i);
+                }
+            }
+        }
+        entries
+    }
+
+    /// Marks the applications that are in menus without `<OnlyUnallocated>`.
+    fn allocate(&self, apps: &[Application], allocated: &mut [bool]) {
+        if self.only_unallocated != Some(true) {
+            for i in self.entries(apps) {
+                allocated[i] = true;
+            }
+        }
+        for menu in &self.menus {
+            menu.allocate(apps, allocated);
+        }
+    }
+
+    /// Translates the menu, returning its string, icon and description, or
+    /// nothing if it is deleted or empty.
+    fn translate(
+        &self,
+        apps: &[Application],
+        allocated: &[bool],
+        warnings: &mut Vec<String>,
+    ) -> Option<(String, Option<String>, Value)> {
+        if self.deleted == Some(true) {
+            return None;
+        }
+        let (string, icon) = self
+            .directory
+            .as_deref()
+            .and_then(directory_entry)
+            .unwrap_or_else(|| (self.name.clone(), None));
+
+        let mut menus: Vec<Item> = self
+            .menus
+            .iter()
+            .filter_map(|menu| {
+                let (string, icon, action) = menu.translate(apps, allocated, warnings)?;
+                Some(Item {
+                    name: menu.name.clone(),
+                    string,
+                    icon,
+                    action: Some(action),
+                    is_menu: true,
+                })
+            })
+            .collect();
+        menus.sort_by_key(|x| x.string.to_lowercase());
+        let mut files: Vec<Item> = self
+            .entries(apps)
+            .into_iter()
+            .filter(|x| self.only_unallocated != Some(true) || !allocated[*x])
+            .map(|x| Item {
+                name: apps[x].id.clone(),
+                string: apps[x].name.clone(),
+                icon: apps[x].icon.clone(),
+                action: Some(launch(apps[x].command.clone())),
+                is_menu: false,
+            })
+            .collect();
+        files.sort_by_key(|x| x.string.to_lowercase());
+        let mut items = menus;
+        items.append(&mut files);
+
+        let mut options = Options::new();
+        match &self.layout {
+            Some(layout) => {
+                for element in &layout.children {
+                    lay_out(element, &mut items, &mut options, warnings);
+                }
+            }
+            None => {
+                for item in &mut items {
+                    push(item, &mut options);
+                }
+            }
+        }
+        if options.is_empty() {
+            return None;
+        }
+        let menu = options.into_menu(&self.name, &string);
+        Some((string, icon, menu))
+    }
+}
+
+/// Adds the items placed by an element of a `<Layout>` to `options`.
+fn lay_out(
+    element: &Element,
+    items: &mut [Item],
+    options: &mut Options,
+    warnings: &mut Vec<String>,
+) {
+    let name = element.text();
+    match element.name.as_str() {
+        "Menuname" => {
+            if let Some(item) = items.iter_mut().find(|x| x.is_menu && x.name == name) {
+                push(item, options);
+            }
+        }
+        "Filename" => {
+            if let Some(item) = items.iter_mut().find(|x| !x.is_menu && x.name == name) {
+                push(item, options);
+            }
+        }
+        "Separator" => options.push_separator(""),
+        "Merge" => {
+            let kind = element.attribute("type").unwrap_or_default();
+            let mut merged: Vec<&mut Item> = items
+                .iter_mut()
+                .filter(|x| match kind {
+                    "menus" => x.is_menu,
+                    "files" => !x.is_menu,
+                    _ => true,
+                })
+                .collect();
+            if kind == "all" {
+                merged.sort_by_key(|x| x.string.to_lowercase());
+            }
+            for item in merged {
+                push(item, options);
+            }
+        }
+        name => warnings.push(trf("<{}> in <Layout> is not supported", &[&name])),
+    }
+}
+
+/// Adds `item` to `options`, unless it has been laid out already.
+fn push(item: &mut Item, options: &mut Options) {
+    if let Some(action) = item.action.take() {
+        options.push(&item.string, action, item.icon.as_deref());
+    }
+}
+
+/// Returns whether the rule `element` selects `app`.
+fn matches(element: &Element, app: &Application) -> bool {
+    match element.name.as_str() {
+        "Filename" => app.id == element.text(),
+        "Category" => app.categories.iter().any(|x| x == element.text()),
+        "All" => true,
+        "And" => element.children.iter().all(|x| matches(x, app)),
+        "Or" => element.children.iter().any(|x| matches(x, app)),
+        "Not" => !element.children.iter().any(|x| matches(x, app)),
+        _ => false,
+    }
+}
+
+/// Warns about the elements of the rules in `element` that are not known,
+/// which select no applications.
+fn check_rules(element: &Element, warnings: &mut Vec<String>) {
+    for child in &element.children {
+        if RULES.contains(&child.name.as_str()) {
+            check_rules(child, warnings);
+        } else {
+            warnings.push(trf(
+                "unknown rule <{}> in <{}>",
+                &[&child.name, &element.name],
+            ));
+        }
+    }
+}
+
+/// An element of an XML document.
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    /// The text directly inside the element.
+    text: String,
+}
+
+impl Element {
+    fn new(name: &str) -> Element {
+        Element {
+            name: String::from(name),
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn text(&self) -> &str {
+        self.text.trim()
+    }
+}
+
+/// Parses the XML document `contents` and returns its root element.
+///
+/// This is only enough of XML for menu files: the declaration, document type
+/// and comments are skipped and internal document type subsets are not
+/// supported.
+fn parse_xml(contents: &str) -> Result<Element, Error> {
+    let malformed = |what: &str| Error::Invalid(trf("The menu file is malformed: {}", &[&what]));
+    let mut stack = vec![Element::new("")];
+    let mut rest = contents;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment
+                .find("-->")
+                .ok_or_else(|| malformed(&tr("unterminated comment")))?;
+            rest = &comment[end + 3..];
+        } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata
+                .find("]]>")
+                .ok_or_else(|| malformed(&tr("unterminated CDATA section")))?;
+            if let Some(top) = stack.last_mut() {
+                top.text.push_str(&cdata[..end]);
+            }
+            rest = &cdata[end + 3..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            let end = rest
+                .find('>')
+                .ok_or_else(|| malformed(&tr("unterminated declaration")))?;
+            rest = &rest[end + 1..];
+        } else if let Some(tag) = rest.strip_prefix("</") {
+            let end = tag
+                .find('>')
+                .ok_or_else(|| malformed(&tr("unterminated tag")))?;
+            let name = tag[..end].trim();
+            let element = match stack.pop() {
+                Some(element) if !stack.is_empty() && element.name == name => element,
+                _ => return Err(malformed(&trf("unexpected </{}>", &[&name]))),
+            };
+            if let Some(parent) = stack.last_mut() {
+                parent.children.push(element);
+            }
+            rest = &tag[end + 1..];
+        } else if let Some(tag) = rest.strip_prefix('<') {
+            let end = tag
+                .find('>')
+                .ok_or_else(|| malformed(&tr("unterminated tag")))?;
+            let (tag, empty) = match tag[..end].strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (&tag[..end], false),
+            };
+            let element = parse_tag(tag).ok_or_else(|| malformed(&trf("<{}>", &[&tag])))?;
+            if empty {
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(element);
+                }
+            } else {
+                stack.push(element);
+            }
+            rest = &rest[end + 2..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            if let Some(top) = stack.last_mut() {
+                top.text.push_str(&decode(&rest[..end]));
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err(malformed(&tr("unclosed elements")));
+    }
+    let mut document = stack.remove(0);
+    if document.children.len() != 1 {
+        return Err(malformed(&tr("there is not exactly one root element")));
+    }
+    Ok(document.children.remove(0))
+}
+
+/// Parses the name and attributes inside a start tag.
+fn parse_tag(tag: &str) -> Option<Element> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    if name_end == 0 {
+        return None;
+    }
+    let mut element = Element::new(&tag[..name_end]);
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let equals = rest.find('=')?;
+        let key = rest[..equals].trim();
+        let value = rest[equals + 1..].trim_start();
+        let quote = value.chars().next().filter(|x| *x == '"' || *x == '\'')?;
+        let end = value[1..].find(quote)?;
+        element
+            .attributes
+            .push((String::from(key), decode(&value[1..end + 1])));
+        rest = value[end + 2..].trim_start();
+    }
+    Some(element)
+}
+
+/// Replaces the entity and character references in `text`.
+fn decode(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|x| u32::from_str_radix(x, 16))
+                .or_else(|| entity.strip_prefix('#').map(|x| x.parse()))
+                .and_then(|x| x.ok())
+                .and_then(std::char::from_u32),
+        };
+        match character {
+            Some(character) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(id: &str, name: &str, categories: &[&str]) -> Application {
+        Application {
+            id: String::from(id),
+            name: String::from(name),
+            icon: None,
+            categories: categories.iter().map(|x| String::from(*x)).collect(),
+            command: vec![name.to_lowercase()],
+            actions: Vec::new(),
+        }
+    }
+
+    /// Translates the menu file `contents` over `apps`, returning the
+    /// strings of the root options, with the submenus' options in brackets.
+    fn strings(contents: &str, apps: &[Application]) -> (Vec<String>, Vec<String>) {
+        let mut warnings = Vec::new();
+        let menu = Menu::parse(parse_xml(contents).unwrap(), &mut warnings);
+        let mut allocated = vec![false; apps.len()];
+        menu.allocate(apps, &mut allocated);
+        let (_, _, menu) = menu.translate(apps, &allocated, &mut warnings).unwrap();
+        (describe(&menu), warnings)
+    }
+
+    fn describe(menu: &Value) -> Vec<String> {
+        menu["options"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|option| {
+                if let Some(separator) = option["separator"].as_str() {
+                    return format!("--{}", separator);
+                }
+                let string = option["string"].as_str().unwrap();
+                match option["action"]["type"].as_str() {
+                    Some("RofiMenu") => {
+                        format!("{} [{}]", string, describe(&option["action"]).join(", "))
+                    }
+                    _ => String::from(string),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_xml_reads_elements_attributes_and_entities() {
+        let root = parse_xml(
+            "<?xml version=\"1.0\"?>\n\
+             <!DOCTYPE Menu PUBLIC \"-//freedesktop//DTD Menu 1.0//EN\" \"menu.dtd\">\n\
+             <Menu><!-- a <comment> -->\n\
+               <Name>A &amp; B &#x41;&#66;</Name>\n\
+               <Merge type='files'/>\n\
+               <Name><![CDATA[<raw>]]></Name>\n\
+             </Menu>",
+        )
+        .unwrap();
+        assert_eq!(root.name, "Menu");
+        assert_eq!(root.children.len(), 3);
+        assert_eq!(root.children[0].text(), "A & B AB");
+        assert_eq!(root.children[1].attribute("type"), Some("files"));
+        assert_eq!(root.children[2].text(), "<raw>");
+    }
+
+    #[test]
+    fn parse_xml_rejects_malformed_documents() {
+        assert!(parse_xml("<Menu><Name></Menu>").is_err());
+        assert!(parse_xml("<Menu>").is_err());
+        assert!(parse_xml("<Menu/><Menu/>").is_err());
+        assert!(parse_xml("<Menu><!-- open").is_err());
+    }
+
+    #[test]
+    fn translate_applies_rules_and_merges_submenus() {
+        let apps = [
+            app("xterm.desktop", "XTerm", &["System", "TerminalEmulator"]),
+            app("firefox.desktop", "Firefox", &["Network"]),
+            app("htop.desktop", "Htop", &["System"]),
+            app("misc.desktop", "Misc", &[]),
+        ];
+        let (strings, warnings) = strings(
+            "<Menu>\n\
+               <Name>Applications</Name>\n\
+               <Menu>\n\
+                 <Name>System</Name>\n\
+                 <Include><Category>System</Category></Include>\n\
+                 <Exclude><And><Category>System</Category><Not><Category>TerminalEmulator</Category></Not></And></Exclude>\n\
+               </Menu>\n\
+               <Menu>\n\
+                 <Name>Internet</Name>\n\
+                 <Include><Filename>firefox.desktop</Filename></Include>\n\
+               </Menu>\n\
+               <Menu>\n\
+                 <Name>System</Name>\n\
+                 <Include><Filename>htop.desktop</Filename></Include>\n\
+               </Menu>\n\
+               <Menu>\n\
+                 <Name>Empty</Name>\n\
+               </Menu>\n\
+               <Menu>\n\
+                 <Name>Other</Name>\n\
+                 <OnlyUnallocated/>\n\
+                 <Include><All/></Include>\n\
+               </Menu>\n\
+             </Menu>",
+            &apps,
+        );
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(
+            strings,
+            vec!["Internet [Firefox]", "Other [Misc]", "System [Htop, XTerm]"]
+        );
+    }
+
+    #[test]
+    fn translate_follows_the_layout() {
+        let apps = [
+            app("a.desktop", "Alpha", &["Utility"]),
+            app("b.desktop", "Beta", &["Utility"]),
+            app("c.desktop", "Gamma", &["Game"]),
+        ];
+        let (strings, warnings) = strings(
+            "<Menu>\n\
+               <Name>Applications</Name>\n\
+               <Include><Category>Utility</Category></Include>\n\
+               <Menu>\n\
+                 <Name>Games</Name>\n\
+                 <Include><Category>Game</Category></Include>\n\
+               </Menu>\n\
+               <Layout>\n\
+                 <Filename>b.desktop</Filename>\n\
+                 <Separator/>\n\
+                 <Merge type=\"all\"/>\n\
+                 <Menuname>Games</Menuname>\n\
+                 <Bogus/>\n\
+               </Layout>\n\
+               <Bogus/>\n\
+             </Menu>",
+            &apps,
+        );
+        assert_eq!(strings, vec!["Beta", "--", "Alpha", "Games [Gamma]"]);
+        assert_eq!(
+            warnings,
+            vec![
+                "unknown element <Bogus>",
+                "<Bogus> in <Layout> is not supported"
+            ]
+        );
+    }
+}
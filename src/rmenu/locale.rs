@@ -0,0 +1,262 @@
+use std::env;
+use std::fmt;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde_yaml::{Mapping, Value};
+
+use super::get_config;
+
+/// The key of the text used when none matches the locale.
+const DEFAULT_KEY: &str = "default";
+
+/// The directory of the message catalogs, inside `$XDG_CONFIG_HOME/i3utils`.
+const CATALOG_DIR: &str = "messages";
+
+/// The message catalogs of the locale, from the most to the least specific.
+static CATALOGS: OnceLock<Vec<Mapping>> = OnceLock::new();
+
+/// Returns the locale names to look localized texts up with, from the most
+/// to the least specific, as described by the desktop entry specification.
+///
+/// `de_AT.UTF-8@euro` gives `de_AT@euro`, `de_AT`, `de@euro` and `de`.
+pub fn locales() -> Vec<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|x| env::var(x).ok())
+        .find(|x| !x.is_empty())
+        .unwrap_or_default();
+    locales_of(&locale)
+}
+
+/// Returns the locale names matching `locale`, from the most to the least
+/// specific.
+fn locales_of(locale: &str) -> Vec<String> {
+    // The encoding is not part of the names: `de_AT.UTF-8@euro` is `de_AT@euro`.
+    let (locale, modifier) = match locale.find('@') {
+        Some(index) => (&locale[..index], Some(&locale[index + 1..])),
+        None => (locale, None),
+    };
+    let locale = locale.split('.').next().unwrap_or("");
+    let lang = locale.split('_').next().unwrap_or("");
+    if lang.is_empty() || lang == "C" || lang == "POSIX" {
+        return Vec::new();
+    }
+
+    let mut locales = Vec::new();
+    if let Some(modifier) = modifier {
+        if locale != lang {
+            locales.push(format!("{}@{}", locale, modifier));
+        }
+    }
+    if locale != lang {
+        locales.push(String::from(locale));
+    }
+    if let Some(modifier) = modifier {
+        locales.push(format!("{}@{}", lang, modifier));
+    }
+    locales.push(String::from(lang));
+    locales
+}
+
+/// Returns the locale name and text of `texts`, a map of locale names to
+/// texts, that best match the locale.
+///
+/// The text of the most specific matching locale is chosen, falling back to
+/// the `default` text and then to the first one.
+pub fn select(texts: &Mapping) -> Option<(&Value, &Value)> {
+    select_for(texts, &locales())
+}
+
+/// Returns the locale name and text of `texts` that best match `locales`.
+fn select_for<'a>(texts: &'a Mapping, locales: &[String]) -> Option<(&'a Value, &'a Value)> {
+    locales
+        .iter()
+        .map(String::as_str)
+        .chain(iter::once(DEFAULT_KEY))
+        .find_map(|locale| texts.iter().find(|(key, _)| key.as_str() == Some(locale)))
+        .or_else(|| texts.iter().next())
+}
+
+/// Returns the text of `value`, which is either a string or a map of locale
+/// names to strings such as `{en: Shutdown, de: Herunterfahren}`.
+pub fn localize(value: &Value) -> Option<&str> {
+    match value {
+        Value::Mapping(texts) => select(texts)?.1.as_str(),
+        value => value.as_str(),
+    }
+}
+
+/// Translates `message`, one of rmenu's own texts, with the message catalogs
+/// of the locale.
+///
+/// A catalog is a YAML map of messages to their translations, in
+/// `$XDG_CONFIG_HOME/i3utils/messages/LOCALE.yaml`. Messages without a
+/// translation are returned as they are. rmenu ships no catalogs; the keys
+/// are the English texts, such as those of the generated menus in `de.yaml`:
+///
+/// ```yaml
+/// window: Fenster
+/// workspace: Arbeitsfläche
+/// output: Ausgabe
+/// Focus: Fokussieren
+/// Move to current workspace: Auf die aktuelle Arbeitsfläche verschieben
+/// New workspace: Neue Arbeitsfläche
+/// Rename: Umbenennen
+/// rename {}: "{} umbenennen"
+/// Move to output: Auf eine Ausgabe verschieben
+/// Move focused container here: Fokussierten Container hierher verschieben
+/// ```
+pub fn tr(message: &str) -> String {
+    let catalogs = CATALOGS.get_or_init(load_catalogs);
+    catalogs
+        .iter()
+        .find_map(|x| x.get(&Value::from(message))?.as_str())
+        .map_or_else(|| String::from(message), String::from)
+}
+
+/// Translates `template`, one of rmenu's own texts, and fills in `values`.
+///
+/// `{}` in a template stands for a text that varies, such as the name of a
+/// command, and is looked up as it is. The translation places the values at
+/// its own `{}`, in order, or at `{0}`, `{1}` and so on.
+pub fn trf(template: &str, values: &[&dyn fmt::Display]) -> String {
+    let values: Vec<String> = values.iter().map(|x| x.to_string()).collect();
+    let values: Vec<&str> = values.iter().map(String::as_str).collect();
+    fill(&tr(template), &values)
+}
+
+/// Loads the message catalogs of the locale that exist.
+fn load_catalogs() -> Vec<Mapping> {
+    let dir = match catalog_dir() {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+    let mut catalogs = Vec::new();
+    for locale in locales() {
+        let file = dir.join(format!("{}.yaml", locale));
+        if !file.exists() {
+            continue;
+        }
+        let filename = file.to_string_lossy();
+        match get_config(&filename) {
+            Ok(Value::Mapping(catalog)) => catalogs.push(catalog),
+            Ok(_) => eprintln!("rmenu: {} is not a map of messages", filename),
+            Err(e) => eprintln!("rmenu: {}: {}", filename, e),
+        }
+    }
+    catalogs
+}
+
+/// Returns the directory holding the message catalogs.
+fn catalog_dir() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|x| Path::new(&x).join(".config")))?;
+    Some(config_home.join("i3utils").join(CATALOG_DIR))
+}
+
+/// Replaces the `{}` and `{N}` of `translation` with `values`.
+fn fill(translation: &str, values: &[&str]) -> String {
+    let mut filled = String::new();
+    let mut next = 0;
+    let mut rest = translation;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let index = match &rest[1..end] {
+                "" => {
+                    next += 1;
+                    next - 1
+                }
+                index => index.parse().ok()?,
+            };
+            Some((end, values.get(index)?))
+        });
+        match value {
+            Some((end, value)) => {
+                filled.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `yaml` as a map of locale names to texts.
+    fn texts(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    /// Returns the text of `texts` selected for `locales`.
+    fn selected<'a>(texts: &'a Mapping, locales: &[&str]) -> Option<&'a str> {
+        let locales: Vec<String> = locales.iter().map(|x| String::from(*x)).collect();
+        select_for(texts, &locales)?.1.as_str()
+    }
+
+    #[test]
+    fn locales_of_falls_back_from_the_most_specific_name() {
+        assert_eq!(
+            locales_of("de_AT.UTF-8@euro"),
+            vec!["de_AT@euro", "de_AT", "de@euro", "de"]
+        );
+        assert_eq!(locales_of("de_AT.UTF-8"), vec!["de_AT", "de"]);
+        assert_eq!(locales_of("de@euro"), vec!["de@euro", "de"]);
+        assert_eq!(locales_of("de"), vec!["de"]);
+    }
+
+    #[test]
+    fn locales_of_ignores_the_c_locale() {
+        assert!(locales_of("").is_empty());
+        assert!(locales_of("C").is_empty());
+        assert!(locales_of("C.UTF-8").is_empty());
+        assert!(locales_of("POSIX").is_empty());
+    }
+
+    #[test]
+    fn select_for_prefers_the_most_specific_locale() {
+        let texts = texts("{de: Deutsch, de_AT: Österreichisch, default: Default}");
+        let locales = ["de_AT@euro", "de_AT", "de@euro", "de"];
+        assert_eq!(selected(&texts, &locales), Some("Österreichisch"));
+        assert_eq!(selected(&texts, &["de_CH", "de"]), Some("Deutsch"));
+    }
+
+    #[test]
+    fn select_for_falls_back_to_the_default_and_then_the_first_text() {
+        let texts_with_default = texts("{fr: Français, default: Default}");
+        assert_eq!(selected(&texts_with_default, &["de"]), Some("Default"));
+        assert_eq!(selected(&texts_with_default, &[]), Some("Default"));
+        let texts_without_default = texts("{fr: Français, it: Italiano}");
+        assert_eq!(selected(&texts_without_default, &["de"]), Some("Français"));
+        assert_eq!(selected(&Mapping::new(), &["de"]), None);
+    }
+
+    #[test]
+    fn localize_takes_plain_strings_and_maps() {
+        assert_eq!(localize(&Value::from("Shutdown")), Some("Shutdown"));
+        assert_eq!(localize(&Value::from(1)), None);
+        // No locale is named `qx`, whatever the locale of the tests.
+        let value = Value::Mapping(texts("{qx: Other, default: Default}"));
+        assert_eq!(localize(&value), Some("Default"));
+        let value = Value::Mapping(texts("{qx: Other, qy: Another}"));
+        assert_eq!(localize(&value), Some("Other"));
+    }
+
+    #[test]
+    fn fill_places_values_in_order_or_by_index() {
+        assert_eq!(fill("{} of {}", &["1", "2"]), "1 of 2");
+        assert_eq!(fill("{1} von {0}", &["1", "2"]), "2 von 1");
+        assert_eq!(fill("{2} {x} {", &["1"]), "{2} {x} {");
+    }
+}
@@ -0,0 +1,229 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::desktop::{data_dirs, desktop_files, mime_handlers, DesktopFile};
+use super::policy::matches;
+
+/// The type of the files no glob matches.
+const DEFAULT_TYPE: &str = "application/octet-stream";
+
+/// The type of directories.
+const DIRECTORY_TYPE: &str = "inode/directory";
+
+/// The prefix of the types of URL schemes, followed by the scheme.
+const SCHEME_TYPE_PREFIX: &str = "x-scheme-handler/";
+
+/// The groups of `mimeapps.list`.
+const DEFAULT_GROUP: &str = "Default Applications";
+const ADDED_GROUP: &str = "Added Associations";
+const REMOVED_GROUP: &str = "Removed Associations";
+
+/// Returns the MIME type of the file at `path`, guessed from its name with
+/// the globs of the shared MIME-info database.
+///
+/// Among the matching globs, the one with the highest weight wins, and then
+/// the longest one.
+pub fn file_type(path: &Path) -> String {
+    if path.is_dir() {
+        return String::from(DIRECTORY_TYPE);
+    }
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return String::from(DEFAULT_TYPE),
+    };
+    let mut best: Option<(u32, usize, String)> = None;
+    for dir in data_dirs() {
+        let globs = match fs::read_to_string(dir.join("mime").join("globs2")) {
+            Ok(globs) => globs,
+            Err(_) => continue,
+        };
+        // Lines are `weight:type:glob`, followed by flags.
+        for line in globs.lines().filter(|x| !x.starts_with('#')) {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 3 {
+                continue;
+            }
+            let (weight, mime_type, glob) = (fields[0], fields[1], fields[2]);
+            let weight = weight.parse().unwrap_or(50);
+            let case_sensitive = fields
+                .get(3)
+                .is_some_and(|x| x.split(',').any(|x| x == "cs"));
+            let matched = if case_sensitive {
+                matches(glob, &name, false)
+            } else {
+                matches(&glob.to_lowercase(), &name.to_lowercase(), false)
+            };
+            let better = match &best {
+                Some((best_weight, best_len, _)) => {
+                    (weight, glob.len()) > (*best_weight, *best_len)
+                }
+                None => true,
+            };
+            if matched && better {
+                best = Some((weight, glob.len(), String::from(mime_type)));
+            }
+        }
+    }
+    best.map_or_else(|| String::from(DEFAULT_TYPE), |(_, _, x)| x)
+}
+
+/// Returns the MIME type of `url`, which is that of its scheme.
+pub fn url_type(url: &str) -> String {
+    let scheme = url.split(':').next().unwrap_or_default();
+    format!("{}{}", SCHEME_TYPE_PREFIX, scheme.to_lowercase())
+}
+
+/// Returns the id of the desktop entry of the application opening files of
+/// `mime_type`, as the `mimeapps.list` files and the desktop entries
+/// configure it.
+///
+/// The installed default applications come first, then the added
+/// associations and last the applications declaring the type, leaving out
+/// the removed associations.
+pub fn default_handler(mime_type: &str) -> Option<String> {
+    let lists: Vec<DesktopFile> = mimeapps_lists()
+        .into_iter()
+        .filter_map(|x| fs::read_to_string(x).ok())
+        .map(|x| DesktopFile::parse(&x))
+        .collect();
+    let installed: Vec<String> = desktop_files().into_iter().map(|(id, _)| id).collect();
+    let is_installed = |id: &&str| installed.iter().any(|x| x == id);
+
+    let default = lists
+        .iter()
+        .flat_map(|x| x.get_list(DEFAULT_GROUP, mime_type))
+        .find(is_installed);
+    if let Some(default) = default {
+        return Some(String::from(default));
+    }
+    let removed: Vec<&str> = lists
+        .iter()
+        .flat_map(|x| x.get_list(REMOVED_GROUP, mime_type))
+        .collect();
+    let added = lists
+        .iter()
+        .flat_map(|x| x.get_list(ADDED_GROUP, mime_type))
+        .filter(|x| !removed.contains(x))
+        .find(is_installed);
+    if let Some(added) = added {
+        return Some(String::from(added));
+    }
+    mime_handlers(mime_type)
+        .into_iter()
+        .find(|x| !removed.contains(&x.as_str()))
+}
+
+/// Returns the paths of the `mimeapps.list` files, the most important first,
+/// as described by the MIME applications associations specification.
+fn mimeapps_lists() -> Vec<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|x| Path::new(&x).join(".config")));
+    let config_dirs = env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| String::from("/etc/xdg"));
+    let dirs = config_home
+        .into_iter()
+        .chain(config_dirs.split(':').map(PathBuf::from))
+        .chain(data_dirs().into_iter().map(|x| x.join("applications")));
+
+    let desktops: Vec<String> = env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|x| !x.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let mut lists = Vec::new();
+    for dir in dirs {
+        for desktop in &desktops {
+            lists.push(dir.join(format!("{}-mimeapps.list", desktop)));
+        }
+        lists.push(dir.join("mimeapps.list"));
+    }
+    lists
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Writes `contents` into the file at `path`, creating its directory.
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// Returns a desktop entry of `name` opening files of `mime_types`.
+    fn entry(name: &str, mime_types: &str, hidden: bool) -> String {
+        format!(
+            "[Desktop Entry]\nType=Application\nName={}\nExec={} %f\nMimeType={}\nHidden={}\n",
+            name, name, mime_types, hidden
+        )
+    }
+
+    // The XDG directories are only set by this test, as the environment is
+    // shared by the tests running alongside.
+    #[test]
+    fn types_and_handlers_follow_the_xdg_directories() {
+        let dir = env::temp_dir().join(format!("rmenu-mime-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let data = dir.join("data");
+        let apps = data.join("applications");
+        write(
+            &apps.join("viewer.desktop"),
+            &entry("viewer", "image/png;", false),
+        );
+        write(
+            &apps.join("editor.desktop"),
+            &entry("editor", "image/png;text/plain;", false),
+        );
+        write(
+            &apps.join("hidden.desktop"),
+            &entry("hidden", "video/mp4;", true),
+        );
+        write(
+            &data.join("mime").join("globs2"),
+            "# weight:type:glob\n50:image/png:*.png\n50:text/x-csrc:*.c:cs\n80:text/x-readme:README*\n",
+        );
+        write(
+            &dir.join("config").join("mimeapps.list"),
+            "[Default Applications]\ntext/plain=missing.desktop;editor.desktop;\n\n\
+             [Added Associations]\napplication/pdf=viewer.desktop;\n\n\
+             [Removed Associations]\nimage/png=viewer.desktop;\n",
+        );
+        env::set_var("XDG_DATA_HOME", dir.join("data-home"));
+        env::set_var("XDG_DATA_DIRS", &data);
+        env::set_var("XDG_CONFIG_HOME", dir.join("config"));
+        env::set_var("XDG_CONFIG_DIRS", dir.join("config-dirs"));
+        env::remove_var("XDG_CURRENT_DESKTOP");
+
+        assert_eq!(file_type(Path::new("photo.PNG")), "image/png");
+        assert_eq!(file_type(Path::new("main.c")), "text/x-csrc");
+        assert_eq!(file_type(Path::new("main.C")), DEFAULT_TYPE);
+        assert_eq!(file_type(Path::new("README.png")), "text/x-readme");
+        assert_eq!(file_type(&data), DIRECTORY_TYPE);
+        assert_eq!(url_type("HTTPS://example.org"), "x-scheme-handler/https");
+
+        // Defaults that are not installed are skipped.
+        assert_eq!(
+            default_handler("text/plain").as_deref(),
+            Some("editor.desktop")
+        );
+        // Removed associations leave the other declared handlers.
+        assert_eq!(
+            default_handler("image/png").as_deref(),
+            Some("editor.desktop")
+        );
+        assert_eq!(
+            default_handler("application/pdf").as_deref(),
+            Some("viewer.desktop")
+        );
+        // Hidden entries do not declare types.
+        assert_eq!(default_handler("video/mp4"), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::desktop::open_command;
+use super::locale::{tr, trf};
+use super::mime::{default_handler, file_type, url_type};
+use super::{Error, RofiAction, RofiCommand};
+
+/// An action opening a URL with the application configured for its scheme,
+/// such as `x-scheme-handler/https`.
+pub struct OpenUrl {
+    url: String,
+    /// The terminal emulator running applications that need one.
+    terminal: String,
+    /// The desktop entry id of the application opening the URL, looked up
+    /// when the action runs if it is not set.
+    handler: Option<String>,
+}
+
+impl OpenUrl {
+    pub fn new(url: String, terminal: String) -> OpenUrl {
+        OpenUrl {
+            url,
+            terminal,
+            handler: None,
+        }
+    }
+
+    /// Looks up the application opening the URL now rather than when the
+    /// action runs, and returns the command it will run.
+    pub fn resolve_handler(&mut self) -> Result<Vec<String>, Error> {
+        let handler = default_handler_of(&self.url, &self.mime_type())?;
+        let command = open_command_of(&handler, &self.url, &self.terminal)?;
+        self.handler = Some(handler);
+        Ok(command)
+    }
+
+    /// Returns the MIME type the URL is opened as.
+    fn mime_type(&self) -> String {
+        // Local files are opened according to their own type.
+        match self.url.strip_prefix("file://") {
+            Some(path) => file_type(Path::new(path)),
+            None => url_type(&self.url),
+        }
+    }
+}
+
+impl RofiAction for OpenUrl {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        let handler = match &self.handler {
+            Some(handler) => handler.clone(),
+            None => default_handler_of(&self.url, &self.mime_type())?,
+        };
+        open(&handler, &self.url, &self.terminal)?;
+        Ok(None)
+    }
+}
+
+/// An action opening a file with the application configured for its MIME
+/// type.
+pub struct OpenFile {
+    /// The path of the file, where a leading `~` stands for the home
+    /// directory.
+    path: String,
+    /// The MIME type of the file, guessed from its name if it is not set.
+    mime_type: Option<String>,
+    /// The terminal emulator running applications that need one.
+    terminal: String,
+    /// The desktop entry id of the application opening the file, looked up
+    /// when the action runs if it is not set.
+    handler: Option<String>,
+}
+
+impl OpenFile {
+    pub fn new(path: String, terminal: String) -> OpenFile {
+        OpenFile {
+            path,
+            mime_type: None,
+            terminal,
+            handler: None,
+        }
+    }
+
+    /// Sets the MIME type of the file, instead of guessing it.
+    pub fn set_mime_type(&mut self, mime_type: Option<String>) {
+        self.mime_type = mime_type;
+    }
+
+    /// Looks up the application opening the file now rather than when the
+    /// action runs, and returns the command it will run.
+    pub fn resolve_handler(&mut self) -> Result<Vec<String>, Error> {
+        let path = self.expanded_path()?;
+        let target = path.to_string_lossy();
+        let handler = default_handler_of(&target, &self.mime_type_of(&path))?;
+        let command = open_command_of(&handler, &target, &self.terminal)?;
+        self.handler = Some(handler);
+        Ok(command)
+    }
+
+    /// Returns the path of the file, with a leading `~` expanded.
+    fn expanded_path(&self) -> Result<PathBuf, Error> {
+        match self.path.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                let home =
+                    env::var_os("HOME").ok_or_else(|| Error::Failed(tr("HOME is not set")))?;
+                Ok(PathBuf::from(format!("{}{}", home.to_string_lossy(), rest)))
+            }
+            _ => Ok(PathBuf::from(&self.path)),
+        }
+    }
+
+    /// Returns the MIME type the file at `path` is opened as.
+    fn mime_type_of(&self, path: &Path) -> String {
+        self.mime_type.clone().unwrap_or_else(|| file_type(path))
+    }
+}
+
+impl RofiAction for OpenFile {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        let path = self.expanded_path()?;
+        if !path.exists() {
+            return Err(Error::io(
+                trf("No such file {}", &[&path.display()]),
+                io::ErrorKind::NotFound.into(),
+            ));
+        }
+        let target = path.to_string_lossy();
+        let handler = match &self.handler {
+            Some(handler) => handler.clone(),
+            None => default_handler_of(&target, &self.mime_type_of(&path))?,
+        };
+        open(&handler, &target, &self.terminal)?;
+        Ok(None)
+    }
+}
+
+/// Returns the desktop entry id of the application configured for
+/// `mime_type`, which opens `target`.
+fn default_handler_of(target: &str, mime_type: &str) -> Result<String, Error> {
+    default_handler(mime_type)
+        .ok_or_else(|| Error::Failed(trf("No application opens {} ({})", &[&target, &mime_type])))
+}
+
+/// Returns the command with which the application `handler` opens `target`.
+fn open_command_of(handler: &str, target: &str, terminal: &str) -> Result<Vec<String>, Error> {
+    open_command(handler, target, terminal)
+        .filter(|x| !x.is_empty())
+        .ok_or_else(|| Error::Failed(trf("Could not read the command of {}", &[&handler])))
+}
+
+/// Opens `target` in the background with the application `handler`.
+fn open(handler: &str, target: &str, terminal: &str) -> Result<(), Error> {
+    let mut command = open_command_of(handler, target, terminal)?;
+    let program = command.remove(0);
+    RofiCommand::detached(program, command).run()?;
+    Ok(())
+}
@@ -0,0 +1,382 @@
+use std::io::prelude::*;
+
+use std::env;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use serde_yaml::Value;
+
+use super::cache::{Cache, Source};
+use super::error::EXIT_COMMAND_FAILED;
+use super::locale::{tr, trf};
+use super::{process, Builder, Error, RofiAction};
+
+/// The version of the protocol spoken with plugins.
+pub const PROTOCOL_VERSION: u64 = 1;
+
+/// The prefix of plugin executable names, followed by the action type.
+const PLUGIN_PREFIX: &str = "rmenu-plugin-";
+
+/// How long a plugin may take to reply to a build request, unless configured
+/// otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a plugin may take to run an action, unless its node sets
+/// `run_timeout`.
+const DEFAULT_RUN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A struct for finding and calling external plugins that provide action
+/// types.
+///
+/// A plugin for type `<type>` is an executable named `rmenu-plugin-<type>`,
+/// looked up in the plugin directories and then in `$PATH`. It receives a
+/// request as a JSON object on its stdin:
+///
+/// ```json
+/// {"version": 1, "request": "build", "node": {"type": "<type>", ...}}
+/// ```
+///
+/// where `node` is the YAML description of the action. It must reply on its
+/// stdout with one JSON object, which is one of:
+///
+/// - `{"version": 1, "action": {...}}` with an action subtree, such as a
+///   `RofiMenu` or a `RofiCommand`, that replaces the node;
+/// - `{"version": 1, "run": true}` to be called again with a `"run"` request
+///   when the action is selected;
+/// - `{"version": 1, "error": "..."}` to report a failure.
+///
+/// A `"run"` request is answered with `{"version": 1}` on success, or with an
+/// error. Since it runs on behalf of the user, it has longer than a build:
+/// the `run_timeout` of the node in seconds, or a minute. A `run_timeout` of
+/// `null` lets it take as long as it needs.
+pub struct Plugins {
+    /// The directories searched before `$PATH`.
+    dirs: Vec<PathBuf>,
+    /// How long a plugin may take to reply to a build request.
+    timeout: Duration,
+}
+
+impl Default for Plugins {
+    fn default() -> Plugins {
+        Plugins::new()
+    }
+}
+
+impl Plugins {
+    /// Creates a plugin registry searching `$XDG_CONFIG_HOME/i3utils/plugins`
+    /// and `$PATH`.
+    pub fn new() -> Plugins {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|x| Path::new(&x).join(".config")));
+        let dirs = config_home
+            .map(|x| vec![x.join("i3utils").join("plugins")])
+            .unwrap_or_default();
+        Plugins {
+            dirs,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Searches `dir` for plugins, before any directory already known.
+    pub fn add_dir(&mut self, dir: PathBuf) {
+        self.dirs.insert(0, dir);
+    }
+
+    /// Sets how long a plugin may take to reply to a build request.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the path of the plugin providing `type_str`, if there is one.
+    pub fn find(&self, type_str: &str) -> Option<PathBuf> {
+        let name = format!("{}{}", PLUGIN_PREFIX, type_str);
+        self.dirs
+            .iter()
+            .map(|x| x.join(&name))
+            .find(|x| process::is_executable(x))
+            .or_else(|| process::find_executable(&name))
+    }
+
+    /// Builds an action of type `type_str` through its plugin, reusing the
+    /// reply cached in `cache` if there is one.
+    pub fn build(
+        &self,
+        builder: &Builder,
+        type_str: &str,
+        yaml: &serde_yaml::Value,
+        cache: Option<Cache>,
+    ) -> Result<Box<dyn RofiAction>, Error> {
+        let path = self
+            .find(type_str)
+            .ok_or_else(|| Error::Invalid(trf("Unknown type {}", &[&type_str])))?;
+        builder.apply_policy(&path.to_string_lossy(), &[], false)?;
+        let mut plugin = Plugin::new(
+            path,
+            serde_json::to_value(yaml)
+                .map_err(|e| Error::json(tr("Action is not valid JSON"), e))?,
+            self.timeout,
+        );
+        plugin.set_run_timeout(match yaml.get("run_timeout") {
+            None => Some(DEFAULT_RUN_TIMEOUT),
+            Some(Value::Null) => None,
+            Some(timeout) => Some(
+                timeout
+                    .as_f64()
+                    .filter(|x| x.is_finite() && *x >= 0.0)
+                    .map(Duration::from_secs_f64)
+                    .ok_or_else(|| Error::Invalid(tr("run_timeout is not a number of seconds")))?,
+            ),
+        });
+
+        let reply = match cache {
+            Some(cache) => cache.get(&Source::Plugin {
+                path: plugin.path.clone(),
+                node: plugin.node.clone(),
+                timeout: plugin.timeout,
+            })?,
+            None => plugin.call("build")?,
+        };
+        if let Some(action) = reply.get("action") {
+            let action: serde_yaml::Value = serde_json::from_value(action.clone())
+                .map_err(|e| Error::json(plugin.error(&tr("invalid action")), e))?;
+            return builder.build_action(&action);
+        }
+        match reply.get("run").and_then(|x| x.as_bool()) {
+            Some(true) => Ok(Box::new(plugin)),
+            _ => Err(Error::Invalid(
+                plugin.error(&tr("reply has neither an action nor a run result")),
+            )),
+        }
+    }
+}
+
+/// An action that is run by its plugin when selected.
+pub struct Plugin {
+    /// The path of the plugin executable.
+    path: PathBuf,
+    /// The description of the action passed to the plugin.
+    node: serde_json::Value,
+    /// How long the plugin may take to reply to a build request.
+    timeout: Duration,
+    /// How long the plugin may take to run the action, if it is limited.
+    run_timeout: Option<Duration>,
+}
+
+impl Plugin {
+    pub fn new(path: PathBuf, node: serde_json::Value, timeout: Duration) -> Plugin {
+        Plugin {
+            path,
+            node,
+            timeout,
+            run_timeout: Some(DEFAULT_RUN_TIMEOUT),
+        }
+    }
+
+    /// Sets how long the plugin may take to run the action, if it is limited.
+    pub fn set_run_timeout(&mut self, timeout: Option<Duration>) {
+        self.run_timeout = timeout;
+    }
+
+    /// Formats an error message about the plugin from a translated one.
+    fn error(&self, message: &str) -> String {
+        trf("Plugin {}: {}", &[&self.path.display(), &message])
+    }
+
+    /// Sends a request to the plugin and returns its checked reply.
+    pub fn call(&self, request: &str) -> Result<serde_json::Value, Error> {
+        let input = json!({
+            "version": PROTOCOL_VERSION,
+            "request": request,
+            "node": self.node,
+        });
+        let timeout = match request {
+            "run" => self.run_timeout,
+            _ => Some(self.timeout),
+        };
+        let output = self.output(input.to_string().as_bytes(), timeout)?;
+        let reply: serde_json::Value = serde_json::from_slice(&output)
+            .map_err(|e| Error::json(self.error(&tr("invalid reply")), e))?;
+
+        match reply.get("version").and_then(|x| x.as_u64()) {
+            Some(PROTOCOL_VERSION) => (),
+            Some(v) => {
+                return Err(Error::Invalid(self.error(&trf(
+                    "speaks protocol version {}, expected {}",
+                    &[&v, &PROTOCOL_VERSION],
+                ))))
+            }
+            None => {
+                return Err(Error::Invalid(
+                    self.error(&tr("reply has no protocol version")),
+                ))
+            }
+        }
+        if let Some(e) = reply.get("error") {
+            let e = e.as_str().map_or(e.to_string(), String::from);
+            return Err(Error::Failed(self.error(&e)));
+        }
+        Ok(reply)
+    }
+
+    /// Runs the plugin with `input` on its stdin and returns its stdout,
+    /// killing it along with what it started if it does not exit within
+    /// `timeout`.
+    fn output(&self, input: &[u8], timeout: Option<Duration>) -> Result<Vec<u8>, Error> {
+        let mut command = Command::new(&self.path);
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if timeout.is_some() {
+            command.process_group(0);
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| Error::spawn(&self.path.to_string_lossy(), e))?;
+
+        // The pipes are serviced from their own threads, so that a plugin
+        // filling one of them can not block us while we wait for it.
+        let mut stdin = process::pipe(child.stdin.take(), "stdin")?;
+        let input = input.to_vec();
+        thread::spawn(move || stdin.write_all(&input));
+        let stdout = process::PipeReader::spawn(process::pipe(child.stdout.take(), "stdout")?);
+        let stderr = process::PipeReader::spawn(process::pipe(child.stderr.take(), "stderr")?);
+
+        let deadline = timeout.map(|x| Instant::now() + x);
+        let status = match process::wait_until(&mut child, deadline) {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                process::kill_groups(&mut [child]);
+                return Err(Error::TimedOut {
+                    command: self.path.to_string_lossy().into_owned(),
+                    timeout: timeout.unwrap_or_default(),
+                });
+            }
+            Err(e) => return Err(Error::io(self.error(&tr("failed to wait")), e)),
+        };
+        // What the plugin started may still hold its pipes. It is stopped
+        // along with its group, if it has one, once the plugin has exited.
+        let pipe_deadline = process::pipe_deadline(deadline);
+        let closed = stdout.wait_closed(pipe_deadline) && stderr.wait_closed(pipe_deadline);
+        if !closed && timeout.is_some() {
+            process::kill_groups(&mut [child]);
+            stdout.wait_closed(process::pipe_deadline(None));
+            stderr.wait_closed(process::pipe_deadline(None));
+        }
+
+        if !status.success() {
+            let stderr = stderr.take();
+            let stderr = String::from_utf8_lossy(&stderr);
+            return Err(Error::Reported {
+                exit_code: EXIT_COMMAND_FAILED,
+                message: self.error(&format!("{}: {}", status, stderr.trim())),
+            });
+        }
+        Ok(stdout.take())
+    }
+}
+
+impl RofiAction for Plugin {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        self.call("run")?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    /// Returns a builder finding the plugin `rmenu-plugin-test`, a shell
+    /// script running `script`, in a new directory, which is returned too.
+    fn builder_with(script: &str) -> (Builder, PathBuf) {
+        let dir = env::temp_dir().join(format!(
+            "rmenu-plugin-test-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rmenu-plugin-test");
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut builder = Builder::new();
+        builder.plugins_mut().add_dir(dir.clone());
+        (builder, dir)
+    }
+
+    /// Builds an action of the plugin type with `builder`.
+    fn build(builder: &Builder) -> Result<Box<dyn RofiAction>, Error> {
+        let yaml: Value = serde_yaml::from_str("{type: test, greeting: hi}").unwrap();
+        builder.build_action(&yaml)
+    }
+
+    #[test]
+    fn build_replaces_the_node_with_the_action_of_the_plugin() {
+        let (builder, dir) = builder_with(
+            r#"cat > "$(dirname "$0")/request"
+echo '{"version": 1, "action": {"type": "RofiCommand", "command": "echo", "args": ["hi"]}}'"#,
+        );
+        let action = build(&builder).unwrap();
+        let command = action.as_command().unwrap();
+        assert_eq!(command.command(), "echo");
+        assert_eq!(command.args(), ["hi"]);
+        let request: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.join("request")).unwrap()).unwrap();
+        assert_eq!(
+            request,
+            json!({
+                "version": PROTOCOL_VERSION,
+                "request": "build",
+                "node": {"type": "test", "greeting": "hi"},
+            })
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn build_rejects_malformed_replies() {
+        let (builder, dir) = builder_with("echo '{\"version\": 1, \"action\": '");
+        assert!(matches!(build(&builder), Err(Error::Json { .. })));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (builder, dir) = builder_with("echo '{\"version\": 2, \"run\": true}'");
+        match build(&builder) {
+            Err(Error::Invalid(message)) => {
+                assert!(message.ends_with("speaks protocol version 2, expected 1"))
+            }
+            _ => panic!("the reply of another version was accepted"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (builder, dir) = builder_with("echo '{\"version\": 1, \"error\": \"no network\"}'");
+        match build(&builder) {
+            Err(e) => assert!(e.to_string().ends_with(": no network")),
+            Ok(_) => panic!("the error of the plugin was ignored"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn build_stops_plugins_at_the_timeout() {
+        let (mut builder, dir) = builder_with("sleep 5");
+        builder
+            .plugins_mut()
+            .set_timeout(Duration::from_millis(100));
+        let start = Instant::now();
+        assert!(matches!(build(&builder), Err(Error::TimedOut { .. })));
+        assert!(start.elapsed() < Duration::from_secs(3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
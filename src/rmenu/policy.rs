@@ -0,0 +1,205 @@
+use std::env;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+
+use super::locale::{tr, trf};
+use super::process::find_executable;
+use super::{get_config, Error};
+
+/// The name of the policy file, inside `$XDG_CONFIG_HOME/i3utils`.
+const POLICY_NAME: &str = "policy.yaml";
+
+/// Restrictions on the commands a config may run.
+///
+/// A policy is read from a YAML file such as:
+///
+/// ```yaml
+/// # Commands are matched by their resolved path. Without `allow`, every
+/// # command is allowed.
+/// allow:
+///   - /usr/bin/*
+///   - /home/me/bin/backup
+/// # No argument of any command may match these patterns.
+/// forbid_args:
+///   - --no-preserve-root
+///   - "*;*"
+/// # Commands matching these must ask for confirmation.
+/// confirm:
+///   - /usr/bin/systemctl
+/// ```
+///
+/// Patterns are shell globs, as understood by fnmatch(3).
+pub struct Policy {
+    /// The patterns of the allowed commands, if commands are restricted.
+    allow: Option<Vec<String>>,
+    /// The patterns no argument may match.
+    forbid_args: Vec<String>,
+    /// The patterns of the commands that must ask for confirmation.
+    confirm: Vec<String>,
+}
+
+impl Policy {
+    /// Returns the path of the policy file used when none is given.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|x| Path::new(&x).join(".config")))?;
+        Some(config_home.join("i3utils").join(POLICY_NAME))
+    }
+
+    /// Loads the policy file at `path`.
+    pub fn load(path: &Path) -> Result<Policy, Error> {
+        let filename = path
+            .to_str()
+            .ok_or_else(|| Error::Invalid(tr("Invalid policy file name")))?;
+        let yaml = get_config(filename)?;
+        let patterns = |key: &str| -> Result<Option<Vec<String>>, Error> {
+            yaml.get(key)
+                .map(|x| {
+                    x.as_sequence()
+                        .ok_or_else(|| Error::Invalid(trf("Policy {} is not a sequence", &[&key])))?
+                        .iter()
+                        .map(|x| {
+                            x.as_str().map(String::from).ok_or_else(|| {
+                                Error::Invalid(trf(
+                                    "Policy {} is not a sequence of strings",
+                                    &[&key],
+                                ))
+                            })
+                        })
+                        .collect()
+                })
+                .transpose()
+        };
+        Ok(Policy {
+            allow: patterns("allow")?,
+            forbid_args: patterns("forbid_args")?.unwrap_or_default(),
+            confirm: patterns("confirm")?.unwrap_or_default(),
+        })
+    }
+
+    /// Returns the ways in which running `name` with `args` breaks the
+    /// policy, where `confirmed` tells whether the user is asked first.
+    pub fn violations(&self, name: &str, args: &[String], confirmed: bool) -> Vec<String> {
+        let mut violations = Vec::new();
+        let path = find_executable(name);
+        let path = path.as_ref().and_then(|x| x.to_str());
+
+        if let Some(allow) = &self.allow {
+            match path {
+                Some(path) if allow.iter().any(|x| matches(x, path, true)) => (),
+                Some(path) => violations.push(trf("Command {} is not allowed", &[&path])),
+                None => violations.push(trf("Command {} was not found", &[&name])),
+            }
+        }
+        for arg in args {
+            if let Some(pattern) = self.forbid_args.iter().find(|x| matches(x, arg, false)) {
+                violations.push(trf(
+                    "Argument {} of command {} matches forbidden pattern {}",
+                    &[arg, &name, pattern],
+                ));
+            }
+        }
+        let path = path.unwrap_or(name);
+        if !confirmed && self.confirm.iter().any(|x| matches(x, path, true)) {
+            violations.push(trf("Command {} requires confirm", &[&path]));
+        }
+        violations
+    }
+}
+
+/// Returns whether `s` matches the glob `pattern`.
+///
+/// With `is_path`, wildcards do not match the `/` between path components.
+pub fn matches(pattern: &str, s: &str, is_path: bool) -> bool {
+    let (pattern, s) = match (CString::new(pattern), CString::new(s)) {
+        (Ok(pattern), Ok(s)) => (pattern, s),
+        _ => return false,
+    };
+    let flags = if is_path { libc::FNM_PATHNAME } else { 0 };
+    unsafe { libc::fnmatch(pattern.as_ptr(), s.as_ptr(), flags) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rmenu::Builder;
+
+    /// Returns the owned form of `patterns`.
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|x| String::from(*x)).collect()
+    }
+
+    #[test]
+    fn matches_globs_within_path_components() {
+        assert!(matches("/usr/bin/*", "/usr/bin/ls", true));
+        assert!(!matches("/usr/bin/*", "/usr/bin/x/ls", true));
+        assert!(matches("/usr/*", "/usr/bin/ls", false));
+        assert!(matches("/usr/bin/[lr]s", "/usr/bin/rs", true));
+        assert!(!matches("/usr/bin/?", "/usr/bin/ls", true));
+        assert!(matches("*;*", "a;b", false));
+        assert!(!matches("*;*", "ab", false));
+    }
+
+    #[test]
+    fn violations_follow_allow_forbid_args_and_confirm() {
+        let policy = Policy {
+            allow: Some(patterns(&["/bin/*"])),
+            forbid_args: patterns(&["--no-preserve-root", "*;*"]),
+            confirm: patterns(&["/bin/sh"]),
+        };
+        let args = patterns(&["-c", "true;false", "--no-preserve-root"]);
+        assert_eq!(
+            policy.violations("/bin/sh", &args, false),
+            vec![
+                "Argument true;false of command /bin/sh matches forbidden pattern *;*",
+                "Argument --no-preserve-root of command /bin/sh \
+                 matches forbidden pattern --no-preserve-root",
+                "Command /bin/sh requires confirm",
+            ]
+        );
+        assert!(policy.violations("/bin/sh", &[], true).is_empty());
+        assert_eq!(
+            policy.violations("/nonexistent/rmenu-test", &[], true),
+            vec!["Command /nonexistent/rmenu-test was not found"]
+        );
+    }
+
+    #[test]
+    fn violations_without_allow_accept_any_command() {
+        let policy = Policy {
+            allow: None,
+            forbid_args: Vec::new(),
+            confirm: Vec::new(),
+        };
+        assert!(policy
+            .violations("/nonexistent/rmenu-test", &patterns(&["x"]), false)
+            .is_empty());
+    }
+
+    #[test]
+    fn policy_applies_to_the_inner_command_of_elevated_commands() {
+        let command: serde_yaml::Value = serde_yaml::from_str(
+            "{type: RofiCommand, command: /bin/sh, args: [], elevate: pkexec}",
+        )
+        .unwrap();
+        let mut builder = Builder::new();
+        // Elevated commands are confirmed by default, which satisfies
+        // `confirm`.
+        builder.set_policy(Some(Policy {
+            allow: Some(patterns(&["/bin/sh"])),
+            forbid_args: Vec::new(),
+            confirm: patterns(&["/bin/sh"]),
+        }));
+        assert!(builder.build_action(&command).is_ok());
+        builder.set_policy(Some(Policy {
+            allow: Some(patterns(&["/usr/bin/pkexec"])),
+            forbid_args: Vec::new(),
+            confirm: Vec::new(),
+        }));
+        assert_eq!(
+            builder.check(&command).unwrap(),
+            vec!["Command /bin/sh is not allowed"]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::prelude::*;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::locale::trf;
+use super::Error;
+
+/// How long a timed out process group has to exit after `SIGTERM`, before
+/// it is sent `SIGKILL`.
+const KILL_DELAY: Duration = Duration::from_secs(2);
+
+/// How often a child is checked while waiting for it with a deadline.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long the pipes of a process are still read after it exits, for what
+/// it wrote just before.
+const PIPE_GRACE: Duration = Duration::from_millis(100);
+
+/// Waits for `child` to exit, until `deadline` if there is one.
+///
+/// Returns `None` if the deadline passed first.
+pub fn wait_until(child: &mut Child, deadline: Option<Instant>) -> io::Result<Option<ExitStatus>> {
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => return child.wait().map(Some),
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Returns the pipe `name` of a child spawned with it piped.
+pub fn pipe<T>(pipe: Option<T>, name: &str) -> Result<T, Error> {
+    pipe.ok_or_else(|| {
+        Error::io(
+            trf("Failed to open {}", &[&name]),
+            io::ErrorKind::BrokenPipe.into(),
+        )
+    })
+}
+
+/// Returns when to stop reading the pipes of a process that has just exited:
+/// shortly after, or at `deadline` if that comes first.
+pub fn pipe_deadline(deadline: Option<Instant>) -> Instant {
+    let grace = Instant::now() + PIPE_GRACE;
+    deadline.map_or(grace, |x| x.min(grace))
+}
+
+/// A pipe read from its own thread, so that a process filling it can not
+/// block while it is waited for.
+///
+/// Anything the process started in the background may hold the pipe open
+/// long after it exits, so the pipe is only waited for until a deadline.
+pub struct PipeReader {
+    /// What has been read, until it is taken.
+    buf: Arc<Mutex<Option<Vec<u8>>>>,
+    /// Disconnected once the pipe is closed.
+    closed: mpsc::Receiver<()>,
+}
+
+impl PipeReader {
+    /// Starts reading `pipe`.
+    pub fn spawn<R: Read + Send + 'static>(mut pipe: R) -> PipeReader {
+        let buf = Arc::new(Mutex::new(Some(Vec::new())));
+        let (sender, closed) = mpsc::channel::<()>();
+        let shared = Arc::clone(&buf);
+        thread::spawn(move || {
+            let _sender = sender;
+            let mut chunk = [0; 4096];
+            loop {
+                let n = match pipe.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                // Once taken, the output is still read, so that the writers
+                // neither block nor get SIGPIPE, but it is dropped.
+                if let Ok(mut buf) = shared.lock() {
+                    if let Some(buf) = buf.as_mut() {
+                        buf.extend_from_slice(&chunk[..n]);
+                    }
+                }
+            }
+        });
+        PipeReader { buf, closed }
+    }
+
+    /// Waits until every writer has closed the pipe, until `deadline`.
+    ///
+    /// Returns whether the pipe was closed.
+    pub fn wait_closed(&self, deadline: Instant) -> bool {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        matches!(
+            self.closed.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        )
+    }
+
+    /// Returns what has been read so far, dropping anything read later.
+    pub fn take(self) -> Vec<u8> {
+        self.buf
+            .lock()
+            .ok()
+            .and_then(|mut x| x.take())
+            .unwrap_or_default()
+    }
+}
+
+/// Stops the process groups led by `children`, which must have been spawned
+/// in their own groups.
+///
+/// The groups are sent `SIGTERM` first, then `SIGKILL` once their leader has
+/// exited or `KILL_DELAY` has passed, so that nothing they started is left
+/// behind.
+pub fn kill_groups(children: &mut [Child]) {
+    for child in children.iter() {
+        signal_group(child, libc::SIGTERM);
+    }
+    let deadline = Instant::now() + KILL_DELAY;
+    for child in children.iter_mut() {
+        while !has_exited(child) && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        signal_group(child, libc::SIGKILL);
+        let _ = child.wait();
+    }
+}
+
+/// Sends `signal` to the process group led by `child`.
+fn signal_group(child: &Child, signal: libc::c_int) {
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), signal);
+    }
+}
+
+/// Returns whether `child` has exited, without reaping it.
+///
+/// Until it is reaped, the id of its group can not be reused, so the group
+/// can still be signalled safely.
+fn has_exited(child: &Child) -> bool {
+    unsafe {
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        let options = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+        libc::waitid(libc::P_PID, child.id() as libc::id_t, &mut info, options) != 0
+            || info.si_pid() != 0
+    }
+}
+
+/// Returns the path of the executable `name`, looked up in `$PATH` unless it
+/// is absolute.
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name)).filter(|x| is_executable(x));
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|x| x.join(name))
+        .find(|x| is_executable(x))
+}
+
+/// Returns whether `path` is an executable file.
+pub fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn pipe_reader_stops_at_the_deadline() {
+        let mut child = Command::new("sh")
+            .args(["-c", "echo out; sleep 5 &"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = PipeReader::spawn(child.stdout.take().unwrap());
+        assert!(child.wait().unwrap().success());
+        let start = Instant::now();
+        assert!(!stdout.wait_closed(pipe_deadline(None)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(stdout.take(), b"out\n");
+    }
+
+    #[test]
+    fn pipe_reader_reads_until_the_pipe_is_closed() {
+        let mut child = Command::new("sh")
+            .args(["-c", "echo out"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = PipeReader::spawn(child.stdout.take().unwrap());
+        assert!(child.wait().unwrap().success());
+        assert!(stdout.wait_closed(Instant::now() + Duration::from_secs(5)));
+        assert_eq!(stdout.take(), b"out\n");
+    }
+}
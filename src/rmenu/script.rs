@@ -0,0 +1,309 @@
+use std::io::prelude::*;
+
+use std::env;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use super::locale::{tr, trf};
+use super::{audit, join_path, run_chain, split_path, Error, RofiAction, RofiMenu};
+
+/// Drives the menu as a rofi script-mode modi.
+///
+/// Rofi runs rmenu again every time an entry is selected. The path of the
+/// selected option is passed back in `ROFI_INFO` and the path of the displayed
+/// menu in `ROFI_DATA`. Menus, including those of generators, are printed as
+/// script-mode entries, while any other action is run in the background,
+/// which closes rofi. Actions asking for input in a window of their own can
+/// not run while rofi is shown, so they fail.
+pub fn run_script(root: &dyn RofiAction, selection: Option<&str>) -> Result<(), Error> {
+    let retv = env::var("ROFI_RETV").unwrap_or_default();
+    let data = env::var("ROFI_DATA").unwrap_or_default();
+    let path = match retv.as_str() {
+        "" | "0" => String::new(),
+        "1" => match env::var("ROFI_INFO") {
+            Ok(info) => info,
+            // Older rofi versions do not set `ROFI_INFO`, so look the
+            // selected row up in the displayed menu.
+            Err(_) => resolve(root, &split_path(&data), &mut |action| {
+                selected_path(action, &data, selection.unwrap_or_default())
+            })?,
+        },
+        // Custom entries and keybindings just redisplay the current menu.
+        _ => data,
+    };
+
+    resolve(root, &split_path(&path), &mut |action| {
+        if let Some(menu) = action.as_menu() {
+            return print_script_menu(menu, &path);
+        }
+        if action.needs_input() {
+            return Err(Error::Invalid(trf(
+                "{} asks for input, which can not be done in script mode",
+                &[&path],
+            )));
+        }
+        audit::begin(&path);
+        detach(action)
+    })
+}
+
+/// Runs `action` and what follows it in a session of its own, returning
+/// right away so that rofi does not wait for it.
+///
+/// The errors of the action, and its audit log entries, are written by the
+/// detached process.
+fn detach(action: &dyn RofiAction) -> Result<(), Error> {
+    // Rofi reads the output of the modi until it is closed, so the detached
+    // process must not hold it.
+    let null = File::open("/dev/null").map_err(|e| Error::io(tr("Failed to open /dev/null"), e))?;
+    match unsafe { libc::fork() } {
+        0 => {
+            unsafe {
+                libc::setsid();
+                libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+                libc::dup2(null.as_raw_fd(), libc::STDOUT_FILENO);
+            }
+            let code = match run_chain(action) {
+                Ok(()) | Err(Error::Cancelled) => 0,
+                Err(e) => {
+                    eprintln!("rmenu: {}", e);
+                    e.exit_code()
+                }
+            };
+            unsafe { libc::_exit(code) }
+        }
+        pid if pid > 0 => Ok(()),
+        _ => Err(Error::io(tr("Failed to fork"), io::Error::last_os_error())),
+    }
+}
+
+/// Calls `f` with the action reached by following `keys` from `action`.
+///
+/// Generators are run to follow the path into the menu they show, as it is
+/// generated again every time rofi runs rmenu.
+fn resolve<T>(
+    action: &dyn RofiAction,
+    keys: &[String],
+    f: &mut dyn FnMut(&dyn RofiAction) -> Result<T, Error>,
+) -> Result<T, Error> {
+    if let Some(menu) = action.generate() {
+        return resolve(&menu?, keys, f);
+    }
+    match keys.split_first() {
+        Some((key, rest)) => {
+            let next = action
+                .get(key)
+                .ok_or_else(|| Error::Invalid(trf("Menu has no option {}", &[&key])))?;
+            resolve(next, rest, f)
+        }
+        None => f(action),
+    }
+}
+
+/// Returns the path of the option shown as `row` in the menu `action`, which
+/// is at `path`.
+fn selected_path(action: &dyn RofiAction, path: &str, row: &str) -> Result<String, Error> {
+    let menu = action
+        .as_menu()
+        .ok_or_else(|| Error::Failed(tr("Script mode did not show a menu")))?;
+    let not_found = || Error::Invalid(trf("Menu has no option {}", &[&row]));
+    if menu.is_flat() {
+        let leaves = menu.leaves();
+        let index = menu
+            .leaf_labels(&leaves)
+            .iter()
+            .position(|x| x == row)
+            .ok_or_else(not_found)?;
+        return Ok(leaves[index]
+            .path()
+            .iter()
+            .fold(String::from(path), |acc, x| join_path(&acc, x)));
+    }
+    let index = menu
+        .labels()
+        .iter()
+        .position(|x| x == row)
+        .ok_or_else(not_found)?;
+    let key = menu.options().keys().nth(index).ok_or_else(not_found)?;
+    Ok(join_path(path, key))
+}
+
+/// Prints `menu` in rofi's script-mode format.
+fn print_script_menu(menu: &RofiMenu, path: &str) -> Result<(), Error> {
+    io::stdout()
+        .write_all(script_menu(menu, path).as_bytes())
+        .map_err(|e| Error::io(tr("Failed to write menu entries"), e))
+}
+
+/// Returns the script-mode entries of `menu`, which is at `path`.
+fn script_menu(menu: &RofiMenu, path: &str) -> String {
+    let mut output = format!(
+        "\0prompt\x1f{}\n\0data\x1f{}\n\0no-custom\x1ftrue\n",
+        menu.prompt(),
+        path
+    );
+    if let Some(mesg) = &menu.appearance().mesg {
+        output.push_str(&format!("\0message\x1f{}\n", mesg));
+    }
+    if menu.is_flat() {
+        let leaves = menu.leaves();
+        for (leaf, label) in leaves.iter().zip(menu.leaf_labels(&leaves)) {
+            let info = leaf
+                .path()
+                .iter()
+                .fold(String::from(path), |acc, x| join_path(&acc, x));
+            output.push_str(&format!(
+                "{}\0info\x1f{}\x1fmeta\x1f{}\n",
+                label,
+                info,
+                leaf.menus().join(" ")
+            ));
+        }
+    } else {
+        let count = menu.options().len();
+        for (i, (key, label)) in menu.options().keys().zip(menu.labels()).enumerate() {
+            for label in menu.separators_before(i) {
+                output.push_str(&format!("{}\0nonselectable\x1ftrue\n", label));
+            }
+            output.push_str(&format!("{}\0info\x1f{}", label, join_path(path, key)));
+            if let Some(icon) = menu.icon(key) {
+                output.push_str(&format!("\x1ficon\x1f{}", icon));
+            }
+            output.push('\n');
+        }
+        for label in menu.separators_before(count) {
+            output.push_str(&format!("{}\0nonselectable\x1ftrue\n", label));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rmenu::RofiCommand;
+    use linked_hash_map::LinkedHashMap;
+
+    /// Returns a command running `program`.
+    fn command(program: &str) -> Box<dyn RofiAction> {
+        Box::new(RofiCommand::new(String::from(program), Vec::new()))
+    }
+
+    /// Returns a menu named `name` of `options`.
+    fn menu(name: &str, options: Vec<(&str, Box<dyn RofiAction>)>, flat: bool) -> RofiMenu {
+        let options: LinkedHashMap<String, Box<dyn RofiAction>> = options
+            .into_iter()
+            .map(|(key, action)| (String::from(key), action))
+            .collect();
+        RofiMenu::new(String::from(name), String::from(name), options, flat)
+    }
+
+    /// A generator showing a menu with a single `a/b` option.
+    struct Generator;
+
+    impl RofiAction for Generator {
+        fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+            Ok(None)
+        }
+
+        fn generate(&self) -> Option<Result<RofiMenu, Error>> {
+            Some(Ok(menu("generated", vec![("a/b", command("b"))], false)))
+        }
+    }
+
+    /// Returns the menu `root > {System > {Power, Lock/Unlock}, Windows, Quit}`.
+    fn tree(flat: bool) -> RofiMenu {
+        let system = menu(
+            "system",
+            vec![
+                ("Power", command("poweroff")),
+                ("Lock/Unlock", command("lock")),
+            ],
+            false,
+        );
+        menu(
+            "root",
+            vec![
+                ("System", Box::new(system)),
+                ("Windows", Box::new(Generator)),
+                ("Quit", command("quit")),
+            ],
+            flat,
+        )
+    }
+
+    /// Returns the program of the command at `keys` in `root`.
+    fn program_at(root: &dyn RofiAction, keys: &[&str]) -> Result<String, Error> {
+        let keys: Vec<String> = keys.iter().map(|x| String::from(*x)).collect();
+        resolve(root, &keys, &mut |action| {
+            Ok(String::from(action.as_command().unwrap().command()))
+        })
+    }
+
+    #[test]
+    fn resolve_follows_menus_and_generators() {
+        let root = tree(false);
+        assert_eq!(program_at(&root, &["System", "Power"]).unwrap(), "poweroff");
+        assert_eq!(
+            program_at(&root, &["System", "Lock/Unlock"]).unwrap(),
+            "lock"
+        );
+        assert_eq!(program_at(&root, &["Windows", "a/b"]).unwrap(), "b");
+        assert!(matches!(
+            program_at(&root, &["System", "Reboot"]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn selected_path_joins_the_key_of_the_row() {
+        let root = tree(false);
+        assert_eq!(selected_path(&root, "", "Quit").unwrap(), "Quit");
+        let system = root.get("System").unwrap();
+        assert_eq!(
+            selected_path(system, "System", "Lock/Unlock").unwrap(),
+            "System/Lock\\/Unlock"
+        );
+        assert!(selected_path(&root, "", "Reboot").is_err());
+        assert!(selected_path(&*command("true"), "", "Quit").is_err());
+    }
+
+    #[test]
+    fn selected_path_finds_the_leaves_of_flat_menus() {
+        let root = tree(true);
+        assert_eq!(
+            selected_path(&root, "", "System › Lock/Unlock").unwrap(),
+            "System/Lock\\/Unlock"
+        );
+        assert_eq!(selected_path(&root, "", "Quit").unwrap(), "Quit");
+    }
+
+    #[test]
+    fn script_menu_lists_options_with_their_paths() {
+        let mut root = tree(false);
+        root.set_icon("Quit", String::from("exit"));
+        root.add_separator(2, String::new());
+        assert_eq!(
+            script_menu(&root, "Top"),
+            "\0prompt\x1froot\n\0data\x1fTop\n\0no-custom\x1ftrue\n\
+             System\0info\x1fTop/System\n\
+             Windows\0info\x1fTop/Windows\n\
+             ────────────\0nonselectable\x1ftrue\n\
+             Quit\0info\x1fTop/Quit\x1ficon\x1fexit\n"
+        );
+    }
+
+    #[test]
+    fn script_menu_lists_the_leaves_of_flat_menus() {
+        let root = tree(true);
+        assert_eq!(
+            script_menu(&root, ""),
+            "\0prompt\x1froot\n\0data\x1f\n\0no-custom\x1ftrue\n\
+             System › Power\0info\x1fSystem/Power\x1fmeta\x1fsystem\n\
+             System › Lock/Unlock\0info\x1fSystem/Lock\\/Unlock\x1fmeta\x1fsystem\n\
+             Windows\0info\x1fWindows\x1fmeta\x1f\n\
+             Quit\0info\x1fQuit\x1fmeta\x1f\n"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use std::io::prelude::*;
+
+use std::ffi::OsStr;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::process::{Child, Command, Stdio};
+use std::ptr;
+use std::str::FromStr;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+use super::locale::{tr, trf};
+use super::{process, Error, ROFI};
+
+/// The dialog program offering a masked entry.
+const ADIR: &str = "adir";
+
+/// The longest secret that can be entered, in bytes.
+const MAX_SECRET_LEN: usize = 4096;
+
+/// How a secret is asked for.
+#[derive(Clone, Copy)]
+pub enum SecretInput {
+    /// Rofi, with its `-password` option.
+    Rofi,
+    /// The masked entry of `adir`.
+    Adir,
+}
+
+impl FromStr for SecretInput {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SecretInput, Error> {
+        match s {
+            "rofi" => Ok(SecretInput::Rofi),
+            "adir" => Ok(SecretInput::Adir),
+            _ => Err(Error::Invalid(trf("Unknown secret input {}", &[&s]))),
+        }
+    }
+}
+
+/// A secret, such as a password, asked for each time a command runs and
+/// passed to it on its stdin or through an environment variable.
+///
+/// The secret never appears in the arguments of the command, and so neither
+/// in the audit log. It is kept in a buffer of its own, zeroed once the
+/// command has it. The copy Rust's `Command` makes of the environment is
+/// freed without being zeroed, so the stdin should be preferred.
+pub struct Secret {
+    prompt: String,
+    input: SecretInput,
+    /// The environment variable holding the secret, if it is not passed on
+    /// the stdin.
+    env: Option<String>,
+    /// Whether a newline follows the secret on the stdin, as when it is
+    /// typed.
+    newline: bool,
+}
+
+/// The value of a secret, zeroed when dropped.
+pub struct SecretValue {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl Secret {
+    pub fn new(prompt: String, input: SecretInput) -> Secret {
+        Secret {
+            prompt,
+            input,
+            env: None,
+            newline: true,
+        }
+    }
+
+    /// Passes the secret through the environment variable `env` instead of
+    /// the stdin.
+    pub fn set_env(&mut self, env: Option<String>) {
+        self.env = env;
+    }
+
+    /// The environment variable holding the secret, if it is not passed on
+    /// the stdin.
+    pub fn env(&self) -> Option<&str> {
+        self.env.as_deref()
+    }
+
+    /// Sets whether a newline follows the secret on the stdin.
+    pub fn set_newline(&mut self, newline: bool) {
+        self.newline = newline;
+    }
+
+    /// Asks for the secret, returning `None` if the prompt was dismissed.
+    pub fn read(&self) -> Result<Option<SecretValue>, Error> {
+        let (program, mut command) = match self.input {
+            SecretInput::Rofi => {
+                let mut command = Command::new(ROFI);
+                command
+                    .arg("-p")
+                    .arg(&self.prompt)
+                    .arg("-l")
+                    .arg("0")
+                    .arg("-dmenu")
+                    .arg("-password");
+                (ROFI, command)
+            }
+            SecretInput::Adir => {
+                let mut command = Command::new(ADIR);
+                command
+                    .arg("entry")
+                    .arg("--masked")
+                    .arg(format!("--prompt={}", self.prompt));
+                (ADIR, command)
+            }
+        };
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| Error::spawn(program, e))?;
+
+        // The secret is read into a buffer that never grows, so that no copy
+        // of it is left behind by a reallocation.
+        let mut value = SecretValue {
+            buf: vec![0; MAX_SECRET_LEN],
+            len: 0,
+        };
+        let mut stdout = process::pipe(child.stdout.take(), "stdout")?;
+        loop {
+            if value.len == value.buf.len() {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(Error::Failed(tr("The secret is too long")));
+            }
+            match stdout.read(&mut value.buf[value.len..]) {
+                Ok(0) => break,
+                Ok(n) => value.len += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::io(tr("Failed to read the secret"), e)),
+            }
+        }
+        let status = child
+            .wait()
+            .map_err(|e| Error::io(tr("Failed to wait for process"), e))?;
+        // A dismissed rofi fails, while a closed adir prints nothing.
+        if !status.success() || value.len == 0 {
+            return Ok(None);
+        }
+        if value.buf[value.len - 1] == b'\n' {
+            value.len -= 1;
+        }
+        Ok(Some(value))
+    }
+
+    /// Prepares `command` to receive `value`.
+    pub fn pass(&self, command: &mut Command, value: &SecretValue) {
+        match &self.env {
+            Some(env) => {
+                command.env(env, OsStr::from_bytes(value.as_bytes()));
+            }
+            None => {
+                command.stdin(Stdio::piped());
+            }
+        }
+    }
+
+    /// Writes `value` on the stdin of `child`, if it is passed there.
+    pub fn feed(&self, child: &mut Child, value: &SecretValue) -> Result<(), Error> {
+        if self.env.is_some() {
+            return Ok(());
+        }
+        let mut stdin = process::pipe(child.stdin.take(), "stdin")?;
+        let written = stdin.write_all(value.as_bytes()).and_then(|_| {
+            if self.newline {
+                stdin.write_all(b"\n")
+            } else {
+                Ok(())
+            }
+        });
+        match written {
+            // The command need not read its stdin.
+            Err(e) if e.kind() != ErrorKind::BrokenPipe => {
+                Err(Error::io(tr("Failed to write the secret"), e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl SecretValue {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Drop for SecretValue {
+    fn drop(&mut self) {
+        for byte in self.buf.iter_mut() {
+            // Volatile writes are not optimized away, although the buffer is
+            // never read again.
+            unsafe { ptr::write_volatile(byte, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
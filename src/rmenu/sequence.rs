@@ -0,0 +1,345 @@
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Instant;
+
+use super::audit::{self, Outcome};
+use super::locale::{tr, trf};
+use super::{ask_confirmation, process, run_chain, Error, RofiAction, RofiCommand};
+
+/// A struct for running several actions one after the other.
+pub struct Sequence {
+    /// The actions to be run, in order.
+    actions: Vec<Box<dyn RofiAction>>,
+    /// Whether the sequence stops at the first action that fails.
+    stop_on_error: bool,
+}
+
+impl Sequence {
+    pub fn new(actions: Vec<Box<dyn RofiAction>>, stop_on_error: bool) -> Sequence {
+        Sequence {
+            actions,
+            stop_on_error,
+        }
+    }
+}
+
+impl RofiAction for Sequence {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        let mut errors = Vec::new();
+        for action in &self.actions {
+            match run_chain(action.as_ref()) {
+                // A cancelled action stops the sequence without failing it.
+                Err(Error::Cancelled) => return Err(Error::Cancelled),
+                Err(e) if self.stop_on_error => return Err(e),
+                Err(e) => errors.push(e),
+                Ok(()) => (),
+            }
+        }
+        if errors.len() == 1 {
+            return Err(errors.remove(0));
+        }
+        if !errors.is_empty() {
+            let messages: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+            return Err(Error::Reported {
+                exit_code: errors[0].exit_code(),
+                message: trf(
+                    "{} of {} actions failed: {}",
+                    &[&errors.len(), &self.actions.len(), &messages.join("; ")],
+                ),
+            });
+        }
+        Ok(None)
+    }
+
+    fn needs_input(&self) -> bool {
+        self.actions.iter().any(|x| x.needs_input())
+    }
+}
+
+/// A struct for running commands with the output of each fed to the next.
+pub struct Pipeline {
+    /// The commands of the pipeline, in order.
+    commands: Vec<Box<dyn RofiAction>>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline of `commands`, which must all be `RofiCommand`s.
+    pub fn new(commands: Vec<Box<dyn RofiAction>>) -> Result<Pipeline, Error> {
+        if commands.is_empty() {
+            return Err(Error::Invalid(tr("Pipeline has no commands")));
+        }
+        if commands.iter().any(|x| x.as_command().is_none()) {
+            return Err(Error::Invalid(tr("Pipeline commands must be RofiCommands")));
+        }
+        // The stdin of each stage is the output of the previous one.
+        if commands
+            .iter()
+            .filter_map(|x| x.as_command())
+            .any(|x| x.secret().is_some())
+        {
+            return Err(Error::Invalid(tr(
+                "Pipeline commands can not have a secret",
+            )));
+        }
+        // The stages are waited for together.
+        if commands
+            .iter()
+            .filter_map(|x| x.as_command())
+            .any(|x| x.detach())
+        {
+            return Err(Error::Invalid(tr("Pipeline commands can not be detached")));
+        }
+        Ok(Pipeline { commands })
+    }
+
+    fn stages(&self) -> impl Iterator<Item = &RofiCommand> {
+        self.commands.iter().filter_map(|x| x.as_command())
+    }
+}
+
+impl RofiAction for Pipeline {
+    fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+        // The whole pipeline is stopped when the shortest timeout of its
+        // commands runs out.
+        let timeout = self.stages().filter_map(|x| x.timeout()).min();
+        let mut stages = Vec::new();
+        let mut children: Vec<Child> = Vec::new();
+        let mut stderrs = Vec::new();
+        let audit = audit::is_enabled();
+        let count = self.commands.len();
+        // Every question is asked before anything runs, as the stages run
+        // together.
+        for question in self.stages().filter_map(|x| x.confirm()) {
+            if !ask_confirmation(question)? {
+                return Err(Error::Cancelled);
+            }
+        }
+        let commands = self
+            .stages()
+            .map(|x| x.process())
+            .collect::<Result<Vec<Command>, Error>>()?;
+        for (i, (stage, mut command)) in self.stages().zip(commands).enumerate() {
+            let stdin = match children.last_mut().and_then(|x| x.stdout.take()) {
+                Some(stdout) => Stdio::from(stdout),
+                None => Stdio::null(),
+            };
+            // The output of the last command is discarded, like that of a
+            // single `RofiCommand`.
+            let stdout = if i + 1 < count {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            };
+            // Stderr is only kept for the audit log, as the end of it is
+            // recorded.
+            command.stdin(stdin).stdout(stdout).stderr(if audit {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            });
+            if timeout.is_some() {
+                command.process_group(0);
+            }
+            match command.spawn() {
+                Ok(mut child) => {
+                    stages.push(stage);
+                    stderrs.push(child.stderr.take().map(process::PipeReader::spawn));
+                    children.push(child);
+                }
+                Err(e) => {
+                    // Let the commands already started see the end of their
+                    // output and exit, which the command holding the last
+                    // output kept them from. They are stopped at the deadline
+                    // if they do not.
+                    drop(command);
+                    for child in &mut children {
+                        drop(child.stdout.take());
+                    }
+                    let deadline = timeout.map(|x| Instant::now() + x);
+                    for i in 0..children.len() {
+                        if let Ok(None) = process::wait_until(&mut children[i], deadline) {
+                            process::kill_groups(&mut children[i..]);
+                            break;
+                        }
+                    }
+                    return Err(Error::spawn(stage.command(), e));
+                }
+            }
+        }
+
+        let start = Instant::now();
+        let deadline = timeout.map(|x| start + x);
+        let mut result = Ok(None);
+        for i in 0..children.len() {
+            let status = process::wait_until(&mut children[i], deadline)
+                .map_err(|e| Error::io(tr("Failed to wait for command"), e))?;
+            let outcome = match status {
+                Some(status) => Outcome::Exited(status),
+                None => Outcome::TimedOut,
+            };
+            // Whatever the stage left running in the background may still
+            // hold stderr, so it is not waited for past the deadline.
+            let stderr = match (status, stderrs[i].take()) {
+                (Some(_), Some(stderr)) => {
+                    stderr.wait_closed(process::pipe_deadline(deadline));
+                    stderr.take()
+                }
+                _ => Vec::new(),
+            };
+            let (program, args) = stages[i].invocation();
+            audit::record(&program, &args, outcome, start.elapsed(), &stderr);
+            match status {
+                Some(status) if !status.success() && result.is_ok() => {
+                    result = Err(Error::CommandFailed {
+                        command: String::from(stages[i].command()),
+                        status,
+                    });
+                }
+                Some(_) => (),
+                None => {
+                    process::kill_groups(&mut children[i..]);
+                    return Err(Error::TimedOut {
+                        command: String::from(stages[i].command()),
+                        timeout: timeout.unwrap_or_default(),
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    fn needs_input(&self) -> bool {
+        self.stages().any(|x| x.needs_input())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    /// An action counting its runs, which fails with `error` if it is set.
+    struct Counted {
+        runs: Rc<Cell<usize>>,
+        error: Option<fn() -> Error>,
+    }
+
+    impl RofiAction for Counted {
+        fn run(&self) -> Result<Option<&dyn RofiAction>, Error> {
+            self.runs.set(self.runs.get() + 1);
+            match self.error {
+                Some(error) => Err(error()),
+                None => Ok(None),
+            }
+        }
+    }
+
+    /// Returns actions failing with `errors`, or succeeding where there is
+    /// none, and the count of their runs.
+    fn counted(errors: Vec<Option<fn() -> Error>>) -> (Vec<Box<dyn RofiAction>>, Rc<Cell<usize>>) {
+        let runs = Rc::new(Cell::new(0));
+        let actions = errors
+            .into_iter()
+            .map(|error| {
+                Box::new(Counted {
+                    runs: runs.clone(),
+                    error,
+                }) as Box<dyn RofiAction>
+            })
+            .collect();
+        (actions, runs)
+    }
+
+    fn failed() -> Error {
+        Error::Failed(String::from("failed"))
+    }
+
+    fn invalid() -> Error {
+        Error::Invalid(String::from("invalid"))
+    }
+
+    /// Returns a command running `script` with sh.
+    fn sh(script: &str) -> Box<dyn RofiAction> {
+        Box::new(RofiCommand::new(
+            String::from("sh"),
+            vec![String::from("-c"), String::from(script)],
+        ))
+    }
+
+    #[test]
+    fn sequence_stops_at_the_first_error_if_told_to() {
+        let (actions, runs) = counted(vec![None, Some(invalid), None]);
+        let sequence = Sequence::new(actions, true);
+        assert!(matches!(sequence.run(), Err(Error::Invalid(_))));
+        assert_eq!(runs.get(), 2);
+    }
+
+    #[test]
+    fn sequence_runs_every_action_and_returns_a_single_error() {
+        let (actions, runs) = counted(vec![Some(invalid), None, None]);
+        let sequence = Sequence::new(actions, false);
+        assert!(matches!(sequence.run(), Err(Error::Invalid(_))));
+        assert_eq!(runs.get(), 3);
+    }
+
+    #[test]
+    fn sequence_sums_up_several_errors() {
+        let (actions, runs) = counted(vec![Some(invalid), None, Some(failed)]);
+        match Sequence::new(actions, false).run() {
+            Err(Error::Reported { exit_code, message }) => {
+                assert_eq!(exit_code, crate::rmenu::error::EXIT_INVALID);
+                assert_eq!(message, "2 of 3 actions failed: invalid; failed");
+            }
+            _ => panic!("the errors were not summed up"),
+        }
+        assert_eq!(runs.get(), 3);
+    }
+
+    #[test]
+    fn pipeline_feeds_each_command_the_output_of_the_previous_one() {
+        let pipeline = Pipeline::new(vec![
+            sh("echo hello"),
+            sh("tr a-z A-Z"),
+            sh("read x; test \"$x\" = HELLO"),
+        ])
+        .unwrap();
+        assert!(pipeline.run().is_ok());
+        let pipeline = Pipeline::new(vec![sh("echo hello"), sh("grep -q bye")]).unwrap();
+        assert!(matches!(pipeline.run(), Err(Error::CommandFailed { .. })));
+    }
+
+    #[test]
+    fn pipeline_stops_the_started_commands_when_one_does_not_start() {
+        // `yes` only stops once its output is closed.
+        let pipeline = Pipeline::new(vec![
+            sh("yes"),
+            Box::new(RofiCommand::new(
+                String::from("/nonexistent/rmenu-test"),
+                Vec::new(),
+            )),
+        ])
+        .unwrap();
+        assert!(matches!(pipeline.run(), Err(Error::Spawn { .. })));
+    }
+
+    #[test]
+    fn pipeline_rejects_detached_commands() {
+        let detached = Box::new(RofiCommand::detached(String::from("true"), Vec::new()));
+        assert!(matches!(
+            Pipeline::new(vec![sh("true"), detached]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn pipeline_times_out_with_its_shortest_timeout() {
+        let mut slow = RofiCommand::new(String::from("sleep"), vec![String::from("5")]);
+        slow.set_timeout(Some(Duration::from_millis(100)));
+        let pipeline = Pipeline::new(vec![sh("echo hello"), Box::new(slow)]).unwrap();
+        let start = Instant::now();
+        assert!(matches!(pipeline.run(), Err(Error::TimedOut { .. })));
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+}